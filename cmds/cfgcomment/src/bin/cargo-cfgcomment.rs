@@ -0,0 +1,29 @@
+//! `cargo cfgcomment`, runs cfgcomment installed alongside, which handles arguments
+//! passed by cargo
+
+use std::{
+    path::PathBuf,
+    process::{Command, ExitCode},
+};
+
+fn main() -> ExitCode {
+    let name = format!("cfgcomment{}", std::env::consts::EXE_SUFFIX);
+    let sibling = std::env::current_exe()
+        .ok()
+        .map(|exe| exe.with_file_name(&name))
+        .filter(|exe| exe.is_file())
+        .unwrap_or_else(|| PathBuf::from(&name));
+    match Command::new(&sibling)
+        .args(std::env::args_os().skip(1))
+        .status()
+    {
+        Ok(status) => match status.code() {
+            Some(code) => ExitCode::from(code as u8),
+            None => ExitCode::FAILURE,
+        },
+        Err(e) => {
+            eprintln!("Error: failed to run {}: {}", sibling.display(), e);
+            ExitCode::FAILURE
+        }
+    }
+}
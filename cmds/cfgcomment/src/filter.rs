@@ -0,0 +1,424 @@
+//! Long-running filter process protocol of git, see "Long Running Filter Process" in
+//! gitattributes(5)
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    sync::{mpsc, Mutex},
+};
+
+/// Largest payload of single pkt-line
+const MAX_PACKET_DATA: usize = 65516;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessingType {
+    /// Ran on stage
+    Clean,
+    /// Ran on checkout
+    Smudge,
+}
+
+pub trait Processor: Sync {
+    /// Filters contents of file at `pathname`, relative to repository root
+    fn process(
+        &self,
+        pathname: &str,
+        process_type: ProcessingType,
+        data: Vec<u8>,
+    ) -> anyhow::Result<Vec<u8>>;
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads single packet into `buf`, returns `false` for flush packet
+fn read_packet(input: &mut impl Read, buf: &mut Vec<u8>) -> io::Result<bool> {
+    let mut len = [0; 4];
+    input.read_exact(&mut len)?;
+    let len = std::str::from_utf8(&len)
+        .ok()
+        .and_then(|len| usize::from_str_radix(len, 16).ok())
+        .ok_or_else(|| invalid("bad packet length"))?;
+    match len {
+        0 => return Ok(false),
+        1..=4 => return Err(invalid("bad packet length")),
+        _ => {}
+    }
+    buf.resize(len - 4, 0);
+    input.read_exact(buf)?;
+    Ok(true)
+}
+
+/// Text packets until flush, without trailing newlines
+fn read_list(input: &mut impl Read) -> io::Result<Vec<String>> {
+    let mut buf = Vec::new();
+    let mut list = Vec::new();
+    while read_packet(input, &mut buf)? {
+        let text = String::from_utf8(buf.clone()).map_err(|_| invalid("non-UTF-8 text packet"))?;
+        list.push(text.strip_suffix('\n').unwrap_or(&text).to_owned());
+    }
+    Ok(list)
+}
+
+/// Binary packets until flush, concatenated
+fn read_content(input: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let mut content = Vec::new();
+    while read_packet(input, &mut buf)? {
+        content.extend_from_slice(&buf);
+    }
+    Ok(content)
+}
+
+fn write_text(output: &mut impl Write, text: &str) -> io::Result<()> {
+    writeln!(output, "{:04x}{}", text.len() + 5, text)
+}
+
+fn write_flush(output: &mut impl Write) -> io::Result<()> {
+    output.write_all(b"0000")
+}
+
+fn write_content(output: &mut impl Write, content: &[u8]) -> io::Result<()> {
+    for chunk in content.chunks(MAX_PACKET_DATA) {
+        write!(output, "{:04x}", chunk.len() + 4)?;
+        output.write_all(chunk)?;
+    }
+    Ok(())
+}
+
+/// Sends filtered file, or reports failure to git, which then fails filtering of this file
+fn respond(
+    output: &mut impl Write,
+    pathname: &str,
+    result: anyhow::Result<Vec<u8>>,
+) -> io::Result<()> {
+    match result {
+        Ok(data) => {
+            write_text(output, "status=success")?;
+            write_flush(output)?;
+            write_content(output, &data)?;
+            write_flush(output)?;
+            // Status is kept
+            write_flush(output)?;
+        }
+        Err(e) => {
+            tracing::error!("{}: {:#}", pathname, e);
+            write_text(output, "status=error")?;
+            write_flush(output)?;
+        }
+    }
+    output.flush()
+}
+
+fn handshake(input: &mut impl Read, output: &mut impl Write) -> io::Result<Vec<String>> {
+    if read_list(input)? != ["git-filter-client", "version=2"] {
+        return Err(invalid("unsupported filter client"));
+    }
+    write_text(output, "git-filter-server")?;
+    write_text(output, "version=2")?;
+    write_flush(output)?;
+    output.flush()?;
+
+    let offered = read_list(input)?;
+    let mut capabilities = Vec::new();
+    for capability in ["capability=clean", "capability=smudge", "capability=delay"] {
+        if offered.iter().any(|c| c == capability) {
+            write_text(output, capability)?;
+            capabilities.push(capability.to_owned());
+        }
+    }
+    write_flush(output)?;
+    output.flush()?;
+    Ok(capabilities)
+}
+
+type Job = (String, ProcessingType, Vec<u8>);
+
+/// Serves git until it closes input. Files git allows to delay are processed on `threads`
+/// worker threads, and handed back once git asks for available ones
+pub fn serve(
+    processor: &impl Processor,
+    threads: usize,
+    input: &mut impl Read,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    let (jobs, queue) = mpsc::channel::<Job>();
+    let queue = Mutex::new(queue);
+    let (done, finished) = mpsc::channel::<(String, anyhow::Result<Vec<u8>>)>();
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            let done = done.clone();
+            let queue = &queue;
+            scope.spawn(move || loop {
+                // Lock is released before processing
+                let job = queue.lock().unwrap().recv();
+                let (pathname, process_type, data) = match job {
+                    Ok(v) => v,
+                    Err(_) => return,
+                };
+                let result = processor.process(&pathname, process_type, data);
+                if done.send((pathname, result)).is_err() {
+                    return;
+                }
+            });
+        }
+        // Only workers may keep results channel open, so its receiver notices them dying
+        drop(done);
+        let result = communicate(processor, input, output, &jobs, &finished);
+        // Stops workers
+        drop(jobs);
+        match result {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(()),
+            other => other,
+        }
+    })
+}
+
+fn communicate(
+    processor: &impl Processor,
+    input: &mut impl Read,
+    output: &mut impl Write,
+    jobs: &mpsc::Sender<Job>,
+    finished: &mpsc::Receiver<(String, anyhow::Result<Vec<u8>>)>,
+) -> io::Result<()> {
+    let capabilities = handshake(input, output)?;
+    let can_delay = capabilities.iter().any(|c| c == "capability=delay");
+    let mut pending = 0;
+    let mut available: HashMap<String, anyhow::Result<Vec<u8>>> = HashMap::new();
+    loop {
+        let mut command = None;
+        let mut pathname = None;
+        let mut delay = false;
+        for line in read_list(input)? {
+            if let Some(value) = line.strip_prefix("command=") {
+                command = Some(value.to_owned());
+            } else if let Some(value) = line.strip_prefix("pathname=") {
+                pathname = Some(value.to_owned());
+            } else if line == "can-delay=1" {
+                delay = can_delay;
+            }
+        }
+        let process_type = match command.as_deref() {
+            Some("clean") => ProcessingType::Clean,
+            Some("smudge") => ProcessingType::Smudge,
+            Some("list_available_blobs") => {
+                // Git waits for at least one blob, empty list means there are none left
+                if pending != 0 {
+                    let mut ready = vec![finished.recv().map_err(|_| invalid("workers died"))?];
+                    ready.extend(finished.try_iter());
+                    for (pathname, result) in ready {
+                        pending -= 1;
+                        write_text(output, &format!("pathname={}", pathname))?;
+                        available.insert(pathname, result);
+                    }
+                }
+                write_flush(output)?;
+                write_text(output, "status=success")?;
+                write_flush(output)?;
+                output.flush()?;
+                continue;
+            }
+            Some(other) => return Err(invalid(format!("unknown command: {}", other))),
+            None => return Err(invalid("missing command")),
+        };
+        let pathname = pathname.ok_or_else(|| invalid("missing pathname"))?;
+        let data = read_content(input)?;
+        if let Some(result) = available.remove(&pathname) {
+            respond(output, &pathname, result)?;
+        } else if delay {
+            jobs.send((pathname, process_type, data))
+                .map_err(|_| invalid("workers died"))?;
+            pending += 1;
+            write_text(output, "status=delayed")?;
+            write_flush(output)?;
+            output.flush()?;
+        } else {
+            let result = processor.process(&pathname, process_type, data);
+            respond(output, &pathname, result)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Uppercases on clean, lowercases on smudge
+    struct Case;
+    impl Processor for Case {
+        fn process(
+            &self,
+            pathname: &str,
+            process_type: ProcessingType,
+            data: Vec<u8>,
+        ) -> anyhow::Result<Vec<u8>> {
+            anyhow::ensure!(pathname != "bad", "bad file");
+            Ok(match process_type {
+                ProcessingType::Clean => data.to_ascii_uppercase(),
+                ProcessingType::Smudge => data.to_ascii_lowercase(),
+            })
+        }
+    }
+
+    /// Input of git, `None` is flush packet
+    fn client(packets: &[Option<&[u8]>]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for packet in packets {
+            match packet {
+                Some(data) => write_content(&mut out, data).unwrap(),
+                None => write_flush(&mut out).unwrap(),
+            }
+        }
+        out
+    }
+
+    fn client_handshake(capabilities: &[&str]) -> Vec<Option<Vec<u8>>> {
+        let mut packets = vec![
+            Some(b"git-filter-client\n".to_vec()),
+            Some(b"version=2\n".to_vec()),
+            None,
+        ];
+        for capability in capabilities {
+            packets.push(Some(format!("capability={}\n", capability).into_bytes()));
+        }
+        packets.push(None);
+        packets
+    }
+
+    fn command(command: &str, pathname: &str, delay: bool) -> Vec<Option<Vec<u8>>> {
+        let mut packets = vec![
+            Some(format!("command={}\n", command).into_bytes()),
+            Some(format!("pathname={}\n", pathname).into_bytes()),
+        ];
+        if delay {
+            packets.push(Some(b"can-delay=1\n".to_vec()));
+        }
+        packets.push(None);
+        packets
+    }
+
+    /// Output packets of server, as text where possible
+    fn run(threads: usize, packets: Vec<Option<Vec<u8>>>) -> Vec<String> {
+        let packets: Vec<Option<&[u8]>> = packets.iter().map(Option::as_deref).collect();
+        let input = client(&packets);
+        let mut output = Vec::new();
+        serve(&Case, threads, &mut input.as_slice(), &mut output).unwrap();
+        let mut output = output.as_slice();
+        let mut buf = Vec::new();
+        let mut transcript = Vec::new();
+        while !output.is_empty() {
+            transcript.push(match read_packet(&mut output, &mut buf).unwrap() {
+                true => String::from_utf8(buf.clone()).unwrap(),
+                false => "0000".to_owned(),
+            });
+        }
+        transcript
+    }
+
+    #[test]
+    fn handshake_keeps_supported_capabilities() {
+        assert_eq!(
+            run(1, client_handshake(&["clean", "smudge", "unknown"])),
+            [
+                "git-filter-server\n",
+                "version=2\n",
+                "0000",
+                "capability=clean\n",
+                "capability=smudge\n",
+                "0000"
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_client_is_rejected() {
+        let input = client(&[Some(b"git-filter-client\n"), Some(b"version=3\n"), None]);
+        let result = serve(&Case, 1, &mut input.as_slice(), &mut Vec::new());
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn clean_and_smudge() {
+        let mut packets = client_handshake(&["clean", "smudge"]);
+        packets.extend(command("clean", "a", false));
+        packets.extend([Some(b"abc".to_vec()), None]);
+        packets.extend(command("smudge", "a", false));
+        packets.extend([Some(b"ABC".to_vec()), None]);
+        packets.extend(command("clean", "bad", false));
+        packets.extend([Some(b"abc".to_vec()), None]);
+        assert_eq!(
+            run(1, packets)[6..],
+            [
+                "status=success\n",
+                "0000",
+                "ABC",
+                "0000",
+                "0000",
+                "status=success\n",
+                "0000",
+                "abc",
+                "0000",
+                "0000",
+                "status=error\n",
+                "0000",
+            ]
+        );
+    }
+
+    #[test]
+    fn delayed_files_are_listed_once_processed() {
+        let mut packets = client_handshake(&["clean", "delay"]);
+        packets.extend(command("clean", "a", true));
+        packets.extend([Some(b"abc".to_vec()), None]);
+        packets.extend(command("list_available_blobs", "", false));
+        packets.extend(command("clean", "a", false));
+        packets.push(None);
+        // Nothing is left
+        packets.extend(command("list_available_blobs", "", false));
+        assert_eq!(
+            run(2, packets)[6..],
+            [
+                "status=delayed\n",
+                "0000",
+                "pathname=a\n",
+                "0000",
+                "status=success\n",
+                "0000",
+                "status=success\n",
+                "0000",
+                "ABC",
+                "0000",
+                "0000",
+                "0000",
+                "status=success\n",
+                "0000",
+            ]
+        );
+    }
+
+    #[test]
+    fn delay_is_ignored_without_capability() {
+        let mut packets = client_handshake(&["clean"]);
+        packets.extend(command("clean", "a", true));
+        packets.extend([Some(b"abc".to_vec()), None]);
+        assert_eq!(
+            run(1, packets)[5..],
+            ["status=success\n", "0000", "ABC", "0000", "0000"]
+        );
+    }
+
+    #[test]
+    fn large_content_is_split_into_packets() {
+        let data = vec![b'a'; MAX_PACKET_DATA + 1];
+        let mut packets = client_handshake(&["clean"]);
+        packets.extend(command("clean", "a", false));
+        packets.extend([Some(data[..10].to_vec()), Some(data[10..].to_vec()), None]);
+        let upper = "A".repeat(MAX_PACKET_DATA);
+        assert_eq!(
+            run(1, packets)[5..],
+            ["status=success\n", "0000", &upper, "A", "0000", "0000"]
+        );
+    }
+}
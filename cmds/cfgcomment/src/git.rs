@@ -0,0 +1,320 @@
+use anyhow::{bail, Context};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::path_from_bytes;
+
+/// Runs git restricted to `paths`, returning its stdout
+fn git(args: &[&str], paths: &[PathBuf]) -> anyhow::Result<Vec<u8>> {
+    let mut command = Command::new("git");
+    command.args(args);
+    if !paths.is_empty() {
+        command.arg("--").args(paths);
+    }
+    let output = command
+        .output()
+        .with_context(|| format!("while running git {}", args[0]))?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Root of work tree containing current directory
+pub fn toplevel() -> anyhow::Result<PathBuf> {
+    let output = git(&["rev-parse", "--show-toplevel"], &[])?;
+    Ok(path_from_bytes(
+        output.strip_suffix(b"\n").unwrap_or(&output),
+    ))
+}
+
+/// NUL-separated paths relative to work tree root
+fn paths_in<'a>(root: &'a Path, output: &'a [u8]) -> impl Iterator<Item = PathBuf> + 'a {
+    output
+        .split(|c| *c == b'\0')
+        .filter(|path| !path.is_empty())
+        .map(move |path| root.join(path_from_bytes(path)))
+}
+
+/// Files under `paths` which were added or modified since `rev`, in commits, in index or
+/// in work tree, plus untracked files not ignored by git, unless `tracked` is set. Deleted
+/// files are left out
+pub fn changed_since(rev: &str, paths: &[PathBuf], tracked: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let root = toplevel()?;
+    let changed = git(
+        &[
+            "diff",
+            "--name-only",
+            "-z",
+            "--no-renames",
+            "--diff-filter=d",
+            rev,
+        ],
+        paths,
+    )?;
+    let untracked = match tracked {
+        true => Vec::new(),
+        false => git(
+            &[
+                "ls-files",
+                "-z",
+                "--full-name",
+                "--others",
+                "--exclude-standard",
+            ],
+            paths,
+        )?,
+    };
+    let mut files: Vec<PathBuf> = paths_in(&root, &changed)
+        .chain(paths_in(&root, &untracked))
+        .collect();
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Files under `paths` which are tracked by git, except ones deleted from work tree
+pub fn tracked(paths: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
+    let root = toplevel()?;
+    let output = git(&["ls-files", "-z", "--full-name"], paths)?;
+    let mut files: Vec<PathBuf> = paths_in(&root, &output)
+        .filter(|path| path.symlink_metadata().is_ok())
+        .collect();
+    // Unmerged files are listed once per stage
+    files.dedup();
+    Ok(files)
+}
+
+/// Files staged for commit, except deleted ones
+pub fn staged() -> anyhow::Result<Vec<PathBuf>> {
+    let root = toplevel()?;
+    let output = git(
+        &[
+            "diff",
+            "--cached",
+            "--name-only",
+            "-z",
+            "--no-renames",
+            "--diff-filter=d",
+        ],
+        &[],
+    )?;
+    Ok(paths_in(&root, &output).collect())
+}
+
+/// Files with changes which are not staged
+pub fn unstaged() -> anyhow::Result<HashSet<PathBuf>> {
+    let root = toplevel()?;
+    let output = git(&["diff", "--name-only", "-z", "--no-renames"], &[])?;
+    Ok(paths_in(&root, &output).collect())
+}
+
+pub fn add(paths: &[PathBuf]) -> anyhow::Result<()> {
+    if !paths.is_empty() {
+        git(&["add"], paths)?;
+    }
+    Ok(())
+}
+
+/// Path of `name` inside of git directory, i.e `hooks/pre-commit`, respecting config like
+/// `core.hooksPath`
+pub fn git_path(name: &str) -> anyhow::Result<PathBuf> {
+    let output = git(&["rev-parse", "--git-path", name], &[])?;
+    Ok(path_from_bytes(
+        output.strip_suffix(b"\n").unwrap_or(&output),
+    ))
+}
+
+/// Value of git config `key`, if set
+pub fn config_get(key: &str) -> anyhow::Result<Option<String>> {
+    let output = Command::new("git")
+        .args(["config", "--get", key])
+        .output()
+        .with_context(|| format!("while reading {}", key))?;
+    // 1 is returned for missing keys
+    match output.status.code() {
+        Some(0) => Ok(Some(
+            String::from_utf8_lossy(&output.stdout)
+                .trim_end()
+                .to_owned(),
+        )),
+        Some(1) => Ok(None),
+        _ => bail!(
+            "git config failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    }
+}
+
+pub fn config_set(key: &str, value: &str) -> anyhow::Result<()> {
+    git(&["config", key, value], &[])?;
+    Ok(())
+}
+
+/// Removes every key of `section`, if there are any
+pub fn config_remove_section(section: &str) -> anyhow::Result<()> {
+    let pattern = format!("^{}\\.", section.replace('.', "\\."));
+    let found = Command::new("git")
+        .args(["config", "--get-regexp", &pattern])
+        .output()
+        .with_context(|| format!("while reading {}", section))?;
+    if found.status.success() {
+        git(&["config", "--remove-section", section], &[])?;
+    }
+    Ok(())
+}
+
+/// Attribute assigning cfgcomment filter
+pub const FILTER_ATTRIBUTE: &str = "filter=cfgcomment";
+
+/// Lines enclosing attributes added by init, uninstall only removes lines between them
+const ATTRIBUTES_START: &str = "# Added by cfgcomment init, removed by cfgcomment uninstall";
+const ATTRIBUTES_END: &str = "# End of cfgcomment attributes";
+
+/// Lines of `content` with their terminators and byte offsets
+fn lines_with_offsets(content: &str) -> impl Iterator<Item = (usize, &str, &str)> {
+    content.split_inclusive('\n').scan(0, |offset, line| {
+        let start = *offset;
+        *offset += line.len();
+        Some((start, line, line.trim_end_matches(&['\r', '\n'][..])))
+    })
+}
+
+/// Offset of end marker of generated block, if there is one
+fn block_end(content: &str) -> Option<usize> {
+    let mut in_block = false;
+    for (offset, _, text) in lines_with_offsets(content) {
+        match text {
+            ATTRIBUTES_START => in_block = true,
+            ATTRIBUTES_END if in_block => return Some(offset),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Adds `lines` to generated block of attributes file `content`, except ones for patterns
+/// which already have cfgcomment filter. Block is appended if there is none yet
+pub fn merge_attributes(content: &str, lines: &[String]) -> String {
+    let mut filtered: HashSet<String> = content
+        .lines()
+        .filter_map(|line| {
+            let mut tokens = line.split_whitespace();
+            let pattern = tokens.next()?;
+            tokens
+                .any(|t| t == FILTER_ATTRIBUTE)
+                .then(|| pattern.to_owned())
+        })
+        .collect();
+    let mut added = String::new();
+    for line in lines {
+        let pattern = line.split_whitespace().next().unwrap_or_default();
+        if filtered.insert(pattern.to_owned()) {
+            added.push_str(line);
+            added.push('\n');
+        }
+    }
+    if added.is_empty() {
+        return content.to_owned();
+    }
+    let mut out = content.to_owned();
+    match block_end(content) {
+        Some(end) => out.insert_str(end, &added),
+        None => {
+            if !out.is_empty() && !out.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str(ATTRIBUTES_START);
+            out.push('\n');
+            out.push_str(&added);
+            out.push_str(ATTRIBUTES_END);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Removes generated block from attributes file `content`, everything outside of it is kept
+/// as is
+pub fn strip_attributes(content: &str) -> String {
+    let mut out = String::new();
+    // Lines of block, kept if it is never closed
+    let mut block: Option<String> = None;
+    for (_, line, text) in lines_with_offsets(content) {
+        match (&mut block, text) {
+            (None, ATTRIBUTES_START) => block = Some(line.to_owned()),
+            (Some(_), ATTRIBUTES_END) => block = None,
+            (Some(block), _) => block.push_str(line),
+            (None, _) => out.push_str(line),
+        }
+    }
+    out.push_str(&block.unwrap_or_default());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn merge_appends_block() {
+        let merged = merge_attributes("*.png binary", &lines(&["*.rs filter=cfgcomment"]));
+        assert_eq!(
+            merged,
+            format!(
+                "*.png binary\n{}\n*.rs filter=cfgcomment\n{}\n",
+                ATTRIBUTES_START, ATTRIBUTES_END
+            )
+        );
+    }
+
+    #[test]
+    fn merge_extends_existing_block() {
+        let content = merge_attributes("", &lines(&["*.rs filter=cfgcomment"]));
+        let content = format!("{}*.png binary\n", content);
+        let merged = merge_attributes(
+            &content,
+            &lines(&["*.rs filter=cfgcomment", "*.toml filter=cfgcomment"]),
+        );
+        assert_eq!(
+            merged,
+            format!(
+                "{}\n*.rs filter=cfgcomment\n*.toml filter=cfgcomment\n{}\n*.png binary\n",
+                ATTRIBUTES_START, ATTRIBUTES_END
+            )
+        );
+    }
+
+    #[test]
+    fn merge_skips_filtered_patterns() {
+        let content = "*.rs   text filter=cfgcomment\n";
+        assert_eq!(
+            merge_attributes(content, &lines(&["*.rs filter=cfgcomment"])),
+            content
+        );
+    }
+
+    #[test]
+    fn strip_removes_only_block() {
+        let content = "*.png binary\r\n*.md  filter=cfgcomment\n";
+        let merged = merge_attributes(content, &lines(&["*.rs filter=cfgcomment"]));
+        assert_eq!(strip_attributes(&merged), content);
+    }
+
+    #[test]
+    fn strip_keeps_unclosed_block() {
+        let content = format!("{}\n*.rs filter=cfgcomment\n", ATTRIBUTES_START);
+        assert_eq!(strip_attributes(&content), content);
+    }
+}
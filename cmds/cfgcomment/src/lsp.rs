@@ -0,0 +1,520 @@
+//! Language server, speaking JSON-RPC with `Content-Length` framing over stdio. Reports
+//! problems of cfg tags as diagnostics, folds cfg blocks, explains predicates on hover and
+//! offers code action to comment out or uncomment branch under cursor
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use cfgcomment_core::{explain, regions, verify, Branch, BranchKind, Data, LangDesc, Region};
+use serde_json::{json, Value};
+
+use crate::{branch_state, branch_tag, project_config, write_evaluation};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Reads single message, `None` once client closes input. Content which isn't JSON is
+/// returned as error, as framing is intact and next message may be read
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<serde_json::Result<Value>>> {
+    let mut length = None;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(
+                    value
+                        .trim()
+                        .parse()
+                        .map_err(|_| invalid("bad content length"))?,
+                );
+            }
+        }
+    }
+    let mut content = vec![0; length.ok_or_else(|| invalid("missing content length"))?];
+    input.read_exact(&mut content)?;
+    Ok(Some(serde_json::from_slice(&content)))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        output,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    output.flush()
+}
+
+/// Path of `file:` URI, with percent-encoding undone
+fn uri_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut path = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        let escaped = encoded
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (encoded[i], escaped) {
+            (b'%', Some(byte)) => {
+                path.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                path.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(path).ok().map(PathBuf::from)
+}
+
+struct Document {
+    text: String,
+    /// `None` for files of unknown language, which are left alone
+    desc: Option<LangDesc>,
+}
+
+/// Content lines of branch, without its tag, 1-based and inclusive. `None` for empty ones
+fn branch_lines(
+    branch: &Branch,
+    branches: &[Branch],
+    regions: &[Region],
+) -> Option<(usize, usize)> {
+    let end = match branch.kind {
+        BranchKind::Line | BranchKind::File => regions.iter().find(|r| r.start == branch.line)?.end,
+        _ => {
+            let region = regions
+                .iter()
+                .filter(|r| r.nesting_depth == branch.nesting_depth)
+                .rfind(|r| r.start <= branch.line && branch.line < r.end)?;
+            let next = branches.iter().find(|b| {
+                b.line > branch.line
+                    && b.line < region.end
+                    && b.nesting_depth == branch.nesting_depth
+            });
+            next.map_or(region.end, |b| b.line) - 1
+        }
+    };
+    Some((branch.line + 1, end)).filter(|(start, end)| start <= end)
+}
+
+/// Innermost branch whose tag or content is on `line`, 1-based
+fn branch_at<'a>(line: usize, branches: &'a [Branch], regions: &[Region]) -> Option<&'a Branch> {
+    branches
+        .iter()
+        .filter(|b| {
+            let end = branch_lines(b, branches, regions).map_or(b.line, |(_, end)| end);
+            b.line <= line && line <= end
+        })
+        .max_by_key(|b| (b.nesting_depth, b.line))
+}
+
+fn indentation(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+fn line_range(line: usize, text: &str) -> Value {
+    json!({
+        "start": { "line": line, "character": 0 },
+        "end": { "line": line, "character": text.encode_utf16().count() },
+    })
+}
+
+/// Edits commenting out every line of branch at `line`, or uncommenting them if the
+/// first one is commented. Lines of nested blocks follow, regardless of their own state.
+/// Languages without line comments aren't supported, as whole runs have to be rewrapped
+fn toggle_edits(
+    text: &str,
+    config: &Data,
+    desc: &LangDesc,
+    line: usize,
+) -> Option<(&'static str, String, Vec<Value>)> {
+    if desc.comment_end.is_some() {
+        return None;
+    }
+    let branches = explain(text, config, desc).ok()?;
+    let regions = regions(text, desc, false).ok()?;
+    let branch = branch_at(line, &branches, &regions)?;
+    let (first, last) = branch_lines(branch, &branches, &regions)?;
+
+    let lines: Vec<&str> = text.lines().collect();
+    let mut tags: HashSet<usize> = branches.iter().map(|b| b.line).collect();
+    for region in &regions {
+        let kind = branches
+            .iter()
+            .find(|b| b.line == region.start)
+            .map(|b| b.kind);
+        if kind == Some(BranchKind::Start) {
+            tags.insert(region.end);
+        }
+    }
+    // Lines are indented at least as their innermost block tag, comment follows that
+    let prefix = |i: usize| {
+        let owner = branch_at(i, &branches, &regions)?;
+        let region = regions
+            .iter()
+            .rfind(|r| r.start <= owner.line && r.nesting_depth == owner.nesting_depth)?;
+        Some(indentation(lines[region.start - 1]))
+    };
+    let own = (first..=last).filter(|i| !tags.contains(i) && !lines[i - 1].trim().is_empty());
+    let own: Vec<(usize, &str, &str)> = own
+        .filter_map(|i| {
+            let prefix = prefix(i)?;
+            Some((i, prefix, lines[i - 1].strip_prefix(prefix)?))
+        })
+        .collect();
+    let comment = own.first()?.2.starts_with(&desc.comment);
+
+    let edits = own
+        .into_iter()
+        .filter_map(|(i, prefix, rest)| {
+            let new = match (comment, rest.strip_prefix(&desc.comment)) {
+                (true, Some(rest)) => format!("{}{}", prefix, rest),
+                (false, None) => format!("{}{}{}", prefix, desc.comment, rest),
+                _ => return None,
+            };
+            Some(json!({ "range": line_range(i - 1, lines[i - 1]), "newText": new }))
+        })
+        .collect();
+    let title = match comment {
+        true => "Uncomment",
+        false => "Comment out",
+    };
+    Some((title, branch_tag(branch, &desc.cfg_keyword), edits))
+}
+
+fn hover(text: &str, config: &Data, desc: &LangDesc, line: usize) -> Option<Value> {
+    let branches = explain(text, config, desc).ok()?;
+    let regions = regions(text, desc, false).ok()?;
+    let branch = branch_at(line, &branches, &regions)?;
+    let mut value = format!(
+        "`{}` — {}",
+        branch_tag(branch, &desc.cfg_keyword),
+        branch_state(branch)
+    );
+    if let Some(evaluation) = &branch.evaluation {
+        let mut tree = String::new();
+        write_evaluation(&mut tree, evaluation, 0);
+        value.push_str(&format!("\n\n```\n{}```", tree));
+    }
+    Some(json!({ "contents": { "kind": "markdown", "value": value } }))
+}
+
+fn folding_ranges(text: &str, desc: &LangDesc) -> Vec<Value> {
+    let regions = regions(text, desc, false).unwrap_or_default();
+    let branches = explain(text, &Data::default(), desc).unwrap_or_default();
+    regions
+        .iter()
+        .filter(|r| {
+            let kind = branches.iter().find(|b| b.line == r.start).map(|b| b.kind);
+            kind == Some(BranchKind::Start)
+        })
+        .map(|r| json!({ "startLine": r.start - 1, "endLine": r.end - 1 }))
+        .collect()
+}
+
+fn diagnostics(text: &str, desc: &LangDesc) -> Vec<Value> {
+    let lines: Vec<&str> = text.lines().collect();
+    verify(text, desc)
+        .into_iter()
+        .map(|e| {
+            let line = e.line().map_or(0, |l| l - 1);
+            json!({
+                "range": line_range(line, lines.get(line).copied().unwrap_or("")),
+                "severity": 1,
+                "source": "cfgcomment",
+                "message": e.to_string(),
+            })
+        })
+        .collect()
+}
+
+pub struct Server {
+    /// Predicates are evaluated against it on hover
+    config: Data,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+impl Server {
+    pub fn new(config: Data) -> Self {
+        Self {
+            config,
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    fn open(&mut self, uri: &str, text: String) -> anyhow::Result<Document> {
+        let path = uri_path(uri);
+        let config_start = path
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or(".".as_ref());
+        let lang_config = project_config(config_start)?.lang_config();
+        let first_line = text.lines().next().unwrap_or("");
+        let desc = lang_config
+            .resolve(path.as_deref().unwrap_or(uri.as_ref()), first_line)?
+            .cloned();
+        Ok(Document { text, desc })
+    }
+
+    fn publish(&self, output: &mut impl Write, uri: &str) -> io::Result<()> {
+        let document = self.documents.get(uri);
+        let diagnostics = match document {
+            Some(Document {
+                text,
+                desc: Some(desc),
+            }) => diagnostics(text, desc),
+            _ => Vec::new(),
+        };
+        let params = json!({ "uri": uri, "diagnostics": diagnostics });
+        let method = "textDocument/publishDiagnostics";
+        write_message(
+            output,
+            &json!({ "jsonrpc": "2.0", "method": method, "params": params }),
+        )
+    }
+
+    fn notify(&mut self, output: &mut impl Write, method: &str, params: &Value) -> io::Result<()> {
+        let uri = match params["textDocument"]["uri"].as_str() {
+            Some(uri) => uri.to_owned(),
+            None => return Ok(()),
+        };
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            // Only full sync is advertised, so last change has the whole text
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|c| c.last())
+                .and_then(|c| c["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return self.publish(output, &uri);
+            }
+            _ => return Ok(()),
+        };
+        let text = match text {
+            Some(text) => text.to_owned(),
+            None => return Ok(()),
+        };
+        match self.open(&uri, text) {
+            Ok(document) => {
+                self.documents.insert(uri.clone(), document);
+            }
+            Err(e) => tracing::error!("{}: {:#}", uri, e),
+        }
+        self.publish(output, &uri)
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let document = params["textDocument"]["uri"]
+            .as_str()
+            .and_then(|uri| Some((uri, self.documents.get(uri)?)));
+        let target = document.and_then(|(uri, d)| Some((uri, d.text.as_str(), d.desc.as_ref()?)));
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "foldingRangeProvider": true,
+                    "codeActionProvider": true,
+                },
+                "serverInfo": { "name": "cfgcomment", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/hover" => {
+                let line = position_line(&params["position"])?;
+                Ok(target
+                    .and_then(|(_, text, desc)| hover(text, &self.config, desc, line))
+                    .unwrap_or(Value::Null))
+            }
+            "textDocument/foldingRange" => Ok(target
+                .map(|(_, text, desc)| folding_ranges(text, desc))
+                .unwrap_or_default()
+                .into()),
+            "textDocument/codeAction" => {
+                let line = position_line(&params["range"]["start"])?;
+                let action = target.and_then(|(uri, text, desc)| {
+                    let (title, tag, edits) = toggle_edits(text, &self.config, desc, line)?;
+                    Some(json!({
+                        "title": format!("{} `{}` branch", title, tag),
+                        "kind": "refactor.rewrite",
+                        "edit": { "changes": { uri: edits } },
+                    }))
+                });
+                Ok(action.into_iter().collect::<Vec<_>>().into())
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method: {}", method))),
+        }
+    }
+
+    /// Serves client until it sends `exit` or closes input, returns whether shutdown was
+    /// requested first
+    pub fn serve(mut self, input: &mut impl BufRead, output: &mut impl Write) -> io::Result<bool> {
+        while let Some(message) = read_message(input)? {
+            let message = match message {
+                Ok(v) => v,
+                Err(e) => {
+                    let error = json!({ "code": PARSE_ERROR, "message": e.to_string() });
+                    let response = json!({ "jsonrpc": "2.0", "id": null, "error": error });
+                    write_message(output, &response)?;
+                    continue;
+                }
+            };
+            let method = message["method"].as_str().unwrap_or("");
+            let params = &message["params"];
+            let id = match message.get("id") {
+                Some(id) => id,
+                None if method == "exit" => return Ok(self.shutdown),
+                None => {
+                    self.notify(output, method, params)?;
+                    continue;
+                }
+            };
+            let response = match self.request(method, params) {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, message)) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": code, "message": message },
+                }),
+            };
+            write_message(output, &response)?;
+        }
+        Ok(self.shutdown)
+    }
+}
+
+/// 1-based line of LSP position
+fn position_line(position: &Value) -> Result<usize, (i64, String)> {
+    match position["line"].as_u64() {
+        Some(line) => Ok(line as usize + 1),
+        None => Err((INVALID_PARAMS, "missing position".to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(content: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", content.len(), content)
+    }
+
+    /// Messages server sent in response to `input`, and whether it was shut down
+    fn transcript(input: &[Value]) -> (Vec<Value>, bool) {
+        let input: String = input.iter().map(|m| frame(&m.to_string())).collect();
+        transcript_raw(&input)
+    }
+
+    fn transcript_raw(input: &str) -> (Vec<Value>, bool) {
+        let mut output = Vec::new();
+        let server = Server::new(Data::default());
+        let shutdown = server.serve(&mut input.as_bytes(), &mut output).unwrap();
+        let mut output = output.as_slice();
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            messages.push(message.unwrap());
+        }
+        (messages, shutdown)
+    }
+
+    #[test]
+    fn messages_are_framed() {
+        let input = "content-length: 2\r\nContent-Type: x\r\n\r\n{}Content-Length: 1\r\n\r\n1";
+        let mut input = input.as_bytes();
+        assert_eq!(
+            read_message(&mut input).unwrap().unwrap().unwrap(),
+            json!({})
+        );
+        assert_eq!(
+            read_message(&mut input).unwrap().unwrap().unwrap(),
+            json!(1)
+        );
+        assert!(read_message(&mut input).unwrap().is_none());
+
+        let mut input = "Content-Type: x\r\n\r\n{}".as_bytes();
+        assert!(read_message(&mut input).is_err());
+    }
+
+    #[test]
+    fn initialize_and_shutdown() {
+        let (messages, shutdown) = transcript(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ]);
+        assert!(shutdown);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["id"], 1);
+        assert_eq!(messages[0]["result"]["capabilities"]["textDocumentSync"], 1);
+        assert_eq!(
+            messages[1],
+            json!({ "jsonrpc": "2.0", "id": 2, "result": null })
+        );
+    }
+
+    #[test]
+    fn exit_without_shutdown() {
+        let (messages, shutdown) = transcript(&[json!({ "jsonrpc": "2.0", "method": "exit" })]);
+        assert!(!shutdown);
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn opened_document_is_diagnosed() {
+        let uri = "file:///nonexistent/a.rs";
+        let (messages, _) = transcript(&[json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": {
+                "uri": uri,
+                "text": "//[cfg(feature = \"a\")]\na();\n",
+            } },
+        })]);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0]["method"], "textDocument/publishDiagnostics");
+        assert_eq!(messages[0]["params"]["uri"], uri);
+        let diagnostics = messages[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["range"]["start"]["line"], 0);
+        assert_eq!(
+            diagnostics[0]["message"],
+            "start tag on line 1 is never closed"
+        );
+    }
+
+    #[test]
+    fn malformed_message_is_reported() {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": "unknown" });
+        let input = format!("{}{}", frame("{"), frame(&request.to_string()));
+        let (messages, _) = transcript_raw(&input);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["id"], Value::Null);
+        assert_eq!(messages[0]["error"]["code"], PARSE_ERROR);
+        assert_eq!(messages[1]["id"], 1);
+        assert_eq!(messages[1]["error"]["code"], METHOD_NOT_FOUND);
+    }
+}
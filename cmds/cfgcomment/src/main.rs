@@ -1,148 +1,2030 @@
 use anyhow::{bail, Context};
-use cfgcomment_core::{process, walkdir_parallel, Data, LangDesc};
-use git_filter_server::{GitFilterServer, ProcessingType, Processor};
+use cfgcomment_core::{
+    explain, looks_binary, process_files, process_str, spans, unified_diff, walkdir_parallel,
+    Branch, BranchKind, CfgCommentError, Config, Data, Encoding, Evaluation, LangDesc,
+    LangRegistry, Manifest, PathFilter, Progress, Region, Span, Target, Version, WalkOptions,
+    WalkSummary,
+};
+use filter::{ProcessingType, Processor};
+use metadata::{package_features, workspace_sources, CargoFeatures};
+use notify::Watcher;
 use std::{
-    collections::{HashMap, HashSet},
-    fs::OpenOptions,
-    io::{BufRead, BufReader, Write},
-    path::PathBuf,
-    process::Command,
-    rc::Rc,
-    sync::Arc,
+    borrow::Cow,
+    cell::OnceCell,
+    collections::{BTreeMap, BTreeSet, HashSet},
+    ffi::OsString,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitCode},
+    str::FromStr,
+    sync::{
+        mpsc::{Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 use structopt::StructOpt;
 
+mod filter;
+mod git;
+mod lsp;
+mod metadata;
+mod toggle;
+
+#[derive(StructOpt)]
+struct WriteOpts {
+    /// Save original of every changed file to <name><suffix> before rewriting it
+    #[structopt(long, name = "suffix", require_equals = true)]
+    backup: Option<Option<String>>,
+    /// Overwrite already existing backups
+    #[structopt(long)]
+    force: bool,
+    /// Don't write anything, list files which need changes and exit with code 1 if there are any
+    #[structopt(long)]
+    check: bool,
+    /// Don't write anything, list files which would be changed with number of changed lines
+    #[structopt(long, conflicts_with = "check")]
+    dry_run: bool,
+    /// Don't write anything, print unified diff of changes, suitable for `patch -p1`
+    #[structopt(long, conflicts_with_all = &["check", "dry-run"])]
+    diff: bool,
+    /// Leave files which are neither UTF-8, nor UTF-16 with byte order mark untouched,
+    /// instead of reporting them as errors
+    #[structopt(long)]
+    skip_unknown_encoding: bool,
+}
+impl WriteOpts {
+    fn walk_options(self) -> WalkOptions {
+        WalkOptions {
+            backup_suffix: self
+                .backup
+                .map(|suffix| suffix.unwrap_or_else(|| ".orig".to_owned())),
+            force: self.force,
+            check: self.check || self.dry_run,
+            diff: self.diff,
+            skip_unknown_encoding: self.skip_unknown_encoding,
+            ..Default::default()
+        }
+    }
+}
+
+/// Parses `name` or `name="value"` from `--cfg`
+fn parse_cfg(s: &str) -> (String, Option<String>) {
+    match s.split_once('=') {
+        Some((name, value)) => {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            (name.trim().to_owned(), Some(value.to_owned()))
+        }
+        None => (s.trim().to_owned(), None),
+    }
+}
+
+/// Overrides for target platform, which defaults to the host
+#[derive(StructOpt)]
+struct TargetOpts {
+    /// Value for cfg(target_os = "...")
+    #[structopt(long)]
+    target_os: Option<String>,
+    /// Value for cfg(target_arch = "...")
+    #[structopt(long)]
+    target_arch: Option<String>,
+    /// Values for cfg(target_family = "..."), may be repeated
+    #[structopt(long, number_of_values = 1)]
+    target_family: Vec<String>,
+    /// Value for cfg(target_env = "...")
+    #[structopt(long)]
+    target_env: Option<String>,
+    /// Value for cfg(target_vendor = "...")
+    #[structopt(long)]
+    target_vendor: Option<String>,
+    /// Value for cfg(target_endian = "...")
+    #[structopt(long)]
+    target_endian: Option<String>,
+    /// Value for cfg(target_pointer_width = "...")
+    #[structopt(long)]
+    target_pointer_width: Option<String>,
+}
+impl TargetOpts {
+    fn target(self) -> Target {
+        let host = Target::host();
+        Target {
+            os: self.target_os.or(host.os),
+            arch: self.target_arch.or(host.arch),
+            family: if self.target_family.is_empty() {
+                host.family
+            } else {
+                self.target_family
+            },
+            env: self.target_env.or(host.env),
+            vendor: self.target_vendor.or(host.vendor),
+            endian: self.target_endian.or(host.endian),
+            pointer_width: self.target_pointer_width.or(host.pointer_width),
+        }
+    }
+}
+
+#[derive(StructOpt)]
+enum HookCmd {
+    /// Install pre-commit hook, which resets staged files and stages them again, failing
+    /// commit if they can't be reset
+    Install {
+        /// Replace existing pre-commit hook
+        #[structopt(long)]
+        force: bool,
+    },
+    /// Remove pre-commit hook installed by hook install
+    Uninstall,
+    /// Reset staged files and stage them again, ran by installed hook
+    Run,
+}
+
+/// Marks pre-commit hook as installed by cfgcomment
+const HOOK_MARKER: &str = "# Installed by cfgcomment hook install";
+
+/// Walker settings shared by all commands processing files
+#[derive(StructOpt)]
+struct WalkFlags {
+    /// Show number of processed files on stderr during long walks
+    #[structopt(long, global = true)]
+    progress: bool,
+    /// Number of threads processing files, defaults to number of cores. 1 gives
+    /// deterministic single-threaded runs
+    #[structopt(long, global = true, default_value = "0", hide_default_value = true)]
+    threads: usize,
+    /// Remember processed files in this file, and skip them on next runs unless they,
+    /// features, environment or language config changed
+    #[structopt(long, global = true)]
+    cache: Option<PathBuf>,
+    /// Skip paths matching this glob, in .gitignore syntax relative to current directory,
+    /// i.e `vendor/**` or `*.min.js`. Applies to --files-from lists too
+    #[structopt(long, global = true, number_of_values = 1)]
+    exclude: Vec<String>,
+    /// Only process files matching this glob, i.e `src/**/*.rs`. Repeat to allow several
+    /// patterns, --exclude still applies to matching files
+    #[structopt(long, global = true, number_of_values = 1)]
+    only: Vec<String>,
+    /// Skip paths ignored by this file, in .gitignore syntax relative to its directory
+    #[structopt(long, global = true, number_of_values = 1)]
+    ignore_file: Vec<PathBuf>,
+    /// Process hidden files and directories, i.e `.github/`
+    #[structopt(long, global = true)]
+    hidden: bool,
+    /// Don't respect .gitignore, .ignore and .cfgignore files
+    #[structopt(long, global = true)]
+    no_ignore: bool,
+    /// Don't respect .gitignore and git excludes, other ignore files are still respected
+    #[structopt(long, global = true)]
+    no_ignore_vcs: bool,
+    /// Walk into symlinked directories and process symlinked files, which are skipped by
+    /// default. Symlink loops are detected and skipped
+    #[structopt(long, global = true)]
+    follow_symlinks: bool,
+    /// Skip files larger than this, with K, M or G suffix for KiB, MiB or GiB. 0 disables
+    /// the limit. Skipped files are reported with --verbose
+    #[structopt(long, global = true, default_value = "10M", parse(try_from_str = parse_size))]
+    max_filesize: u64,
+}
+
+/// Size in bytes, i.e `512`, `64K` or `10M`
+fn parse_size(size: &str) -> anyhow::Result<u64> {
+    let (number, unit) = match size.char_indices().last() {
+        Some((i, 'K')) => (&size[..i], 1 << 10),
+        Some((i, 'M')) => (&size[..i], 1 << 20),
+        Some((i, 'G')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    let number: u64 = number.parse().context("expected size, i.e 512, 64K or 10M")?;
+    number.checked_mul(unit).context("size is too large")
+}
+
+impl WalkFlags {
+    fn filter(&self) -> anyhow::Result<PathFilter> {
+        Ok(PathFilter::new(&self.exclude, &self.only, &self.ignore_file)?)
+    }
+}
+
+/// Explicit list of files, i.e output of `git ls-files -z`
+#[derive(StructOpt, Default)]
+struct FilesFromOpts {
+    /// Process exactly the files listed in this file, one per line, `-` reads list from stdin.
+    /// Directory walker and ignore files are bypassed
+    #[structopt(long, conflicts_with = "paths")]
+    files_from: Option<PathBuf>,
+    /// Paths in --files-from list are separated by NUL instead of newline
+    #[structopt(short = "0", long, requires = "files-from")]
+    null: bool,
+    /// Process only files under paths which changed since this git revision, including
+    /// uncommitted and untracked ones. Like with --files-from, ignore files are bypassed
+    #[structopt(long, conflicts_with = "files-from")]
+    since: Option<String>,
+    /// Process only files under paths which are tracked by git, so build artifacts and
+    /// scratch files are never touched. Like with --files-from, ignore files are bypassed
+    #[structopt(long, conflicts_with = "files-from")]
+    tracked: bool,
+    /// List is read once, stdin can't be read again
+    #[structopt(skip)]
+    list: OnceCell<Vec<PathBuf>>,
+}
+impl FilesFromOpts {
+    /// Files to process instead of walking `paths`, if any
+    fn read(&self, paths: &[PathBuf]) -> anyhow::Result<Option<Vec<PathBuf>>> {
+        if let Some(list) = self.list.get() {
+            return Ok(Some(list.clone()));
+        }
+        let list = match (&self.files_from, &self.since) {
+            (Some(source), _) => read_list(source, self.null)?,
+            (None, Some(rev)) => git::changed_since(rev, paths, self.tracked)?,
+            (None, None) if self.tracked => git::tracked(paths)?,
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(self.list.get_or_init(|| list).clone()))
+    }
+
+    /// Processes listed files, or walks paths if there is no list
+    fn process(
+        &self,
+        paths: Vec<PathBuf>,
+        config: Data,
+        options: WalkOptions,
+        flags: &WalkFlags,
+    ) -> anyhow::Result<WalkSummary> {
+        walk(self.read(&paths)?, paths, config, options, flags)
+    }
+
+    /// Processes paths like [`Self::process`], then keeps processing files changed under
+    /// them again, until interrupted
+    fn watch(
+        &self,
+        paths: Vec<PathBuf>,
+        config: Data,
+        options: WalkOptions,
+        flags: &WalkFlags,
+        show: &PathDisplay,
+    ) -> anyhow::Result<()> {
+        let files = self.read(&paths)?;
+        let lang_config = match &files {
+            Some(_) => project_config(".".as_ref())?,
+            None => paths_config(&paths)?,
+        }
+        .lang_config();
+        let roots = files.clone().unwrap_or_else(|| paths.clone());
+        let filter = flags.filter()?;
+        let summary = walk(files, paths, config.clone(), options.clone(), flags)?;
+        report_changed(summary, show);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx).context("while starting watcher")?;
+        for root in &roots {
+            watcher
+                .watch(root, notify::RecursiveMode::Recursive)
+                .with_context(|| format!("while watching {}", root.display()))?;
+        }
+        eprintln!("watching for changes, press Ctrl-C to stop");
+        loop {
+            // Changed files are processed without ignore files, only git internals are known
+            // to be never wanted
+            let files: Vec<PathBuf> = next_changes(&rx)?
+                .into_iter()
+                .filter(|p| p.is_file() && !p.components().any(|c| c.as_os_str() == ".git"))
+                .collect();
+            if files.is_empty() {
+                continue;
+            }
+            let options = WalkOptions {
+                threads: flags.threads,
+                cache: flags.cache.clone(),
+                filter: filter.clone(),
+                ..options.clone()
+            };
+            let summary = process_files(files, config.clone(), lang_config.clone(), options);
+            report_changed(summary, show);
+        }
+    }
+
+    /// Lists cfg regions without evaluating or changing anything
+    fn regions(&self, paths: Vec<PathBuf>, flags: &WalkFlags) -> anyhow::Result<WalkSummary> {
+        // Tags are only parsed, so evaluation inputs don't matter
+        let config = Data {
+            reset: true,
+            ..Default::default()
+        };
+        let options = WalkOptions {
+            check: true,
+            regions: true,
+            ..Default::default()
+        };
+        self.process(paths, config, options, flags)
+    }
+}
+
+/// Paths listed in `source` file, one per line or NUL-separated, `-` is stdin
+fn read_list(source: &Path, null: bool) -> anyhow::Result<Vec<PathBuf>> {
+    let list = if source.as_os_str() == "-" {
+        let mut list = Vec::new();
+        std::io::stdin().read_to_end(&mut list)?;
+        list
+    } else {
+        std::fs::read(source).with_context(|| format!("while reading {}", source.display()))?
+    };
+    let separator = if null { b'\0' } else { b'\n' };
+    Ok(list
+        .split(|c| *c == separator)
+        .map(|path| match separator {
+            b'\n' => path.strip_suffix(b"\r").unwrap_or(path),
+            _ => path,
+        })
+        .filter(|path| !path.is_empty())
+        .map(path_from_bytes)
+        .collect())
+}
+
+/// Processes listed files, or walks paths if there is no list
+fn walk(
+    files: Option<Vec<PathBuf>>,
+    paths: Vec<PathBuf>,
+    config: Data,
+    mut options: WalkOptions,
+    flags: &WalkFlags,
+) -> anyhow::Result<WalkSummary> {
+    options.threads = flags.threads;
+    options.cache = flags.cache.clone();
+    options.filter = flags.filter()?;
+    options.hidden = flags.hidden;
+    options.no_ignore = flags.no_ignore;
+    options.no_ignore_vcs = flags.no_ignore_vcs;
+    options.follow_symlinks = flags.follow_symlinks;
+    options.max_filesize = Some(flags.max_filesize).filter(|max| *max != 0);
+    let bar = Arc::new(ProgressBar::default());
+    if flags.progress {
+        let bar = bar.clone();
+        options.progress = Some(Arc::new(move |p| bar.update(p)));
+    }
+    let summary = match files {
+        Some(files) => {
+            let lang_config = project_config(".".as_ref())?.lang_config();
+            process_files(files, config, lang_config, options)
+        }
+        None => {
+            let lang_config = paths_config(&paths)?.lang_config();
+            walkdir_parallel(paths, config, lang_config, options)
+        }
+    };
+    if flags.progress {
+        bar.finish();
+    }
+    Ok(summary)
+}
+
+/// Pause in events after which changes are processed, editors may write single file in
+/// several steps
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Waits for changes, and collects paths changed until there is a pause
+fn next_changes(
+    rx: &Receiver<notify::Result<notify::Event>>,
+) -> anyhow::Result<BTreeSet<PathBuf>> {
+    let mut changed = BTreeSet::new();
+    let mut event = rx.recv().context("watcher stopped")?;
+    loop {
+        match event {
+            Ok(event) if event.kind.is_create() || event.kind.is_modify() => {
+                changed.extend(event.paths)
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("watch error: {}", e),
+        }
+        event = match rx.recv_timeout(WATCH_DEBOUNCE) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => return Ok(changed),
+            Err(RecvTimeoutError::Disconnected) => bail!("watcher stopped"),
+        };
+    }
+}
+
+/// Lists written files with number of changed lines
+fn report_changed(summary: WalkSummary, paths: &PathDisplay) -> u8 {
+    for path in &summary.changed {
+        let lines = summary.changed_lines[path].len();
+        let plural = if lines == 1 { "" } else { "s" };
+        eprintln!("{}: {} line{} changed", paths.show(path).display(), lines, plural);
+    }
+    summary_exit_code(summary, Report::Written, Format::Text, paths)
+}
+
+/// Single status line on stderr, redrawn at most every 100ms
+#[derive(Default)]
+struct ProgressBar {
+    state: Mutex<(Option<Instant>, Progress)>,
+}
+impl ProgressBar {
+    fn update(&self, progress: Progress) {
+        let mut state = self.state.lock().unwrap();
+        state.1 = progress;
+        if matches!(state.0, Some(drawn) if drawn.elapsed() < Duration::from_millis(100)) {
+            return;
+        }
+        state.0 = Some(Instant::now());
+        Self::draw(progress);
+    }
+    fn finish(&self) {
+        Self::draw(self.state.lock().unwrap().1);
+        eprintln!();
+    }
+    fn draw(p: Progress) {
+        eprint!(
+            "\r{}/{} files processed, {} changed",
+            p.processed, p.discovered, p.changed
+        );
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes(path: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::OsStr::from_bytes(path).into()
+}
+#[cfg(not(unix))]
+fn path_from_bytes(path: &[u8]) -> PathBuf {
+    String::from_utf8_lossy(path).into_owned().into()
+}
+
+/// Filter mode, for use in pipes and by editors
+#[derive(StructOpt)]
+struct StdinOpts {
+    /// Read single file from stdin and write processed result to stdout, instead of
+    /// processing paths
+    #[structopt(
+        long,
+        requires = "lang",
+        conflicts_with_all = &["paths", "files-from", "check", "dry-run", "diff", "suffix"]
+    )]
+    stdin: bool,
+    /// Language of stdin input, by name (`rust`) or extension (`rs`)
+    #[structopt(long, requires = "stdin")]
+    lang: Option<String>,
+}
+impl StdinOpts {
+    /// Processes stdin to stdout if enabled, encoding and line terminators are kept
+    fn filter(&self, config: &Data) -> anyhow::Result<bool> {
+        let lang = match (self.stdin, &self.lang) {
+            (true, Some(lang)) => lang,
+            _ => return Ok(false),
+        };
+        let lang_config = project_config(".".as_ref())?.lang_config();
+        let desc = lang_config
+            .get_by_name(lang)
+            .with_context(|| format!("unknown language {:?}", lang))?;
+        let mut data = Vec::new();
+        std::io::stdin().read_to_end(&mut data)?;
+        let (encoding, text) = Encoding::decode(&data).ok_or(CfgCommentError::UnknownEncoding)?;
+        let processed = process_str(&text, config, desc)?;
+        std::io::stdout().write_all(&encoding.encode(&processed))?;
+        Ok(true)
+    }
+}
+
+/// Inputs for evaluating cfg predicates, shared by apply and check
 #[derive(StructOpt)]
-#[structopt(name = "cfgcomment", author)]
-enum Opts {
+struct EvalOpts {
+    /// Features to use with cfg(feature = "name")
+    #[structopt(long)]
+    features: Vec<String>,
+    /// Also enable features of `[preset.<name>]` from project config. May be repeated
+    #[structopt(long, number_of_values = 1)]
+    preset: Vec<String>,
+    /// Enable features like cargo does for Cargo.toml at this path, or closest to paths:
+    /// default ones, and everything enabled features depend on
+    #[structopt(long, name = "manifest", require_equals = true)]
+    features_from_manifest: Option<Option<PathBuf>>,
+    /// Don't enable default features of --features-from-manifest
+    #[structopt(long, requires = "manifest")]
+    no_default_features: bool,
+    /// Enable every feature referenced by cfg tags under paths, and every feature of
+    /// --features-from-manifest
+    #[structopt(long)]
+    all_features: bool,
+    /// Resolve --features-from-manifest with `cargo metadata`, giving exactly the features
+    /// cargo builds with, unified across the workspace
+    #[structopt(long, requires = "manifest")]
+    cargo_metadata: bool,
+    /// Workspace member to take features of with --cargo-metadata, defaults to package
+    /// of the manifest
+    #[structopt(long, requires = "cargo-metadata")]
+    package: Option<String>,
+    /// Custom options, `name` enables cfg(name) and `name="value"` enables cfg(name = "value").
+    /// May be repeated
+    #[structopt(long, number_of_values = 1, parse(from_str = parse_cfg))]
+    cfg: Vec<(String, Option<String>)>,
+    /// Version for cfg(version("1.70")), blocks gated on version stay disabled if unset
+    #[structopt(long)]
+    lang_version: Option<Version>,
+    #[structopt(flatten)]
+    target: TargetOpts,
+    /// Reject misaligned end tags and trailing commas in tags, skipping affected files
+    #[structopt(long)]
+    strict: bool,
+}
+impl EvalOpts {
+    /// Presets are looked up in project config of `paths`, manifest closest to the first of them
+    fn data(
+        self,
+        paths: &[PathBuf],
+        files_from: &FilesFromOpts,
+        flags: &WalkFlags,
+    ) -> anyhow::Result<Data> {
+        let config_start = config_start(paths);
+        let config = paths_config(paths)?;
+        let mut features: HashSet<String> = self.features.into_iter().collect();
+        for name in &self.preset {
+            features.extend(config.preset(name)?.features.iter().cloned());
+        }
+        if let Some(path) = self.features_from_manifest {
+            let manifest = match path {
+                Some(path) => Manifest::load(&path)?,
+                None => Manifest::discover(config_start)?
+                    .context("no Cargo.toml found for --features-from-manifest")?,
+            };
+            if self.all_features {
+                features.extend(manifest.features().map(str::to_owned));
+            }
+            let mut requested: Vec<String> = features.into_iter().collect();
+            requested.sort();
+            let default = !self.no_default_features;
+            features = if self.cargo_metadata {
+                let flags = CargoFeatures {
+                    features: &requested,
+                    all: self.all_features,
+                    default,
+                };
+                package_features(manifest.path(), self.package.as_deref(), flags)?
+            } else {
+                manifest.resolve(&requested, default)?
+            };
+        }
+        if self.all_features {
+            let found = files_from.regions(paths.to_vec(), flags)?;
+            features.extend(used_features(&found).map(str::to_owned));
+        }
+        config.check_exclusive(&features)?;
+        let mut data = Data {
+            features,
+            cfg: self.cfg.into_iter().collect(),
+            version: self.lang_version,
+            target: self.target.target(),
+            strict: self.strict,
+            declared_features: config.declared_features,
+            ..Default::default()
+        };
+        data.capture_env();
+        Ok(data)
+    }
+}
+
+/// Where project config is discovered from, for paths given on command line
+fn config_start(paths: &[PathBuf]) -> &Path {
+    paths.first().map_or(".".as_ref(), |p| p.as_path())
+}
+
+/// Nothing needed changing, or all changes were written
+const EXIT_CLEAN: u8 = 0;
+/// `--check` or check command found files which need changes
+const EXIT_CHANGES_PENDING: u8 = 1;
+/// Some files failed to process, or command failed as a whole
+const EXIT_ERROR: u8 = 2;
+
+#[derive(StructOpt)]
+#[structopt(
+    name = "cfgcomment",
+    author,
+    after_help = "EXIT CODES:\n    0    Success, nothing left to change\n    1    --check or check found files which need changes\n    2    Processing errors"
+)]
+struct Opts {
+    /// Directory reported paths are relative to, defaults to current directory
+    #[structopt(long, global = true)]
+    root: Option<PathBuf>,
+    /// Log skipped files and other details
+    #[structopt(short, long, global = true)]
+    verbose: bool,
+    #[structopt(flatten)]
+    walk: WalkFlags,
+    #[structopt(subcommand)]
+    cmd: Cmd,
+}
+
+#[derive(StructOpt)]
+#[allow(clippy::large_enum_variant)]
+enum Cmd {
     /// Configure git filter for resetting comments on stage
-    Init,
+    Init {
+        /// Replace filter config which runs something else, i.e set up by hand
+        #[structopt(long)]
+        force: bool,
+        /// Attributes file to assign filter in, relative to repository root. Use
+        /// `.git/info/attributes` to set filter up for this clone only
+        #[structopt(long, default_value = ".gitattributes")]
+        attributes_file: PathBuf,
+    },
+    /// Manage pre-commit hook, an alternative to git filter which doesn't need every
+    /// contributor to run init
+    Hook {
+        #[structopt(subcommand)]
+        cmd: HookCmd,
+    },
+    /// Remove git filter config, and filter assignments added by init
+    Uninstall {
+        /// Attributes file filter was assigned in, relative to repository root
+        #[structopt(long, default_value = ".gitattributes")]
+        attributes_file: PathBuf,
+    },
     /// Internal command used by git attributes
+    ///
+    /// Set `CFGCOMMENT_VERIFY=1` or `git config cfgcomment.verify true` to reject
+    /// staging files which are not in reset state, instead of resetting them.
+    ///
+    /// `committed_features` from `.cfgcomment.toml` are applied instead of reset, if set
+    ///
+    /// Set `git config cfgcomment.features "gui,tls"` to apply these features on checkout,
+    /// so every clone may work with its own feature set, while repository keeps reset state.
+    /// With both set, files are verified to be in the state checkout would produce
     Git,
     /// Apply cfg comments
     Apply {
         /// Paths to process, if dir passed - then it is recursive walked
-        #[structopt(required = true)]
+        #[structopt(required_unless_one = &["stdin", "files-from"])]
         paths: Vec<PathBuf>,
-        /// Features to use with cfg(feature = "name")
+        #[structopt(flatten)]
+        files_from: FilesFromOpts,
+        #[structopt(flatten)]
+        stdin: StdinOpts,
+        #[structopt(flatten)]
+        eval: EvalOpts,
+        #[structopt(flatten)]
+        write: WriteOpts,
+        /// Keep running after applying, and apply again to every file changed under paths
+        #[structopt(long, conflicts_with_all = &["stdin", "check", "dry-run", "diff"])]
+        watch: bool,
+        /// Print `json` report of the whole run instead of text
+        #[structopt(
+            long = "report",
+            name = "format",
+            default_value = "text",
+            possible_values = &["text", "json"]
+        )]
+        format: Format,
+    },
+    /// Evaluate cfg comments like apply, but never write, list every line which doesn't
+    /// match requested features and exit with code 1 if there are any
+    Check {
+        /// Paths to check, if dir passed - then it is recursive walked
+        #[structopt(required_unless = "files-from")]
+        paths: Vec<PathBuf>,
+        #[structopt(flatten)]
+        files_from: FilesFromOpts,
+        #[structopt(flatten)]
+        eval: EvalOpts,
+        /// Expect reset state, with everything uncommented, instead of evaluating features
         #[structopt(long)]
-        features: Vec<String>,
+        reset: bool,
+        /// Print `json` report of the whole run, or `sarif` log for code scanning, instead of text
+        #[structopt(
+            long = "report",
+            visible_alias = "format",
+            name = "format",
+            default_value = "text",
+            possible_values = &["text", "json", "sarif"]
+        )]
+        format: Format,
+    },
+    /// List every cfg block with its line range, current state and predicate, nested
+    /// blocks are indented
+    Status {
+        /// Paths to inspect, if dir passed - then it is recursive walked
+        #[structopt(required_unless = "files-from")]
+        paths: Vec<PathBuf>,
+        #[structopt(flatten)]
+        files_from: FilesFromOpts,
+    },
+    /// List feature names referenced by cfg tags, with number of uses and locations of
+    /// every tag. Glob patterns are listed as written
+    ListFeatures {
+        /// Paths to inspect, if dir passed - then it is recursive walked
+        #[structopt(required_unless = "files-from")]
+        paths: Vec<PathBuf>,
+        #[structopt(flatten)]
+        files_from: FilesFromOpts,
+    },
+    /// Rewrite cfg tags into canonical form, with consistent spacing and without trailing
+    /// commas
+    Fmt {
+        /// Paths to format, if dir passed - then it is recursive walked
+        #[structopt(required_unless = "files-from")]
+        paths: Vec<PathBuf>,
+        #[structopt(flatten)]
+        files_from: FilesFromOpts,
+        /// Sort operands of `all()` and `any()`
+        #[structopt(long)]
+        sort: bool,
+        #[structopt(flatten)]
+        write: WriteOpts,
+    },
+    /// Count lines guarded by cfg blocks per feature and per file, how many of them are
+    /// currently commented, and list the largest blocks
+    Stats {
+        /// Paths to inspect, if dir passed - then it is recursive walked
+        #[structopt(required_unless = "files-from")]
+        paths: Vec<PathBuf>,
+        #[structopt(flatten)]
+        files_from: FilesFromOpts,
+        /// Number of largest blocks to list
+        #[structopt(long, default_value = "10")]
+        top: usize,
+    },
+    /// Check syntax of every cfg tag, balance of blocks and consistent indentation without
+    /// changing anything, print every problem found and exit with error if there are any
+    Verify {
+        /// Paths to verify, if dir passed - then it is recursive walked
+        #[structopt(required_unless = "files-from")]
+        paths: Vec<PathBuf>,
+        #[structopt(flatten)]
+        files_from: FilesFromOpts,
+    },
+    /// Pick features to apply interactively, from the list of features found in cfg tags,
+    /// with preview of how many lines would change
+    Toggle {
+        /// Paths to process, if dir passed - then it is recursive walked
+        #[structopt(required_unless = "files-from")]
+        paths: Vec<PathBuf>,
+        #[structopt(flatten)]
+        files_from: FilesFromOpts,
+        /// Features are initially selected
+        #[structopt(flatten)]
+        eval: EvalOpts,
+    },
+    /// Print cfg blocks of single file with evaluation of every predicate and its
+    /// operands, to find out why some block is commented
+    Explain {
+        /// File to explain
+        path: PathBuf,
+        #[structopt(flatten)]
+        eval: EvalOpts,
+    },
+    /// Print branches of cfg blocks in single file as JSON, with line and byte ranges,
+    /// predicate and state, for gutter markers and dimming in editors
+    Spans {
+        /// File to inspect
+        path: PathBuf,
+        /// Whether branches are enabled is judged with these
+        #[structopt(flatten)]
+        eval: EvalOpts,
+    },
+    /// Run language server on stdio: diagnostics for malformed and unbalanced tags,
+    /// folding of cfg blocks, evaluation of predicates on hover and code action to comment
+    /// out or uncomment branch
+    Lsp {
+        /// Predicates are evaluated with these on hover, --all-features enables features
+        /// referenced under current directory
+        #[structopt(flatten)]
+        eval: EvalOpts,
     },
     /// Reset cfg comments, uncommenting everything
-    Reset { paths: Vec<PathBuf> },
+    Reset {
+        /// Paths to process, if dir passed - then it is recursive walked
+        #[structopt(required_unless_one = &["stdin", "files-from"])]
+        paths: Vec<PathBuf>,
+        #[structopt(flatten)]
+        files_from: FilesFromOpts,
+        #[structopt(flatten)]
+        stdin: StdinOpts,
+        /// Reject misaligned end tags and trailing commas in tags, skipping affected files
+        #[structopt(long)]
+        strict: bool,
+        #[structopt(flatten)]
+        write: WriteOpts,
+        /// Print `json` report of the whole run instead of text
+        #[structopt(
+            long = "report",
+            name = "format",
+            default_value = "text",
+            possible_values = &["text", "json"]
+        )]
+        format: Format,
+    },
 }
 
 struct UncommentingProcessor {
     config: Arc<Data>,
-    lang_config: HashMap<String, LangDesc>,
+    /// Config applied on checkout, files are checked out as is if there is none
+    smudge: Option<Arc<Data>>,
+    lang_config: LangRegistry,
+    /// Fail on files not in canonical form, instead of fixing them
+    verify: bool,
 }
 impl Processor for UncommentingProcessor {
-    fn process<R: std::io::Read, W: Write>(
-        &mut self,
+    fn process(
+        &self,
         pathname: &str,
-        _process_type: ProcessingType,
-        input: &mut R,
-        output: &mut W,
-    ) -> anyhow::Result<()> {
-        let path = PathBuf::from(pathname);
-        let extension = match path.as_path().extension() {
+        process_type: ProcessingType,
+        data: Vec<u8>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let (config, verify) = match (process_type, &self.smudge) {
+            (ProcessingType::Smudge, Some(smudge)) => (smudge, false),
+            // Required filter has to handle checkout too
+            (ProcessingType::Smudge, None) => return Ok(data),
+            (ProcessingType::Clean, _) => (&self.config, self.verify),
+        };
+        if looks_binary(&data) {
+            return Ok(data);
+        }
+        let (encoding, text) = match Encoding::decode(&data) {
             Some(v) => v,
             None => {
-                std::io::copy(input, output)?;
-                return Ok(());
+                tracing::warn!("{}: unknown encoding, passed as is", pathname);
+                return Ok(data);
             }
         };
-        let extension = extension.to_string_lossy().to_string();
-        let desc = match self.lang_config.get(&extension) {
+        let first_line = text.split('\n').next().unwrap_or_default();
+        let desc = match self.lang_config.resolve(pathname.as_ref(), first_line)? {
             Some(v) => v,
-            None => {
-                std::io::copy(input, output)?;
-                return Ok(());
+            None => return Ok(data),
+        };
+
+        let processed = process_str(&text, config, desc)?;
+        if verify {
+            // Work tree is in smudged state, which is canonical if checkout reproduces it
+            let expected = match &self.smudge {
+                Some(smudge) => Cow::Owned(process_str(&processed, smudge, desc)?),
+                None => Cow::Borrowed(&processed),
+            };
+            if let Some(i) = first_difference(&text, &expected) {
+                bail!("{}:{} is not in committed state", pathname, i + 1);
+            }
+            if self.smudge.is_none() {
+                return Ok(data);
+            }
+        }
+        Ok(encoding.encode(&processed))
+    }
+}
+
+/// Index of first line not equal in both, including lines only one of them has
+fn first_difference(original: &str, expected: &str) -> Option<usize> {
+    let original: Vec<&str> = original.lines().collect();
+    let expected: Vec<&str> = expected.lines().collect();
+    original
+        .iter()
+        .zip(&expected)
+        .position(|(original, expected)| original != expected)
+        .or_else(|| {
+            (original.len() != expected.len()).then(|| original.len().min(expected.len()))
+        })
+}
+
+fn git_verify_enabled() -> anyhow::Result<bool> {
+    if let Some(value) = std::env::var_os("CFGCOMMENT_VERIFY") {
+        return Ok(!value.is_empty() && value != "0");
+    }
+    let output = Command::new("git")
+        .args(["config", "--bool", "cfgcomment.verify"])
+        .output()
+        .context("while reading cfgcomment.verify")?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim() == "true")
+}
+
+/// How files are committed, `committed_features` are applied if set, otherwise files are
+/// reset
+fn committed_data(config: Config) -> Data {
+    match config.committed_features {
+        Some(features) => Data {
+            features,
+            declared_features: config.declared_features,
+            ..Default::default()
+        },
+        None => Data {
+            reset: true,
+            ..Default::default()
+        },
+    }
+}
+
+fn run_hook(cmd: HookCmd, flags: &WalkFlags, paths: &PathDisplay) -> anyhow::Result<u8> {
+    let path = git::git_path("hooks/pre-commit")?;
+    let ours = read_if_exists(&path)?.map(|hook| hook.contains(HOOK_MARKER));
+    match cmd {
+        HookCmd::Install { force } => {
+            if ours == Some(false) && !force {
+                bail!(
+                    "{} already exists, use --force to replace it",
+                    path.display()
+                );
+            }
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
             }
+            std::fs::write(
+                &path,
+                format!("#!/bin/sh\n{}\nexec cfgcomment hook run\n", HOOK_MARKER),
+            )
+            .with_context(|| format!("while writing {}", path.display()))?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+            }
+        }
+        HookCmd::Uninstall => match ours {
+            Some(true) => std::fs::remove_file(&path)?,
+            Some(false) => bail!("{} is not installed by cfgcomment", path.display()),
+            None => {}
+        },
+        HookCmd::Run => {
+            let staged = git::staged()?;
+            let config = project_config(&git::toplevel()?)?;
+            let lang_config = config.lang_config();
+            let data = committed_data(config);
+            let options = WalkOptions {
+                threads: flags.threads,
+                ..Default::default()
+            };
+            let check = WalkOptions {
+                check: true,
+                ..options.clone()
+            };
+            let pending = process_files(staged, data.clone(), lang_config.clone(), check);
+            if !pending.errors.is_empty() {
+                return Ok(summary_exit_code(pending, Report::Nothing, Format::Text, paths));
+            }
+            // Staging whole file would sneak unstaged changes into commit
+            let unstaged = git::unstaged()?;
+            let partial: Vec<&PathBuf> =
+                pending.changed.iter().filter(|p| unstaged.contains(*p)).collect();
+            for path in &partial {
+                tracing::error!(
+                    "{}: not in committed state and has unstaged changes, stage them or reset it",
+                    paths.show(path).display()
+                );
+            }
+            if !partial.is_empty() {
+                return Ok(EXIT_ERROR);
+            }
+            let summary = process_files(pending.changed, data, lang_config, options);
+            git::add(&summary.changed)?;
+            return Ok(report_changed(summary, paths));
+        }
+    }
+    Ok(EXIT_CLEAN)
+}
+
+/// Command git runs as filter, set up by init
+const FILTER_PROCESS: &str = "cfgcomment git";
+
+fn read_if_exists(path: &Path) -> anyhow::Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(v) => Ok(Some(v)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("while reading {}", path.display())),
+    }
+}
+
+/// Features of `git config cfgcomment.features`, separated by commas or spaces
+fn git_smudge_features() -> anyhow::Result<Option<HashSet<String>>> {
+    let output = Command::new("git")
+        .args(["config", "--get", "cfgcomment.features"])
+        .output()
+        .context("while reading cfgcomment.features")?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(
+        String::from_utf8_lossy(&output.stdout)
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|f| !f.is_empty())
+            .map(str::to_owned)
+            .collect(),
+    ))
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path)
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_owned())
+}
+
+/// Formats paths for diagnostics relative to root, paths outside of it are shown absolute
+struct PathDisplay {
+    root: PathBuf,
+}
+impl PathDisplay {
+    fn new(root: Option<PathBuf>) -> anyhow::Result<Self> {
+        let root = match root {
+            Some(root) => root,
+            None => std::env::current_dir().context("while getting current directory")?,
         };
+        Ok(Self {
+            root: absolute(&root),
+        })
+    }
+    fn show(&self, path: &Path) -> PathBuf {
+        let path = absolute(path);
+        match path.strip_prefix(&self.root) {
+            Ok(relative) => relative.to_owned(),
+            Err(_) => path,
+        }
+    }
+}
+
+/// What is printed about files which need changes, for `Files` and `Lines` pending changes
+/// are also reported with exit code
+#[derive(Clone, Copy, PartialEq)]
+enum Report {
+    Written,
+    /// Pending changes are irrelevant to inspection commands
+    Nothing,
+    DryRun,
+    Diff,
+    Files,
+    Lines,
+}
+impl Report {
+    fn for_write(write: &WriteOpts) -> Self {
+        if write.check {
+            Report::Files
+        } else if write.dry_run {
+            Report::DryRun
+        } else if write.diff {
+            Report::Diff
+        } else {
+            Report::Written
+        }
+    }
+}
+
+/// Output of apply, reset and check
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    /// Single JSON document per run, for scripts and bots
+    Json,
+    /// SARIF log for code scanning, only supported by check
+    Sarif,
+}
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "sarif" => Ok(Format::Sarif),
+            _ => Err(format!("unknown report format {:?}", s)),
+        }
+    }
+}
+impl Format {
+    fn walk_options(self, options: WalkOptions) -> WalkOptions {
+        WalkOptions {
+            stats: self == Format::Json,
+            ..options
+        }
+    }
+}
+
+fn json_report(summary: &WalkSummary, paths: &PathDisplay) -> serde_json::Value {
+    let show = |path: &Path| paths.show(path).display().to_string();
+    serde_json::json!({
+        "scanned": summary.scanned,
+        "changed": summary
+            .changed
+            .iter()
+            .map(|path| serde_json::json!({
+                "path": show(path),
+                "lines": summary.changed_lines[path],
+            }))
+            .collect::<Vec<_>>(),
+        "commented_lines": summary.commented_lines,
+        "uncommented_lines": summary.uncommented_lines,
+        "errors": summary
+            .errors
+            .iter()
+            .map(|(path, e)| serde_json::json!({
+                "path": show(path),
+                "line": e.line(),
+                "message": e.to_string(),
+            }))
+            .collect::<Vec<_>>(),
+        "feature_blocks": summary.feature_blocks,
+    })
+}
+
+fn spans_report(spans: &[Span]) -> serde_json::Value {
+    let spans = spans.iter().map(|span| {
+        serde_json::json!({
+            "kind": branch_kind_name(span.kind),
+            "nesting_depth": span.nesting_depth,
+            "predicate": span.predicate,
+            "start_line": span.start_line,
+            "end_line": span.end_line,
+            "start_byte": span.bytes.start,
+            "end_byte": span.bytes.end,
+            "enabled": span.enabled,
+            "commented": span.commented,
+        })
+    });
+    spans.collect::<Vec<_>>().into()
+}
+
+fn sarif_report(summary: &WalkSummary, paths: &PathDisplay) -> serde_json::Value {
+    let location = |path: &Path, line: Option<usize>, column: Option<usize>| {
+        let uri = paths.show(path).display().to_string().replace('\\', "/");
+        let mut location = serde_json::json!({
+            "physicalLocation": { "artifactLocation": { "uri": uri } }
+        });
+        if let Some(line) = line {
+            let mut region = serde_json::json!({ "startLine": line });
+            if let Some(column) = column {
+                region["startColumn"] = column.into();
+            }
+            location["physicalLocation"]["region"] = region;
+        }
+        location
+    };
+    let out_of_state = summary.changed.iter().flat_map(|path| {
+        summary.changed_lines[path].iter().map(move |line| {
+            serde_json::json!({
+                "ruleId": "out-of-state",
+                "level": "error",
+                "message": { "text": "line is not in the requested commented state" },
+                "locations": [location(path, Some(*line), None)],
+            })
+        })
+    });
+    let errors = summary.errors.iter().map(|(path, e)| {
+        let column = match e {
+            CfgCommentError::InvalidTag { column, .. } => Some(*column),
+            _ => None,
+        };
+        serde_json::json!({
+            "ruleId": "processing-error",
+            "level": "error",
+            "message": { "text": e.to_string() },
+            "locations": [location(path, e.line(), column)],
+        })
+    });
+    serde_json::json!({
+        "version": "2.1.0",
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "cfgcomment",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": [
+                        {
+                            "id": "out-of-state",
+                            "shortDescription": {
+                                "text": "Line is commented differently from what cfg tags require"
+                            },
+                        },
+                        {
+                            "id": "processing-error",
+                            "shortDescription": {
+                                "text": "Malformed cfg tags or file which can't be processed"
+                            },
+                        },
+                    ],
+                },
+            },
+            "results": out_of_state.chain(errors).collect::<Vec<_>>(),
+        }],
+    })
+}
+
+fn summary_exit_code(
+    summary: WalkSummary,
+    report: Report,
+    format: Format,
+    paths: &PathDisplay,
+) -> u8 {
+    match format {
+        Format::Text => {}
+        Format::Json => println!("{}", json_report(&summary, paths)),
+        Format::Sarif => println!("{}", sarif_report(&summary, paths)),
+    }
+    for path in summary.changed.iter().filter(|_| format == Format::Text) {
+        match report {
+            Report::Written | Report::Nothing => {}
+            Report::DryRun => {
+                let lines = summary.changed_lines[path].len();
+                let plural = if lines == 1 { "" } else { "s" };
+                println!("{}: {} line{} would change", paths.show(path).display(), lines, plural);
+            }
+            Report::Diff => {
+                let name = paths.show(path);
+                print!(
+                    "{}",
+                    unified_diff(
+                        &format!("a/{}", name.display()),
+                        &format!("b/{}", name.display()),
+                        &summary.diffs[path],
+                    )
+                );
+            }
+            Report::Files => println!("{}", paths.show(path).display()),
+            Report::Lines => {
+                for line in &summary.changed_lines[path] {
+                    println!("{}:{}", paths.show(path).display(), line);
+                }
+            }
+        }
+    }
+    for (path, e) in &summary.errors {
+        tracing::error!("{}: {}, file skipped", paths.show(path).display(), e);
+    }
+    if !summary.errors.is_empty() {
+        EXIT_ERROR
+    } else if matches!(report, Report::Files | Report::Lines) && !summary.changed.is_empty() {
+        EXIT_CHANGES_PENDING
+    } else {
+        EXIT_CLEAN
+    }
+}
+
+/// Features given with --features, which no tag mentions, are likely misspelled
+fn warn_unreferenced(summary: &WalkSummary, requested: &[String], flags: &WalkFlags) {
+    // Files skipped as cached aren't read, their tags are unknown
+    if flags.cache.is_some() {
+        return;
+    }
+    let unreferenced = summary.unreferenced_features(requested);
+    if !unreferenced.is_empty() {
+        let list: Vec<&str> = unreferenced.iter().map(|f| f.as_str()).collect();
+        tracing::warn!("features not referenced by any cfg tag: {}", list.join(", "));
+    }
+}
+
+/// Reports blocks of mutually exclusive features which are active together, returns
+/// whether there are any
+fn print_conflicts(summary: &WalkSummary, exclusive: &[Vec<String>], paths: &PathDisplay) -> bool {
+    let conflicts = summary.exclusive_conflicts(exclusive);
+    for conflict in &conflicts {
+        let (feature, path, line) = &conflict.active[0];
+        for (other, other_path, other_line) in &conflict.active[1..] {
+            println!(
+                "{}:{}: block of {:?} is active together with block of exclusive {:?} at {}:{}",
+                paths.show(other_path).display(),
+                other_line,
+                other,
+                feature,
+                paths.show(path).display(),
+                line,
+            );
+        }
+    }
+    !conflicts.is_empty()
+}
 
-        let lines = BufReader::new(input).lines().map(|l| l.unwrap());
-        for line in process(lines, self.config.clone(), Rc::new(desc.clone())) {
-            writeln!(output, "{}", line)?;
+fn print_status(summary: &WalkSummary, paths: &PathDisplay) {
+    let mut files: Vec<_> = summary.regions.iter().collect();
+    files.sort_by(|a, b| a.0.cmp(b.0));
+    for (path, regions) in files {
+        for region in regions {
+            println!(
+                "{}:{}-{} {:9} {}{}",
+                paths.show(path).display(),
+                region.start,
+                region.end,
+                if region.commented { "commented" } else { "active" },
+                "  ".repeat(region.nesting_depth),
+                region.predicate_src,
+            );
         }
+    }
+}
 
-        Ok(())
+fn print_features(summary: &WalkSummary, paths: &PathDisplay) {
+    let mut uses: BTreeMap<&str, Vec<(&Path, usize)>> = BTreeMap::new();
+    for (path, regions) in &summary.regions {
+        for region in regions {
+            for feature in &region.features {
+                uses.entry(feature).or_default().push((path, region.start));
+            }
+        }
+    }
+    for (feature, mut locations) in uses {
+        locations.sort();
+        println!("{} ({})", feature, locations.len());
+        for (path, line) in locations {
+            println!("    {}:{}", paths.show(path).display(), line);
+        }
     }
+}
 
-    fn supports_processing(&self, process_type: ProcessingType) -> bool {
-        matches!(process_type, ProcessingType::Clean)
+/// Blocks and lines guarded by them
+#[derive(Default)]
+struct BlockStats {
+    blocks: usize,
+    lines: usize,
+    commented: usize,
+}
+impl BlockStats {
+    fn add(&mut self, region: &Region) {
+        self.blocks += 1;
+        self.lines += region.lines;
+        self.commented += region.commented_lines;
+    }
+    fn print(&self, name: &str, width: usize) {
+        println!(
+            "{:width$}  {:>6}  {:>7}  {:>7}  {:>9}",
+            name,
+            self.blocks,
+            self.lines,
+            self.lines - self.commented,
+            self.commented,
+            width = width,
+        );
     }
 }
 
-fn main() -> anyhow::Result<()> {
+fn print_stats(summary: &WalkSummary, top: usize, paths: &PathDisplay) {
+    let mut features: BTreeMap<&str, BlockStats> = BTreeMap::new();
+    let mut files: BTreeMap<PathBuf, BlockStats> = BTreeMap::new();
+    for (path, regions) in &summary.regions {
+        let file = files.entry(paths.show(path)).or_default();
+        for region in regions {
+            for feature in &region.features {
+                features.entry(feature).or_default().add(region);
+            }
+            // Nested blocks are already counted by their parents
+            if region.nesting_depth == 0 {
+                file.add(region);
+            } else {
+                file.blocks += 1;
+            }
+        }
+    }
+
+    let header = |name: &str, width| {
+        println!(
+            "{:width$}  {:>6}  {:>7}  {:>7}  {:>9}",
+            name,
+            "blocks",
+            "lines",
+            "enabled",
+            "commented",
+            width = width,
+        )
+    };
+    let width = features.keys().map(|f| f.len()).max().unwrap_or(0).max(7);
+    header("feature", width);
+    for (feature, stats) in &features {
+        stats.print(feature, width);
+    }
+    println!();
+    let names: Vec<String> = files.keys().map(|p| p.display().to_string()).collect();
+    let width = names.iter().map(|f| f.len()).max().unwrap_or(0).max(4);
+    header("file", width);
+    for (name, stats) in names.iter().zip(files.values()) {
+        stats.print(name, width);
+    }
+
+    let mut largest: Vec<(&Path, &Region)> = summary
+        .regions
+        .iter()
+        .flat_map(|(path, regions)| regions.iter().map(move |r| (path.as_path(), r)))
+        .collect();
+    largest.sort_by(|a, b| {
+        b.1.lines
+            .cmp(&a.1.lines)
+            .then(a.0.cmp(b.0))
+            .then(a.1.start.cmp(&b.1.start))
+    });
+    if top != 0 && !largest.is_empty() {
+        println!();
+        println!("largest blocks");
+    }
+    for (path, region) in largest.into_iter().take(top) {
+        println!(
+            "{:>7}  {}:{}-{}  {}",
+            region.lines,
+            paths.show(path).display(),
+            region.start,
+            region.end,
+            region.predicate_src
+        );
+    }
+}
+
+fn write_evaluation(out: &mut String, evaluation: &Evaluation, depth: usize) {
+    out.push_str(&format!(
+        "{}{:5}  {}\n",
+        "  ".repeat(depth),
+        evaluation.value,
+        evaluation.predicate
+    ));
+    for operand in &evaluation.operands {
+        write_evaluation(out, operand, depth + 1);
+    }
+}
+
+fn branch_kind_name(kind: BranchKind) -> &'static str {
+    match kind {
+        BranchKind::Start => "start",
+        BranchKind::ElseIf => "else_if",
+        BranchKind::Else => "else",
+        BranchKind::Line => "line",
+        BranchKind::File => "file",
+    }
+}
+
+/// Tag of branch as written in canonical form, i.e `cfg(else if feature = "a")`
+fn branch_tag(branch: &Branch, keyword: &str) -> String {
+    match (branch.kind, &branch.evaluation) {
+        (BranchKind::Start, Some(e)) => format!("{}({})", keyword, e.predicate),
+        (BranchKind::ElseIf, Some(e)) => format!("{}(else if {})", keyword, e.predicate),
+        (BranchKind::Line, Some(e)) => format!("{}_line({})", keyword, e.predicate),
+        (BranchKind::File, Some(e)) => format!("{}_file({})", keyword, e.predicate),
+        _ => format!("{}(else)", keyword),
+    }
+}
+
+fn branch_state(branch: &Branch) -> &'static str {
+    if branch.enabled {
+        "active"
+    } else if !branch.enclosing_enabled {
+        "commented, enclosing block is disabled"
+    } else if branch.earlier_branch_matched {
+        "commented, earlier branch is enabled"
+    } else {
+        "commented, predicate is false"
+    }
+}
+
+fn print_explanation(branches: &[Branch], keyword: &str) {
+    for branch in branches {
+        let indent = "  ".repeat(branch.nesting_depth * 2);
+        let tag = branch_tag(branch, keyword);
+        println!("{:>5}: {}{}  => {}", branch.line, indent, tag, branch_state(branch));
+        if let Some(evaluation) = &branch.evaluation {
+            let mut tree = String::new();
+            write_evaluation(&mut tree, evaluation, branch.nesting_depth * 2 + 4);
+            print!("{}", tree);
+        }
+    }
+}
+
+fn is_glob(feature: &str) -> bool {
+    feature.contains(['*', '?', '[', '{'])
+}
+
+/// Features named in regions, without glob patterns
+fn used_features(summary: &WalkSummary) -> impl Iterator<Item = &str> {
+    let features = summary.regions.values().flatten().flat_map(|r| &r.features);
+    features.filter(|f| !is_glob(f)).map(String::as_str)
+}
+
+/// Features named in regions, without glob patterns, and selected features which aren't
+/// used anywhere
+fn toggle_features(summary: &WalkSummary, selected: &HashSet<String>) -> Vec<toggle::Feature> {
+    let mut found: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for region in summary.regions.values().flatten() {
+        for feature in region.features.iter().filter(|f| !is_glob(f)) {
+            let entry = found.entry(feature).or_default();
+            entry.0 += 1;
+            entry.1 += region.lines;
+        }
+    }
+    for feature in selected {
+        found.entry(feature).or_default();
+    }
+    found
+        .into_iter()
+        .map(|(name, (blocks, lines))| toggle::Feature {
+            name: name.to_owned(),
+            blocks,
+            lines,
+            enabled: selected.contains(name),
+        })
+        .collect()
+}
+
+/// Decoded text of single file, with its language
+fn read_source<'r>(
+    path: &Path,
+    lang_config: &'r LangRegistry,
+) -> anyhow::Result<(String, &'r LangDesc)> {
+    let data = std::fs::read(path).with_context(|| format!("while reading {}", path.display()))?;
+    let (_, text) = Encoding::decode(&data).ok_or(CfgCommentError::UnknownEncoding)?;
+    let desc = lang_config
+        .resolve(path, text.lines().next().unwrap_or(""))?
+        .with_context(|| format!("unknown language of {}", path.display()))?;
+    Ok((text, desc))
+}
+
+/// Project config closest to `start`, defaults if there is none
+fn project_config(start: &Path) -> anyhow::Result<Config> {
+    Ok(Config::discover(start)?.unwrap_or_default())
+}
+
+/// Project config shared by all of `paths`, walks use languages of a single config, so paths
+/// under different ones are refused
+fn paths_config(paths: &[PathBuf]) -> anyhow::Result<Config> {
+    let start = config_start(paths);
+    let found = Config::discover_path(start)?;
+    for path in paths {
+        let other = Config::discover_path(path)?;
+        if other != found {
+            let show = |c: &Option<PathBuf>| match c {
+                Some(c) => c.display().to_string(),
+                None => "no config".to_owned(),
+            };
+            bail!(
+                "{} uses {}, but {} uses {}, process them separately",
+                start.display(),
+                show(&found),
+                path.display(),
+                show(&other)
+            );
+        }
+    }
+    Ok(match found {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    })
+}
+
+/// Under cargo, paths default to `src` directories of workspace members
+fn cargo_opts(args: Vec<OsString>) -> Opts {
+    let error = match Opts::from_iter_safe(&args) {
+        Ok(opts) => return opts,
+        Err(e) if e.kind == structopt::clap::ErrorKind::MissingRequiredArgument => e,
+        Err(e) => e.exit(),
+    };
+    let sources = workspace_sources().unwrap_or_else(|e| {
+        eprintln!("Error: {:?}", e);
+        error.exit()
+    });
+    let args = args
+        .into_iter()
+        .chain(std::iter::once("--".into()))
+        .chain(sources.into_iter().map(OsString::from));
+    Opts::from_iter_safe(args).unwrap_or_else(|_| error.exit())
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    // `cargo cfgcomment apply` runs `cargo-cfgcomment cfgcomment apply`
+    let opts = if args.get(1).is_some_and(|arg| arg == "cfgcomment") {
+        args.remove(1);
+        cargo_opts(args)
+    } else {
+        Opts::from_iter(args)
+    };
     tracing_subscriber::fmt::fmt()
         .with_writer(std::io::stderr)
+        // Debug output of dependencies is only noise for users
+        .with_env_filter(match opts.verbose {
+            true => "info,cfgcomment=debug,cfgcomment_core=debug",
+            false => "info",
+        })
         .init();
-    let opts = Opts::from_args();
+    match run(opts) {
+        Ok(code) => ExitCode::from(code),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
 
-    let lang_config = LangDesc::default_list();
+fn run(opts: Opts) -> anyhow::Result<u8> {
+    let path_display = PathDisplay::new(opts.root)?;
 
-    match opts {
-        Opts::Init => {
-            if std::fs::metadata(".git")
-                .map(|f| f.is_dir())
-                .unwrap_or(false)
-            {
-                bail!("cfgcomment init should be called in root of git repo");
-            }
-            Command::new("git")
-                .args(["config", "--bool", "filter.cfgcomment.required", "true"])
-                .output()
-                .context("while setting require")?;
-            Command::new("git")
-                .args(["config", "filter.cfgcomment.process", "cfgcomment git"])
-                .output()
-                .context("while setting process")?;
-            let attributes = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(".gitattributes")
-                .context("while creating .gitattributes")?;
-            let attributes_reader = BufReader::new(attributes);
-            let lines: HashSet<String> = attributes_reader.lines().flatten().collect();
-
-            let mut attributes = OpenOptions::new().append(true).open(".gitattributes")?;
+    match opts.cmd {
+        Cmd::Init {
+            force,
+            attributes_file,
+        } => {
+            let root = git::toplevel().context("cfgcomment init should be called in git repo")?;
+            match git::config_get("filter.cfgcomment.process")? {
+                Some(process) if process != FILTER_PROCESS && !force => bail!(
+                    "filter is already set up to run {:?}, use --force to replace it",
+                    process
+                ),
+                _ => {}
+            }
+            git::config_set("filter.cfgcomment.required", "true")?;
+            git::config_set("filter.cfgcomment.process", FILTER_PROCESS)?;
+
+            let lang_config = project_config(&root)?.lang_config();
             let needed_lines: Vec<String> = lang_config
-                .keys()
-                .map(|k| format!("*.{} filter=cfgcomment", k))
+                .extensions()
+                .map(|(k, _)| format!("*.{} {}", k, git::FILTER_ATTRIBUTE))
+                .chain(
+                    lang_config
+                        .filenames()
+                        .map(|(k, _)| format!("{} {}", k, git::FILTER_ATTRIBUTE)),
+                )
                 .collect();
-
-            for line in needed_lines {
-                if lines.contains(&line) {
-                    continue;
+            let path = root.join(attributes_file);
+            let content = read_if_exists(&path)?.unwrap_or_default();
+            let merged = git::merge_attributes(&content, &needed_lines);
+            if merged != content {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
                 }
-                writeln!(attributes, "{}", line)?;
+                std::fs::write(&path, merged)
+                    .with_context(|| format!("while writing {}", path.display()))?;
             }
         }
-        Opts::Git => {
-            GitFilterServer::new(UncommentingProcessor {
-                config: Arc::new(Data {
-                    features: HashSet::new(),
-                    reset: true,
-                }),
+        Cmd::Uninstall { attributes_file } => {
+            let root =
+                git::toplevel().context("cfgcomment uninstall should be called in git repo")?;
+            git::config_remove_section("filter.cfgcomment")?;
+            let path = root.join(attributes_file);
+            if let Some(content) = read_if_exists(&path)? {
+                let stripped = git::strip_attributes(&content);
+                if stripped.trim().is_empty() {
+                    std::fs::remove_file(&path)?;
+                } else if stripped != content {
+                    std::fs::write(&path, stripped)
+                        .with_context(|| format!("while writing {}", path.display()))?;
+                }
+            }
+        }
+        Cmd::Hook { cmd } => return run_hook(cmd, &opts.walk, &path_display),
+        Cmd::Git => {
+            let config = project_config(".".as_ref())?;
+            let lang_config = config.lang_config();
+            let smudge = match git_smudge_features()? {
+                Some(features) => {
+                    config.check_exclusive(&features)?;
+                    Some(Arc::new(Data {
+                        features,
+                        declared_features: config.declared_features.clone(),
+                        ..Default::default()
+                    }))
+                }
+                None => None,
+            };
+            let processor = UncommentingProcessor {
+                config: Arc::new(committed_data(config)),
+                smudge,
                 lang_config,
-            }).communicate_stdio()?;
+                verify: git_verify_enabled()?,
+            };
+            let threads = match opts.walk.threads {
+                0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+                n => n,
+            };
+            filter::serve(
+                &processor,
+                threads,
+                &mut std::io::stdin().lock(),
+                &mut std::io::BufWriter::new(std::io::stdout().lock()),
+            )?;
+        }
+        Cmd::Apply {
+            paths,
+            files_from,
+            stdin,
+            eval,
+            write,
+            watch,
+            format,
+        } => {
+            let requested = eval.features.clone();
+            let config = eval.data(&paths, &files_from, &opts.walk)?;
+            if stdin.filter(&config)? {
+                return Ok(EXIT_CLEAN);
+            }
+            if watch {
+                files_from.watch(paths, config, write.walk_options(), &opts.walk, &path_display)?;
+                return Ok(EXIT_CLEAN);
+            }
+            let report = Report::for_write(&write);
+            let options = format.walk_options(write.walk_options());
+            let summary = files_from.process(paths, config, options, &opts.walk)?;
+            warn_unreferenced(&summary, &requested, &opts.walk);
+            return Ok(summary_exit_code(summary, report, format, &path_display));
         }
-        Opts::Apply { paths, features } => {
+        Cmd::Check {
+            paths,
+            files_from,
+            eval,
+            reset,
+            format,
+        } => {
+            let requested = eval.features.clone();
+            // Everything is active in reset state
+            let exclusive = match reset {
+                true => Vec::new(),
+                false => paths_config(&paths)?.exclusive_features,
+            };
             let config = Data {
-                features: features.into_iter().collect(),
-                reset: false,
+                reset,
+                ..eval.data(&paths, &files_from, &opts.walk)?
+            };
+            let options = format.walk_options(WalkOptions {
+                check: true,
+                regions: !exclusive.is_empty(),
+                ..Default::default()
+            });
+            let summary = files_from.process(paths, config, options, &opts.walk)?;
+            if !reset {
+                warn_unreferenced(&summary, &requested, &opts.walk);
+            }
+            let conflicts = print_conflicts(&summary, &exclusive, &path_display);
+            let code = summary_exit_code(summary, Report::Lines, format, &path_display);
+            return Ok(if code == EXIT_CLEAN && conflicts {
+                EXIT_CHANGES_PENDING
+            } else {
+                code
+            });
+        }
+        Cmd::Status { paths, files_from } => {
+            let summary = files_from.regions(paths, &opts.walk)?;
+            print_status(&summary, &path_display);
+            return Ok(summary_exit_code(summary, Report::Nothing, Format::Text, &path_display));
+        }
+        Cmd::ListFeatures { paths, files_from } => {
+            let summary = files_from.regions(paths, &opts.walk)?;
+            print_features(&summary, &path_display);
+            return Ok(summary_exit_code(summary, Report::Nothing, Format::Text, &path_display));
+        }
+        Cmd::Fmt {
+            paths,
+            files_from,
+            sort,
+            write,
+        } => {
+            let report = Report::for_write(&write);
+            let options = WalkOptions {
+                format_tags: true,
+                sort_operands: sort,
+                ..write.walk_options()
             };
-            walkdir_parallel(paths, config, lang_config)
+            let summary = files_from.process(paths, Data::default(), options, &opts.walk)?;
+            return Ok(summary_exit_code(summary, report, Format::Text, &path_display));
+        }
+        Cmd::Stats {
+            paths,
+            files_from,
+            top,
+        } => {
+            let summary = files_from.regions(paths, &opts.walk)?;
+            print_stats(&summary, top, &path_display);
+            return Ok(summary_exit_code(summary, Report::Nothing, Format::Text, &path_display));
         }
-        Opts::Reset { paths } => {
+        Cmd::Verify { paths, files_from } => {
             let config = Data {
-                features: HashSet::new(),
                 reset: true,
+                ..Default::default()
+            };
+            let options = WalkOptions {
+                check: true,
+                verify: true,
+                ..Default::default()
             };
-            walkdir_parallel(paths, config, lang_config)
+            let summary = files_from.process(paths, config, options, &opts.walk)?;
+            for (path, e) in &summary.errors {
+                match e.line() {
+                    Some(line) => println!("{}:{}: {}", path_display.show(path).display(), line, e),
+                    None => println!("{}: {}", path_display.show(path).display(), e),
+                }
+            }
+            return Ok(if summary.errors.is_empty() {
+                EXIT_CLEAN
+            } else {
+                EXIT_ERROR
+            });
         }
+        Cmd::Toggle {
+            paths,
+            files_from,
+            eval,
+        } => {
+            let files = files_from.read(&paths)?;
+            let config = eval.data(&paths, &files_from, &opts.walk)?;
+            // Progress would be drawn over the list
+            let flags = WalkFlags {
+                progress: false,
+                ..opts.walk
+            };
+            let inspect = WalkOptions {
+                check: true,
+                regions: true,
+                ..Default::default()
+            };
+            let reset = Data {
+                reset: true,
+                ..Default::default()
+            };
+            let found = walk(files.clone(), paths.clone(), reset, inspect, &flags)?;
+            let mut features = toggle_features(&found, &config.features);
+            summary_exit_code(found, Report::Nothing, Format::Text, &path_display);
+
+            let with_selected = |features: &[toggle::Feature]| Data {
+                features: features
+                    .iter()
+                    .filter(|f| f.enabled)
+                    .map(|f| f.name.clone())
+                    .collect(),
+                ..config.clone()
+            };
+            let preview = |features: &[toggle::Feature]| {
+                let check = WalkOptions {
+                    check: true,
+                    ..Default::default()
+                };
+                let summary = walk(files.clone(), paths.clone(), with_selected(features), check, &flags)?;
+                Ok(summary.changed_lines.values().map(Vec::len).sum())
+            };
+            if !toggle::run(&mut features, preview)? {
+                return Ok(EXIT_CLEAN);
+            }
+            let config = with_selected(&features);
+            let summary = walk(files, paths, config, WalkOptions::default(), &flags)?;
+            return Ok(report_changed(summary, &path_display));
+        }
+        Cmd::Explain { path, eval } => {
+            let single = FilesFromOpts::default();
+            let config = eval.data(std::slice::from_ref(&path), &single, &opts.walk)?;
+            let lang_config = project_config(&path)?.lang_config();
+            let (text, desc) = read_source(&path, &lang_config)?;
+            print_explanation(&explain(&text, &config, desc)?, &desc.cfg_keyword);
+        }
+        Cmd::Spans { path, eval } => {
+            let single = FilesFromOpts::default();
+            let config = eval.data(std::slice::from_ref(&path), &single, &opts.walk)?;
+            let lang_config = project_config(&path)?.lang_config();
+            let (text, desc) = read_source(&path, &lang_config)?;
+            println!("{}", spans_report(&spans(&text, &config, desc)?));
+        }
+        Cmd::Lsp { eval } => {
+            let paths = [PathBuf::from(".")];
+            let config = eval.data(&paths, &FilesFromOpts::default(), &opts.walk)?;
+            let server = lsp::Server::new(config);
+            let shutdown = server.serve(&mut std::io::stdin().lock(), &mut std::io::stdout())?;
+            if !shutdown {
+                return Ok(EXIT_ERROR);
+            }
+        }
+        Cmd::Reset {
+            paths,
+            files_from,
+            stdin,
+            strict,
+            write,
+            format,
+        } => {
+            let config = Data {
+                reset: true,
+                strict,
+                ..Default::default()
+            };
+            if stdin.filter(&config)? {
+                return Ok(EXIT_CLEAN);
+            }
+            let report = Report::for_write(&write);
+            let options = format.walk_options(write.walk_options());
+            let summary = files_from.process(paths, config, options, &opts.walk)?;
+            return Ok(summary_exit_code(summary, report, format, &path_display));
+        }
+    }
+    Ok(EXIT_CLEAN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verifier() -> UncommentingProcessor {
+        UncommentingProcessor {
+            config: Arc::new(Data {
+                reset: true,
+                ..Default::default()
+            }),
+            smudge: None,
+            lang_config: LangRegistry::with_defaults(),
+            verify: true,
+        }
+    }
+
+    #[test]
+    fn verify_accepts_committed_state() {
+        let input = b"//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n".to_vec();
+        let output = verifier().process("a.rs", ProcessingType::Clean, input.clone());
+        assert_eq!(output.unwrap(), input);
+    }
+
+    #[test]
+    fn verify_rejects_changed_line() {
+        let input = b"//[cfg(feature = \"a\")]\n//# a();\n//[cfg(end)]\n".to_vec();
+        let err = verifier().process("a.rs", ProcessingType::Clean, input).unwrap_err();
+        assert_eq!(err.to_string(), "a.rs:2 is not in committed state");
+    }
+
+    #[test]
+    fn verify_rejects_trailing_extra_line() {
+        assert_eq!(first_difference("a\nb\n", "a\nb\n"), None);
+        assert_eq!(first_difference("a\nb\n", "a\nc\n"), Some(1));
+        assert_eq!(first_difference("a\nb\nc\n", "a\nb\n"), Some(2));
+        assert_eq!(first_difference("a\n", "a\nb\n"), Some(1));
+    }
+
+    fn pending(path: &str) -> WalkSummary {
+        let path = PathBuf::from(path);
+        WalkSummary {
+            changed: vec![path.clone()],
+            changed_lines: std::iter::once((path, vec![2, 3])).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn failed(path: &str) -> (PathBuf, CfgCommentError) {
+        let e = CfgCommentError::InvalidTag {
+            line: 4,
+            column: 7,
+            expected: "\")\"".to_owned(),
+            text: "cfg(feature = \"a\"".to_owned(),
+        };
+        (PathBuf::from(path), e)
+    }
+
+    fn root() -> PathDisplay {
+        PathDisplay {
+            root: PathBuf::from("/repo"),
+        }
+    }
+
+    #[test]
+    fn exit_code_is_clean_without_changes() {
+        let code = summary_exit_code(WalkSummary::default(), Report::Files, Format::Json, &root());
+        assert_eq!(code, EXIT_CLEAN);
+    }
+
+    #[test]
+    fn exit_code_reports_pending_changes() {
+        for report in [Report::Files, Report::Lines] {
+            let code = summary_exit_code(pending("/repo/a.rs"), report, Format::Json, &root());
+            assert_eq!(code, EXIT_CHANGES_PENDING);
+        }
+        // Written and previewed changes aren't pending
+        for report in [Report::Written, Report::Nothing, Report::DryRun] {
+            let code = summary_exit_code(pending("/repo/a.rs"), report, Format::Json, &root());
+            assert_eq!(code, EXIT_CLEAN);
+        }
+    }
+
+    #[test]
+    fn exit_code_reports_errors_first() {
+        let mut summary = pending("/repo/a.rs");
+        summary.errors.push(failed("/repo/b.rs"));
+        let code = summary_exit_code(summary, Report::Files, Format::Json, &root());
+        assert_eq!(code, EXIT_ERROR);
+    }
+
+    #[test]
+    fn sarif_locates_results() {
+        let mut summary = pending("/repo/src/a.rs");
+        summary.errors.push(failed("/repo/src/b.rs"));
+        let report = sarif_report(&summary, &root());
+        assert_eq!(report["version"], "2.1.0");
+        let run = &report["runs"][0];
+        let rules: Vec<&str> = run["tool"]["driver"]["rules"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(rules, ["out-of-state", "processing-error"]);
+
+        let results = run["results"].as_array().unwrap();
+        let located: Vec<(&str, &str, u64)> = results
+            .iter()
+            .map(|r| {
+                let location = &r["locations"][0]["physicalLocation"];
+                (
+                    r["ruleId"].as_str().unwrap(),
+                    location["artifactLocation"]["uri"].as_str().unwrap(),
+                    location["region"]["startLine"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            located,
+            [
+                ("out-of-state", "src/a.rs", 2),
+                ("out-of-state", "src/a.rs", 3),
+                ("processing-error", "src/b.rs", 4),
+            ]
+        );
+        let region = &results[2]["locations"][0]["physicalLocation"]["region"];
+        assert_eq!(region["startColumn"], 7);
+        assert!(results[0]["locations"][0]["physicalLocation"]["region"]
+            .get("startColumn")
+            .is_none());
     }
-    Ok(())
 }
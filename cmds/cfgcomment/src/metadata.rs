@@ -0,0 +1,100 @@
+use anyhow::{bail, Context};
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Feature flags passed to cargo as is
+pub struct CargoFeatures<'a> {
+    pub features: &'a [String],
+    pub all: bool,
+    pub default: bool,
+}
+
+fn list(value: &serde_json::Value) -> &[serde_json::Value] {
+    value.as_array().map_or(&[], |v| v.as_slice())
+}
+
+/// Output of `cargo metadata` with extra `args`
+fn cargo_metadata(args: &[&OsStr]) -> anyhow::Result<serde_json::Value> {
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let output = Command::new(cargo)
+        .args(["metadata", "--format-version", "1"])
+        .args(args)
+        .output()
+        .context("while running cargo metadata")?;
+    if !output.status.success() {
+        bail!(
+            "cargo metadata failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    serde_json::from_slice(&output.stdout).context("while parsing cargo metadata")
+}
+
+/// `src` directories of workspace members of current directory, or directories of their
+/// manifests if there is no `src`
+pub fn workspace_sources() -> anyhow::Result<Vec<PathBuf>> {
+    let metadata = cargo_metadata(&["--no-deps".as_ref()])?;
+    let mut sources: Vec<PathBuf> = list(&metadata["packages"])
+        .iter()
+        .filter_map(|p| p["manifest_path"].as_str())
+        .filter_map(|manifest| Path::new(manifest).parent())
+        .map(|dir| match dir.join("src") {
+            src if src.is_dir() => src,
+            _ => dir.to_owned(),
+        })
+        .collect();
+    sources.sort();
+    sources.dedup();
+    Ok(sources)
+}
+
+/// Features cargo enables for package of `manifest`, or for `package` of its workspace,
+/// as resolved by `cargo metadata`, with features unified across the workspace
+pub fn package_features(
+    manifest: &Path,
+    package: Option<&str>,
+    flags: CargoFeatures<'_>,
+) -> anyhow::Result<HashSet<String>> {
+    let features = flags.features.join(",");
+    let mut args: Vec<&OsStr> = vec!["--manifest-path".as_ref(), manifest.as_os_str()];
+    if !flags.features.is_empty() {
+        args.extend::<[&OsStr; 2]>(["--features".as_ref(), features.as_ref()]);
+    }
+    if flags.all {
+        args.push("--all-features".as_ref());
+    }
+    if !flags.default {
+        args.push("--no-default-features".as_ref());
+    }
+    let metadata = cargo_metadata(&args)?;
+
+    let members = list(&metadata["workspace_members"]);
+    let id = match package {
+        Some(name) => list(&metadata["packages"])
+            .iter()
+            .filter(|p| members.contains(&p["id"]))
+            .find(|p| p["name"] == name)
+            .map(|p| &p["id"])
+            .with_context(|| format!("package {:?} is not a workspace member", name))?,
+        None => match &metadata["resolve"]["root"] {
+            serde_json::Value::Null => bail!(
+                "{} is a virtual manifest, select package with --package",
+                manifest.display()
+            ),
+            root => root,
+        },
+    };
+    let node = list(&metadata["resolve"]["nodes"])
+        .iter()
+        .find(|n| n["id"] == *id)
+        .context("package is missing from cargo metadata resolve graph")?;
+    Ok(list(&node["features"])
+        .iter()
+        .filter_map(|f| f.as_str())
+        .map(str::to_owned)
+        .collect())
+}
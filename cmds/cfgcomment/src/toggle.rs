@@ -0,0 +1,115 @@
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, ClearType},
+};
+use std::io::Write;
+
+/// Feature found in cfg tags, with blocks and lines guarded by it
+pub struct Feature {
+    pub name: String,
+    pub blocks: usize,
+    pub lines: usize,
+    pub enabled: bool,
+}
+
+/// Restores terminal even if drawing fails
+struct RawScreen;
+impl RawScreen {
+    fn enter() -> std::io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        queue!(std::io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(Self)
+    }
+}
+impl Drop for RawScreen {
+    fn drop(&mut self) {
+        let _ = queue!(std::io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = std::io::stdout().flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn draw(
+    features: &[Feature],
+    selected: usize,
+    top: usize,
+    rows: usize,
+    pending: &anyhow::Result<usize>,
+) -> std::io::Result<()> {
+    let mut out = std::io::stdout();
+    queue!(
+        out,
+        terminal::Clear(ClearType::All),
+        cursor::MoveTo(0, 0),
+        Print("space: flip, enter: apply, q: quit"),
+    )?;
+    let width = features.iter().map(|f| f.name.len()).max().unwrap_or(0);
+    for (row, (i, feature)) in features.iter().enumerate().skip(top).take(rows).enumerate() {
+        queue!(out, cursor::MoveTo(0, row as u16 + 2))?;
+        if i == selected {
+            queue!(out, SetAttribute(Attribute::Reverse))?;
+        }
+        queue!(
+            out,
+            Print(format!(
+                "[{}] {:width$}  {:>4} blocks  {:>6} lines",
+                if feature.enabled { 'x' } else { ' ' },
+                feature.name,
+                feature.blocks,
+                feature.lines,
+                width = width,
+            )),
+            SetAttribute(Attribute::Reset),
+        )?;
+    }
+    let status = match pending {
+        Ok(1) => "1 line would change".to_owned(),
+        Ok(lines) => format!("{} lines would change", lines),
+        Err(e) => format!("can't preview: {}", e),
+    };
+    queue!(out, cursor::MoveTo(0, rows as u16 + 3), Print(status))?;
+    out.flush()
+}
+
+/// Lets user flip features, `preview` gives number of lines which would change with
+/// current selection. Returns `true` if selection is confirmed
+pub fn run(
+    features: &mut [Feature],
+    mut preview: impl FnMut(&[Feature]) -> anyhow::Result<usize>,
+) -> anyhow::Result<bool> {
+    let _screen = RawScreen::enter()?;
+    let mut selected = 0;
+    let mut top = 0;
+    let mut pending = preview(features);
+    loop {
+        // Header, blank line, list, blank line and status
+        let rows = (terminal::size()?.1 as usize).saturating_sub(4).max(1);
+        if selected < top {
+            top = selected;
+        } else if selected >= top + rows {
+            top = selected + 1 - rows;
+        }
+        draw(features, selected, top, rows, &pending)?;
+
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => selected = selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') if selected + 1 < features.len() => selected += 1,
+            KeyCode::Char(' ') if !features.is_empty() => {
+                features[selected].enabled = !features[selected].enabled;
+                pending = preview(features);
+            }
+            KeyCode::Enter => return Ok(true),
+            KeyCode::Esc | KeyCode::Char('q') => return Ok(false),
+            // Raw mode doesn't turn Ctrl-C into a signal
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(false),
+            _ => {}
+        }
+    }
+}
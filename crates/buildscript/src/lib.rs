@@ -1,20 +1,397 @@
-use std::{collections::HashSet, path::PathBuf};
+//! Processes sources of crate from its build script, with features and target cargo builds
+//! for. Either rewrites `src` in place with [`preprocess`], or leaves it untouched and
+//! writes processed copy to `OUT_DIR` with [`preprocess_out_dir`]. Other layouts are
+//! configured with [`Preprocess`]
 
-use cfgcomment_core::{walkdir_parallel, Data, LangDesc};
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
-pub fn preprocess() {
+mod sync;
+
+use cfgcomment_core::{
+    walkdir_parallel, CfgCommentError, Config, Data, Manifest, Target, TargetKey, Version,
+    WalkOptions, WalkSummary, CONFIG_FILE_NAMES,
+};
+pub use cfgcomment_core::{LangDesc, LangRegistry};
+
+/// Target cargo is building for, from `CARGO_CFG_TARGET_*`
+fn cargo_target() -> Target {
+    let var = |name: &str| std::env::var(format!("CARGO_CFG_TARGET_{}", name)).ok();
+    Target {
+        os: var("OS"),
+        arch: var("ARCH"),
+        family: var("FAMILY")
+            .map(|f| f.split(',').map(|s| s.to_owned()).collect())
+            .unwrap_or_default(),
+        env: var("ENV"),
+        vendor: var("VENDOR"),
+        endian: var("ENDIAN"),
+        pointer_width: var("POINTER_WIDTH"),
+    }
+}
+
+/// Other cfgs cargo sets, i.e `unix`, `debug_assertions` or `target_feature = "sse2"`,
+/// from `CARGO_CFG_*`. Options with multiple values are set once per value
+fn cargo_cfg() -> HashSet<(String, Option<String>)> {
+    std::env::vars()
+        .filter_map(|(n, v)| Some((n.strip_prefix("CARGO_CFG_")?.to_ascii_lowercase(), v)))
+        // Features are already set from `CARGO_FEATURE_*`, and target keys from `cargo_target`
+        .filter(|(name, _)| {
+            let target_key = name.strip_prefix("target_").and_then(TargetKey::from_name);
+            name != "feature" && target_key.is_none()
+        })
+        .flat_map(|(name, value)| {
+            let values: Vec<Option<String>> = match value.is_empty() {
+                true => vec![None],
+                false => value.split(',').map(|v| Some(v.to_owned())).collect(),
+            };
+            values.into_iter().map(move |v| (name.clone(), v))
+        })
+        .collect()
+}
+
+/// Version of rustc used by cargo, parsed from `rustc 1.70.0 (90c541806 2023-05-31)`
+fn rustc_version() -> Option<Version> {
+    let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = Command::new(rustc).arg("--version").output().ok()?;
+    let output = String::from_utf8(output.stdout).ok()?;
+    output.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Variable cargo sets for enabled `feature`
+fn feature_var(feature: &str) -> String {
+    format!(
+        "CARGO_FEATURE_{}",
+        feature.to_ascii_uppercase().replace('-', "_")
+    )
+}
+
+/// Makes cargo rerun build script when any feature or variable of `env` is flipped, or any
+/// of processed files or config files is changed. Directories are listed too, so added
+/// files are noticed
+fn rerun_directives(paths: &[PathBuf], files: &[PathBuf], env: &BTreeSet<String>) {
+    let mut vars: HashSet<String> = std::env::vars()
+        .map(|(n, _)| n)
+        .filter(|n| n.starts_with("CARGO_FEATURE_"))
+        .chain(env.iter().cloned())
+        .collect();
+    // Features which are disabled now have no variable to look at
+    if let Ok(Some(manifest)) = Manifest::discover(Path::new("Cargo.toml")) {
+        println!("cargo:rerun-if-changed={}", manifest.path().display());
+        vars.extend(manifest.features().map(feature_var));
+    }
+    let mut vars: Vec<String> = vars.into_iter().collect();
+    vars.sort();
+    for var in vars {
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
+    for name in CONFIG_FILE_NAMES {
+        if Path::new(name).is_file() {
+            println!("cargo:rerun-if-changed={}", name);
+        }
+    }
+    for path in paths.iter().chain(files) {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+}
+
+/// Includes file processed by [`preprocess_out_dir`], path is relative to `src`, i.e
+/// `cfgcomment_buildscript::include_src!("backend.rs");`. Modules declared by included
+/// file are looked up relative to including one, so they need `#[path]`
+#[macro_export]
+macro_rules! include_src {
+    ($path:literal) => {
+        include!(concat!(env!("OUT_DIR"), "/cfgcomment/src/", $path));
+    };
+}
+
+/// Directory of `OUT_DIR` holding processed copies, mirroring layout of crate. Caches of
+/// both modes are stored beside it
+fn out_dir() -> PathBuf {
+    let out = std::env::var_os("OUT_DIR").expect("OUT_DIR is only set for build scripts");
+    PathBuf::from(out).join("cfgcomment")
+}
+
+/// Variables `env(...)` predicates referred to on previous run, remembered beside caches
+fn env_list() -> PathBuf {
+    out_dir().with_extension("env")
+}
+
+fn cargo_data() -> Data {
     let features: HashSet<String> = std::env::vars()
         .filter_map(|(n, _)| n.strip_prefix("CARGO_FEATURE_").map(|s| s.to_owned()))
         .map(|s| s.to_ascii_lowercase().replace("_", "-"))
         .collect();
-    let paths = vec![PathBuf::from("src")];
-
-    walkdir_parallel(
-        paths,
-        Data {
-            features,
-            reset: false,
-        },
-        LangDesc::default_list(),
-    )
+    Data {
+        features,
+        target: cargo_target(),
+        cfg: cargo_cfg(),
+        version: rustc_version(),
+        ..Default::default()
+    }
+}
+
+/// Walks with only variables of `env` captured. Files may name other variables, which
+/// weren't known before processing them, then walk is repeated with them captured too
+fn walk_with_env(
+    mut walk: impl FnMut(Data) -> WalkSummary,
+    mut data: Data,
+    env: &mut BTreeSet<String>,
+) -> WalkSummary {
+    loop {
+        data.capture_env_vars(env.iter().map(String::as_str));
+        let summary = walk(data.clone());
+        let known = env.len();
+        env.extend(summary.referenced_env.iter().cloned());
+        if env.len() == known {
+            return summary;
+        }
+    }
+}
+
+fn write_env_list(list: &Path, env: &BTreeSet<String>) -> Result<(), Error> {
+    let mut text = String::new();
+    for name in env {
+        text.push_str(name);
+        text.push('\n');
+    }
+    if let Some(dir) = list.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| Error::single(list, e))?;
+    }
+    std::fs::write(list, text).map_err(|e| Error::single(list, e))
+}
+
+/// Problems found by [`Preprocess::run`], every one of them is already reported to cargo
+/// as warning
+#[derive(Debug)]
+pub struct Error {
+    /// Files of `OUT_DIR` copy are reported by their paths relative to crate
+    pub errors: Vec<(PathBuf, CfgCommentError)>,
+}
+
+impl Error {
+    /// Reports `errors` to cargo, unless there are none
+    fn check(errors: Vec<(PathBuf, CfgCommentError)>) -> Result<(), Self> {
+        if errors.is_empty() {
+            return Ok(());
+        }
+        for (path, e) in &errors {
+            println!("cargo:warning={}", located(path, e));
+        }
+        Err(Self { errors })
+    }
+
+    fn single(path: &Path, e: impl Into<CfgCommentError>) -> Self {
+        match Self::check(vec![(path.to_owned(), e.into())]) {
+            Err(e) => e,
+            Ok(()) => unreachable!("error is listed"),
+        }
+    }
+}
+
+/// `path:line: error`, line is omitted if error isn't about specific one
+fn located(path: &Path, e: &CfgCommentError) -> String {
+    match e.line() {
+        Some(line) => format!("{}:{}: {}", path.display(), line, e),
+        None => format!("{}: {}", path.display(), e),
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (path, e) = &self.errors[0];
+        write!(f, "{}", located(path, e))?;
+        if self.errors.len() > 1 {
+            write!(f, " (and {} more)", self.errors.len() - 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Processing of sources, configured by builder methods
+pub struct Preprocess {
+    paths: Vec<PathBuf>,
+    extra_features: HashSet<String>,
+    env_vars: BTreeSet<String>,
+    languages: Option<LangRegistry>,
+    out_dir: bool,
+}
+
+impl Default for Preprocess {
+    fn default() -> Self {
+        Self {
+            paths: vec![PathBuf::from("src")],
+            extra_features: HashSet::new(),
+            env_vars: BTreeSet::new(),
+            languages: None,
+            out_dir: false,
+        }
+    }
+}
+
+impl Preprocess {
+    /// Processes `src` in place, in languages of discovered config
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Directories or files to process instead of `src`, relative to crate root, i.e
+    /// `["src", "shaders"]`
+    pub fn paths<P: Into<PathBuf>>(mut self, paths: impl IntoIterator<Item = P>) -> Self {
+        self.paths = paths.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Features enabled in addition to ones cargo enables
+    pub fn extra_features<F: Into<String>>(
+        mut self,
+        features: impl IntoIterator<Item = F>,
+    ) -> Self {
+        self.extra_features
+            .extend(features.into_iter().map(Into::into));
+        self
+    }
+
+    /// Environment variables `env(...)` predicates refer to. Only these, and ones found by
+    /// previous runs, are captured, so other variables neither affect processing, nor make
+    /// cargo rerun build script. Undeclared ones are still found, at cost of processing
+    /// files twice on first run
+    pub fn env_vars<V: Into<String>>(mut self, names: impl IntoIterator<Item = V>) -> Self {
+        self.env_vars.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Languages to use instead of defaults extended by config discovered from first path
+    ///
+    /// ```
+    /// use cfgcomment_buildscript::{LangDesc, LangRegistry, Preprocess};
+    ///
+    /// let mut languages = LangRegistry::with_defaults();
+    /// languages
+    ///     .register(LangDesc::block("/*", "*/"), &["wgsl"], &[])
+    ///     .unwrap()
+    ///     .register(LangDesc::new("--"), &["lua"], &[])
+    ///     .unwrap();
+    /// let preprocess = Preprocess::new().paths(["src", "shaders"]).languages(languages);
+    /// ```
+    pub fn languages(mut self, languages: LangRegistry) -> Self {
+        self.languages = Some(languages);
+        self
+    }
+
+    /// Leave checkout untouched, processing copy of every path in `OUT_DIR/cfgcomment/<path>`
+    /// instead, see [`include_src!`]
+    pub fn out_dir(mut self, out_dir: bool) -> Self {
+        self.out_dir = out_dir;
+        self
+    }
+
+    /// Processes files, reporting every problem to cargo as warning. Files with problems are
+    /// left untouched, others are processed anyway. Build fails only if returned error is,
+    /// i.e with `preprocess().unwrap()`.
+    ///
+    /// Files which didn't change since last run with the same features, target and variables
+    /// are skipped, see [`cfgcomment_core::WalkOptions::cache`]
+    pub fn run(self) -> Result<(), Error> {
+        let lang_config = match self.languages {
+            Some(v) => v,
+            None => {
+                let start = self.paths.first().map_or(Path::new("."), |p| p);
+                match Config::discover(start) {
+                    Ok(config) => config.unwrap_or_default().lang_config(),
+                    Err(e) => return Err(Error::single(start, e)),
+                }
+            }
+        };
+        let mut data = cargo_data();
+        data.features.extend(self.extra_features);
+        let mut env = self.env_vars;
+        let list = env_list();
+        if let Ok(previous) = std::fs::read_to_string(&list) {
+            env.extend(previous.lines().map(str::to_owned));
+        }
+
+        if !self.out_dir {
+            let options = WalkOptions {
+                cache: Some(out_dir().with_extension("cache")),
+                ..Default::default()
+            };
+            let paths = &self.paths;
+            let walk =
+                |data| walkdir_parallel(paths.clone(), data, lang_config.clone(), options.clone());
+            let summary = walk_with_env(walk, data, &mut env);
+            write_env_list(&list, &env)?;
+            rerun_directives(&self.paths, &summary.files, &env);
+            return Error::check(summary.errors);
+        }
+
+        let out = out_dir();
+        let (sources, copies) =
+            sync::sync(&out, &self.paths).map_err(|(path, e)| Error::single(&path, e))?;
+
+        let options = WalkOptions {
+            // Copies are selected already, and ignore files of `OUT_DIR` parents don't apply
+            no_ignore: true,
+            hidden: true,
+            cache: Some(out.with_extension("cache")),
+            ..Default::default()
+        };
+        let walk =
+            |data| walkdir_parallel(copies.clone(), data, lang_config.clone(), options.clone());
+        let summary = walk_with_env(walk, data, &mut env);
+        write_env_list(&list, &env)?;
+        rerun_directives(&self.paths, &sources, &env);
+        let errors = summary.errors.into_iter().map(|(path, e)| {
+            let path = path.strip_prefix(&out).map_or(path.clone(), Path::to_owned);
+            (path, e)
+        });
+        Error::check(errors.collect())
+    }
+}
+
+/// Processes `src` in place
+pub fn preprocess() -> Result<(), Error> {
+    Preprocess::new().run()
+}
+
+/// Processes copy of `src` in `OUT_DIR/cfgcomment/src`, leaving checkout untouched. Files
+/// are included with [`include_src!`]
+pub fn preprocess_out_dir() -> Result<(), Error> {
+    Preprocess::new().out_dir(true).run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Environment is shared by the whole process, so there is only one test here
+    #[test]
+    fn cargo_cfg_leaves_target_keys_to_target() {
+        std::env::set_var("CARGO_CFG_UNIX", "");
+        std::env::set_var("CARGO_CFG_TARGET_OS", "linux");
+        std::env::set_var("CARGO_CFG_TARGET_FAMILY", "unix,wasm");
+        std::env::set_var("CARGO_CFG_TARGET_FEATURE", "sse,sse2");
+        std::env::set_var("CARGO_CFG_FEATURE", "a");
+
+        let target = cargo_target();
+        assert_eq!(target.os.as_deref(), Some("linux"));
+        assert_eq!(target.family, ["unix", "wasm"]);
+
+        let cfg = cargo_cfg();
+        let has = |name: &str, value: Option<&str>| {
+            cfg.contains(&(name.to_owned(), value.map(str::to_owned)))
+        };
+        assert!(has("unix", None));
+        assert!(has("target_feature", Some("sse")));
+        assert!(has("target_feature", Some("sse2")));
+        assert!(cfg
+            .iter()
+            .all(|(name, _)| !["target_os", "target_family", "feature"].contains(&name.as_str())));
+    }
 }
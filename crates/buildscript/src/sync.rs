@@ -0,0 +1,110 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+
+const HEADER: &str = "cfgcomment-sources v1";
+
+/// Path of copy in `out`, paths outside of crate are put inside of it too
+fn copy_path(out: &Path, path: &Path) -> PathBuf {
+    let relative = path
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)));
+    out.join(relative.collect::<PathBuf>())
+}
+
+/// Every file under `path`, or `path` itself if it is a file
+fn list_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if !std::fs::metadata(path)?.is_dir() {
+        return Ok(vec![path.to_owned()]);
+    }
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        files.extend(list_files(&entry?.path())?);
+    }
+    Ok(files)
+}
+
+fn content_hash(content: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Hashes of sources as of their last copy, missing or unreadable file gives none
+fn read_hashes(path: &Path) -> HashMap<PathBuf, u64> {
+    let text = std::fs::read_to_string(path).unwrap_or_default();
+    let mut lines = text.lines();
+    if lines.next() != Some(HEADER) {
+        return HashMap::new();
+    }
+    lines
+        .filter_map(|line| {
+            let (hash, path) = line.split_once(' ')?;
+            Some((PathBuf::from(path), u64::from_str_radix(hash, 16).ok()?))
+        })
+        .collect()
+}
+
+fn write_hashes(path: &Path, hashes: &HashMap<PathBuf, u64>) -> io::Result<()> {
+    let mut out = io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(out, "{}", HEADER)?;
+    let mut hashes: Vec<_> = hashes.iter().collect();
+    hashes.sort();
+    for (path, hash) in hashes {
+        // Paths which can't be stored as a single line are just copied every time
+        match path.to_str() {
+            Some(p) if !p.contains('\n') => writeln!(out, "{:016x} {}", hash, p)?,
+            _ => {}
+        }
+    }
+    out.flush()
+}
+
+/// Mirrors `paths` into `out`, only files which changed since last run are copied, so
+/// [`cfgcomment_core::WalkOptions::cache`] can skip the rest. Copies of removed files are
+/// removed too. Returns mirrored files and roots of copies
+pub(crate) fn sync(
+    out: &Path,
+    paths: &[PathBuf],
+) -> Result<(Vec<PathBuf>, Vec<PathBuf>), (PathBuf, io::Error)> {
+    let hashes_path = out.with_extension("sources");
+    let previous = read_hashes(&hashes_path);
+    let mut hashes = HashMap::new();
+    let mut sources = Vec::new();
+    let mut copies = HashSet::new();
+    let mut roots = Vec::new();
+    for path in paths {
+        let root = copy_path(out, path);
+        for source in list_files(path).map_err(|e| (path.clone(), e))? {
+            let copy = match source.strip_prefix(path) {
+                Ok(rel) if !rel.as_os_str().is_empty() => root.join(rel),
+                _ => root.clone(),
+            };
+            let content = std::fs::read(&source).map_err(|e| (source.clone(), e))?;
+            let hash = content_hash(&content);
+            if previous.get(&source) != Some(&hash) || !copy.is_file() {
+                let written = copy
+                    .parent()
+                    .map_or(Ok(()), std::fs::create_dir_all)
+                    .and_then(|_| std::fs::write(&copy, &content));
+                written.map_err(|e| (copy.clone(), e))?;
+            }
+            hashes.insert(source.clone(), hash);
+            copies.insert(copy);
+            sources.push(source);
+        }
+        roots.push(root);
+    }
+    if out.is_dir() {
+        for copy in list_files(out).map_err(|e| (out.to_owned(), e))? {
+            if !copies.contains(&copy) {
+                std::fs::remove_file(&copy).map_err(|e| (copy.clone(), e))?;
+            }
+        }
+    }
+    write_hashes(&hashes_path, &hashes).map_err(|e| (hashes_path, e))?;
+    Ok((sources, roots))
+}
@@ -0,0 +1,59 @@
+use std::{fs::File, path::Path, time::SystemTime};
+
+use cfgcomment_buildscript::Preprocess;
+
+fn mtime(path: &Path) -> SystemTime {
+    std::fs::metadata(path).unwrap().modified().unwrap()
+}
+
+/// Rewrites file keeping its size and mtime, so only processing it again notices the change
+fn tamper(path: &Path, content: &str) {
+    let time = mtime(path);
+    std::fs::write(path, content).unwrap();
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(time)
+        .unwrap();
+}
+
+// Environment is shared by the whole process, so there is only one test here
+#[test]
+fn only_referenced_env_invalidates_cache() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    let file = src.join("lib.rs");
+    std::fs::write(
+        &file,
+        "//[cfg(env(\"CFGCOMMENT_TEST_A\"))]\na();\n//[cfg(end)]\n",
+    )
+    .unwrap();
+    let out = dir.path().join("out");
+    std::fs::create_dir(&out).unwrap();
+    std::env::set_var("OUT_DIR", &out);
+    std::env::remove_var("CFGCOMMENT_TEST_A");
+    std::env::set_var("CFGCOMMENT_TEST_B", "1");
+    let run = || Preprocess::new().paths([&src]).run().unwrap();
+    run();
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "//[cfg(env(\"CFGCOMMENT_TEST_A\"))]\n//# a();\n//[cfg(end)]\n"
+    );
+    let env = std::fs::read_to_string(out.join("cfgcomment.env")).unwrap();
+    assert_eq!(env, "CFGCOMMENT_TEST_A\n");
+
+    let tampered = "//[cfg(env(\"CFGCOMMENT_TEST_A\"))]\n//# b();\n//[cfg(end)]\n";
+    tamper(&file, tampered);
+    std::env::set_var("CFGCOMMENT_TEST_B", "2");
+    run();
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), tampered);
+
+    std::env::set_var("CFGCOMMENT_TEST_A", "1");
+    run();
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "//[cfg(env(\"CFGCOMMENT_TEST_A\"))]\nb();\n//[cfg(end)]\n"
+    );
+}
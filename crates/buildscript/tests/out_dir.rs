@@ -0,0 +1,54 @@
+use std::path::Path;
+
+use cfgcomment_buildscript::Preprocess;
+
+const LIB: &str = "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n";
+
+fn read(path: impl AsRef<Path>) -> String {
+    std::fs::read_to_string(path).unwrap()
+}
+
+// Environment and working directory are shared by the whole process, so there is only one
+// test here
+#[test]
+fn copies_are_processed() {
+    let dir = tempfile::tempdir().unwrap();
+    std::env::set_current_dir(dir.path()).unwrap();
+    std::fs::create_dir_all("src/nested").unwrap();
+    std::fs::write("src/lib.rs", LIB).unwrap();
+    std::fs::write("src/nested/old.rs", LIB).unwrap();
+    std::fs::create_dir("out").unwrap();
+    std::env::set_var("OUT_DIR", dir.path().join("out"));
+    std::env::remove_var("CARGO_FEATURE_A");
+    let run = || Preprocess::new().out_dir(true).run();
+    let copies = Path::new("out/cfgcomment/src");
+
+    run().unwrap();
+    let processed = "//[cfg(feature = \"a\")]\n//# a();\n//[cfg(end)]\n";
+    assert_eq!(read(copies.join("lib.rs")), processed);
+    assert_eq!(read(copies.join("nested/old.rs")), processed);
+    // Checkout stays untouched
+    assert_eq!(read("src/lib.rs"), LIB);
+    assert_eq!(read("src/nested/old.rs"), LIB);
+
+    // Copies of removed sources are removed
+    std::fs::remove_file("src/nested/old.rs").unwrap();
+    run().unwrap();
+    assert!(!copies.join("nested/old.rs").exists());
+    assert_eq!(read(copies.join("lib.rs")), processed);
+
+    // Changed sources are copied again
+    std::fs::write("src/lib.rs", "b();\n").unwrap();
+    run().unwrap();
+    assert_eq!(read(copies.join("lib.rs")), "b();\n");
+
+    // Errors are reported by paths in crate, not in `OUT_DIR`
+    let bad = "//[cfg(feature = \"a\")]\nc();\n";
+    std::fs::write("src/bad.rs", bad).unwrap();
+    let e = run().unwrap_err();
+    assert_eq!(e.errors.len(), 1);
+    assert_eq!(e.errors[0].0, Path::new("src/bad.rs"));
+    assert!(e.to_string().starts_with("src/bad.rs:"), "{}", e);
+    assert_eq!(read("src/bad.rs"), bad);
+    assert_eq!(read(copies.join("bad.rs")), bad);
+}
@@ -0,0 +1,53 @@
+use cfgcomment_buildscript::Preprocess;
+
+// Environment is shared by the whole process, so there is only one test here
+#[test]
+fn cargo_cfg_is_applied() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    let file = src.join("lib.rs");
+    std::fs::write(
+        &file,
+        "\\
+//[cfg(unix)]
+//# a();
+//[cfg(end)]
+//[cfg(windows)]
+b();
+//[cfg(end)]
+//[cfg(target_os = \"linux\")]
+//# c();
+//[cfg(end)]
+//[cfg(target_os = \"macos\")]
+d();
+//[cfg(end)]
+",
+    )
+    .unwrap();
+    let out = dir.path().join("out");
+    std::fs::create_dir(&out).unwrap();
+    std::env::set_var("OUT_DIR", &out);
+    std::env::set_var("CARGO_CFG_UNIX", "");
+    std::env::set_var("CARGO_CFG_TARGET_OS", "linux");
+    std::env::remove_var("CARGO_CFG_WINDOWS");
+
+    Preprocess::new().paths([&src]).run().unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "\\
+//[cfg(unix)]
+a();
+//[cfg(end)]
+//[cfg(windows)]
+//# b();
+//[cfg(end)]
+//[cfg(target_os = \"linux\")]
+c();
+//[cfg(end)]
+//[cfg(target_os = \"macos\")]
+//# d();
+//[cfg(end)]
+"
+    );
+}
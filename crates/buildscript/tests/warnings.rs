@@ -0,0 +1,55 @@
+use std::{path::Path, process::Command};
+
+use cfgcomment_buildscript::Preprocess;
+
+/// Set for the copy of this test run to capture output of build script
+const CHILD: &str = "CFGCOMMENT_TEST_CHILD";
+
+const BAD: &str = "a();\n//[cfg(feature = )]\nb();\n//[cfg(end)]\n";
+
+#[test]
+fn errors_are_reported_as_warnings() {
+    if let Some(dir) = std::env::var_os(CHILD) {
+        let dir = Path::new(&dir);
+        std::env::set_var("OUT_DIR", dir.join("out"));
+        let e = Preprocess::new()
+            .paths([dir.join("src")])
+            .run()
+            .unwrap_err();
+        assert_eq!(e.errors.len(), 1);
+        assert_eq!(e.errors[0].0, dir.join("src/bad.rs"));
+        return;
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    std::fs::create_dir(dir.path().join("out")).unwrap();
+    std::fs::write(src.join("bad.rs"), BAD).unwrap();
+    std::fs::write(
+        src.join("good.rs"),
+        "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n",
+    )
+    .unwrap();
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "errors_are_reported_as_warnings", "--nocapture"])
+        .env(CHILD, dir.path())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{}", stdout);
+
+    let warnings: Vec<&str> = stdout
+        .lines()
+        .filter_map(|l| l.strip_prefix("cargo:warning="))
+        .collect();
+    assert_eq!(warnings.len(), 1, "{}", stdout);
+    let prefix = format!("{}:2: ", src.join("bad.rs").display());
+    assert!(warnings[0].starts_with(&prefix), "{}", warnings[0]);
+    // File with problem is left untouched, others are processed anyway
+    assert_eq!(std::fs::read_to_string(src.join("bad.rs")).unwrap(), BAD);
+    assert_eq!(
+        std::fs::read_to_string(src.join("good.rs")).unwrap(),
+        "//[cfg(feature = \"a\")]\n//# a();\n//[cfg(end)]\n"
+    );
+}
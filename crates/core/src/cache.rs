@@ -0,0 +1,219 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs::Metadata,
+    hash::{Hash, Hasher},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use crate::{Data, LangRegistry};
+
+const HEADER: &str = "cfgcomment-cache v2";
+
+/// Size and modification time of file, as left by last run
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    len: u64,
+    mtime: u128,
+}
+impl Stamp {
+    fn of(meta: &Metadata) -> Option<Self> {
+        let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            len: meta.len(),
+            mtime: mtime.as_nanos(),
+        })
+    }
+}
+
+/// Files known to be already processed with the same features and language config, so
+/// they may be skipped without reading. Whole cache is dropped if any evaluation input
+/// differs from the run which wrote it. Of environment, only variables named by `env(...)`
+/// predicates of cached files count
+pub(crate) struct Cache {
+    path: PathBuf,
+    fingerprint: u64,
+    /// Runs which don't write files (check, diff) only consult the cache
+    read_only: bool,
+    env: HashMap<String, String>,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Variables named by tags of files processed since the cache was dropped last time
+    env: BTreeSet<String>,
+    files: HashMap<PathBuf, Stamp>,
+}
+
+impl Cache {
+    /// Missing or outdated cache file gives empty cache
+    pub fn open(
+        path: PathBuf,
+        config: &Data,
+        lang_config: &LangRegistry,
+        read_only: bool,
+    ) -> io::Result<Self> {
+        let fingerprint = fingerprint(config, lang_config);
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(text) => parse(&text, fingerprint, &config.env).unwrap_or_default(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Entries::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path,
+            fingerprint,
+            read_only,
+            env: config.env.clone(),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_fresh(&self, path: &Path, meta: &Metadata) -> bool {
+        let stamp = match Stamp::of(meta) {
+            Some(v) => v,
+            None => return false,
+        };
+        self.entries.lock().unwrap().files.get(path) == Some(&stamp)
+    }
+
+    /// Variables files of cache depend on, including skipped ones
+    pub fn referenced_env(&self) -> Vec<String> {
+        self.entries.lock().unwrap().env.iter().cloned().collect()
+    }
+
+    /// Remembers variables processed file depends on
+    pub fn reference_env(&self, names: &HashSet<String>) {
+        if !names.is_empty() {
+            self.entries.lock().unwrap().env.extend(names.iter().cloned());
+        }
+    }
+
+    /// Remembers file as processed, or forgets it if processing failed
+    pub fn update(&self, path: &Path, processed: bool) {
+        if self.read_only {
+            return;
+        }
+        let files = &mut self.entries.lock().unwrap().files;
+        match std::fs::metadata(path).ok().filter(|_| processed).and_then(|m| Stamp::of(&m)) {
+            Some(stamp) => files.insert(path.to_owned(), stamp),
+            None => files.remove(path),
+        };
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        let dir = match self.path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+        {
+            let mut out = io::BufWriter::new(&mut tmp);
+            writeln!(out, "{} {:016x}", HEADER, self.fingerprint)?;
+            let entries = self.entries.lock().unwrap();
+            // Variables which can't be listed on a single line make every file stale
+            if entries.env.iter().any(|name| name.is_empty() || name.contains(char::is_whitespace)) {
+                return out.flush();
+            }
+            let names: Vec<&str> = entries.env.iter().map(String::as_str).collect();
+            let env = env_hash(&entries.env, &self.env);
+            writeln!(out, "env {:016x} {}", env, names.join(" "))?;
+            let mut files: Vec<_> = entries.files.iter().collect();
+            files.sort_by(|a, b| a.0.cmp(b.0));
+            for (path, stamp) in files {
+                // Paths which can't be stored as a single line are just not cached
+                match path.to_str() {
+                    Some(p) if !p.contains('\n') => {
+                        writeln!(out, "{} {} {}", stamp.len, stamp.mtime, p)?
+                    }
+                    _ => {}
+                }
+            }
+            out.flush()?;
+        }
+        tmp.persist(&self.path).map_err(|e| e.error)?;
+        Ok(())
+    }
+}
+
+fn parse(text: &str, fingerprint: u64, env: &HashMap<String, String>) -> Option<Entries> {
+    let mut lines = text.lines();
+    let stored = lines.next()?.strip_prefix(HEADER)?.trim();
+    if u64::from_str_radix(stored, 16).ok()? != fingerprint {
+        return None;
+    }
+    let mut names = lines.next()?.strip_prefix("env ")?.split(' ');
+    let stored = u64::from_str_radix(names.next()?, 16).ok()?;
+    let names: BTreeSet<String> = names.filter(|n| !n.is_empty()).map(str::to_owned).collect();
+    if env_hash(&names, env) != stored {
+        return None;
+    }
+    let files = lines
+        .map(|line| {
+            let mut parts = line.splitn(3, ' ');
+            let len = parts.next()?.parse().ok()?;
+            let mtime = parts.next()?.parse().ok()?;
+            Some((PathBuf::from(parts.next()?), Stamp { len, mtime }))
+        })
+        .collect::<Option<_>>()?;
+    Some(Entries { env: names, files })
+}
+
+fn sorted<T: Ord>(items: impl Iterator<Item = T>) -> Vec<T> {
+    let mut items: Vec<_> = items.collect();
+    items.sort();
+    items
+}
+
+/// FNV-1a, which unlike [`std::collections::hash_map::DefaultHasher`] is the same for every
+/// build, so its output may be stored
+struct Fnv(u64);
+impl Default for Fnv {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Hash of values of variables `names`, unset ones differ from empty
+fn env_hash(names: &BTreeSet<String>, env: &HashMap<String, String>) -> u64 {
+    let mut hasher = Fnv::default();
+    for name in names {
+        name.hash(&mut hasher);
+        env.get(name).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Hash of everything affecting processing output, except for environment
+fn fingerprint(config: &Data, lang_config: &LangRegistry) -> u64 {
+    let mut hasher = Fnv::default();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    sorted(config.features.iter()).hash(&mut hasher);
+    config.reset.hash(&mut hasher);
+    config.strict.hash(&mut hasher);
+    config.target.hash(&mut hasher);
+    sorted(config.cfg.iter()).hash(&mut hasher);
+    config.version.as_ref().map(|v| v.to_string()).hash(&mut hasher);
+    config.declared_features.as_ref().map(|d| sorted(d.iter())).hash(&mut hasher);
+    lang_config.hash_into(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,163 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+
+use crate::{CfgCommentError, LangDesc, LangRegistry};
+
+/// Config file names, checked in this order in every directory
+pub const CONFIG_FILE_NAMES: &[&str] = &[".cfgcomment.toml", "cfgcomment.toml"];
+
+/// Project configuration, stored in `.cfgcomment.toml` or `cfgcomment.toml`
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Features which stay enabled in committed code. When unset, git filter
+    /// resets every block instead
+    pub committed_features: Option<HashSet<String>>,
+    /// Languages added to [`LangRegistry::with_defaults`] or overriding its entries
+    pub languages: HashMap<String, LangEntry>,
+    /// Select language of files without known extension by their shebang, enabled by default
+    pub detect_shebang: Option<bool>,
+    /// Every valid feature name, tags referring to anything else are reported as errors
+    pub declared_features: Option<HashSet<String>>,
+    /// Groups of features which can't be enabled together, i.e `[["backend-gl", "backend-vk"]]`
+    pub exclusive_features: Vec<Vec<String>>,
+    /// Named feature sets, `[preset.embedded]`
+    pub preset: HashMap<String, Preset>,
+}
+
+/// Features enabled together by `--preset name`
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Preset {
+    pub features: Vec<String>,
+}
+
+/// Language in config, table name is used as its extension unless
+/// `extensions` or `filenames` are listed
+#[derive(Serialize)]
+pub struct LangEntry {
+    #[serde(flatten)]
+    pub desc: LangDesc,
+    pub extensions: Vec<String>,
+    pub filenames: Vec<String>,
+    /// Interpreters in shebang, i.e `bash` for `#!/usr/bin/env bash`
+    pub interpreters: Vec<String>,
+}
+
+fn config_in_dir(dir: &Path) -> Option<PathBuf> {
+    CONFIG_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+// Flattened `desc` can't reject unknown fields, so matching keys are taken out of the table,
+// and the rest has to be valid `LangDesc`
+impl<'de> Deserialize<'de> for LangEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut table = toml::Table::deserialize(deserializer)?;
+        let mut list = |key: &str| match table.remove(key) {
+            Some(value) => Vec::<String>::deserialize(value).map_err(D::Error::custom),
+            None => Ok(Vec::new()),
+        };
+        let extensions = list("extensions")?;
+        let filenames = list("filenames")?;
+        let interpreters = list("interpreters")?;
+        let desc = LangDesc::deserialize(toml::Value::Table(table)).map_err(D::Error::custom)?;
+        Ok(Self {
+            desc,
+            extensions,
+            filenames,
+            interpreters,
+        })
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, CfgCommentError> {
+        let data = std::fs::read_to_string(path).map_err(|source| CfgCommentError::ConfigRead {
+            path: path.to_owned(),
+            source,
+        })?;
+        let config: Self = toml::from_str(&data).map_err(|source| CfgCommentError::ConfigParse {
+            path: path.to_owned(),
+            source,
+        })?;
+        for entry in config.languages.values() {
+            entry.desc.validate()?;
+        }
+        Ok(config)
+    }
+
+    /// Loads config from directory, if it exists
+    pub fn load_from_dir(dir: &Path) -> Result<Option<Self>, CfgCommentError> {
+        match config_in_dir(dir) {
+            Some(path) => Self::load(&path).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Loads config from the closest directory containing one, starting from `start`
+    /// (or its parent, if `start` is a file) and going upward
+    pub fn discover(start: &Path) -> Result<Option<Self>, CfgCommentError> {
+        match Self::discover_path(start)? {
+            Some(path) => Self::load(&path).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Absolute path of config [`Config::discover`] would load, without loading it
+    pub fn discover_path(start: &Path) -> Result<Option<PathBuf>, CfgCommentError> {
+        let start = std::path::absolute(start)?;
+        let start = if start.is_file() {
+            start.parent().unwrap_or(&start)
+        } else {
+            &start
+        };
+        Ok(start.ancestors().find_map(config_in_dir))
+    }
+
+    pub fn preset(&self, name: &str) -> Result<&Preset, CfgCommentError> {
+        self.preset
+            .get(name)
+            .ok_or_else(|| CfgCommentError::UnknownPreset(name.to_owned()))
+    }
+
+    /// Refuses features enabling more than one feature of any exclusive group
+    pub fn check_exclusive(&self, features: &HashSet<String>) -> Result<(), CfgCommentError> {
+        for group in &self.exclusive_features {
+            let enabled: Vec<String> =
+                group.iter().filter(|f| features.contains(*f)).cloned().collect();
+            if enabled.len() > 1 {
+                return Err(CfgCommentError::ExclusiveFeatures(enabled));
+            }
+        }
+        Ok(())
+    }
+
+    /// Default languages, extended with configured ones
+    pub fn lang_config(&self) -> LangRegistry {
+        let mut registry =
+            LangRegistry::with_defaults().with_shebang_detection(self.detect_shebang.unwrap_or(true));
+        for (name, entry) in &self.languages {
+            registry.insert_name(name, entry.desc.clone());
+            if entry.extensions.is_empty() && entry.filenames.is_empty() {
+                registry.insert_extension(name, entry.desc.clone());
+            }
+            for extension in &entry.extensions {
+                registry.insert_extension(extension, entry.desc.clone());
+            }
+            for filename in &entry.filenames {
+                registry.insert_filename(filename, entry.desc.clone());
+            }
+            for interpreter in &entry.interpreters {
+                registry.insert_interpreter(interpreter, entry.desc.clone());
+            }
+        }
+        registry
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Context(String),
+    Removed(String),
+    Added(String),
+}
+
+/// Changed lines with surrounding context, line numbers are 1-based
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+impl fmt::Display for Hunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "@@ -{},{} +{},{} @@",
+            self.old_start, self.old_len, self.new_start, self.new_len
+        )?;
+        for line in &self.lines {
+            match line {
+                DiffLine::Context(l) => writeln!(f, " {}", l)?,
+                DiffLine::Removed(l) => writeln!(f, "-{}", l)?,
+                DiffLine::Added(l) => writeln!(f, "+{}", l)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares lines at the same positions, which is exact for processing output, as it
+/// never inserts or removes lines. Lines past the end of shorter side are reported as
+/// removed or added
+pub fn diff_lines(old: &[String], new: &[String], context: usize) -> Vec<Hunk> {
+    let len = old.len().max(new.len());
+    let changed: Vec<usize> = (0..len).filter(|&i| old.get(i) != new.get(i)).collect();
+
+    // Ranges of positions covered by each hunk, merged if their context overlaps
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(context);
+        let end = (i + 1 + context).min(len);
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            let mut lines = Vec::new();
+            let mut i = start;
+            while i < end {
+                if old.get(i) == new.get(i) {
+                    lines.push(DiffLine::Context(old[i].clone()));
+                    i += 1;
+                    continue;
+                }
+                let run_end = (i..end).find(|&j| old.get(j) == new.get(j)).unwrap_or(end);
+                let removed = &old[i.min(old.len())..run_end.min(old.len())];
+                let added = &new[i.min(new.len())..run_end.min(new.len())];
+                lines.extend(removed.iter().cloned().map(DiffLine::Removed));
+                lines.extend(added.iter().cloned().map(DiffLine::Added));
+                i = run_end;
+            }
+            let old_len = end.min(old.len()).saturating_sub(start);
+            let new_len = end.min(new.len()).saturating_sub(start);
+            Hunk {
+                // Empty side points at the line before hunk
+                old_start: if old_len == 0 { start } else { start + 1 },
+                old_len,
+                new_start: if new_len == 0 { start } else { start + 1 },
+                new_len,
+                lines,
+            }
+        })
+        .collect()
+}
+
+/// Renders hunks as unified diff of a single file, nothing for no hunks
+pub fn unified_diff(old_name: &str, new_name: &str, hunks: &[Hunk]) -> String {
+    if hunks.is_empty() {
+        return String::new();
+    }
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for hunk in hunks {
+        out.push_str(&hunk.to_string());
+    }
+    out
+}
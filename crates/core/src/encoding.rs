@@ -0,0 +1,103 @@
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+/// Encoding of processed file, written back as is, including byte order mark
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    /// Decodes UTF-8, or UTF-8/UTF-16 with byte order mark, `None` for anything else
+    pub fn decode(bytes: &[u8]) -> Option<(Self, String)> {
+        if let Some(rest) = bytes.strip_prefix(UTF8_BOM) {
+            let text = std::str::from_utf8(rest).ok()?;
+            return Some((Encoding::Utf8Bom, text.to_owned()));
+        }
+        if let Some(rest) = bytes.strip_prefix(UTF16LE_BOM) {
+            return Some((Encoding::Utf16Le, decode_utf16(rest, u16::from_le_bytes)?));
+        }
+        if let Some(rest) = bytes.strip_prefix(UTF16BE_BOM) {
+            return Some((Encoding::Utf16Be, decode_utf16(rest, u16::from_be_bytes)?));
+        }
+        let text = std::str::from_utf8(bytes).ok()?;
+        Some((Encoding::Utf8, text.to_owned()))
+    }
+
+    pub fn encode(self, text: &str) -> Vec<u8> {
+        match self {
+            Encoding::Utf8 => text.as_bytes().to_vec(),
+            Encoding::Utf8Bom => [UTF8_BOM, text.as_bytes()].concat(),
+            Encoding::Utf16Le => UTF16LE_BOM
+                .iter()
+                .copied()
+                .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+                .collect(),
+            Encoding::Utf16Be => UTF16BE_BOM
+                .iter()
+                .copied()
+                .chain(text.encode_utf16().flat_map(u16::to_be_bytes))
+                .collect(),
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Option<String> {
+    let units = bytes.chunks_exact(2);
+    if !units.remainder().is_empty() {
+        return None;
+    }
+    char::decode_utf16(units.map(|c| unit([c[0], c[1]])))
+        .collect::<Result<String, _>>()
+        .ok()
+}
+
+/// Same amount of bytes is checked by git
+const SNIFF_LEN: usize = 8000;
+
+/// Content sniffing for files which only have matching name, NUL bytes or a large share of
+/// invalid UTF-8 near the start of file mean it is binary. UTF-16 with byte order mark is
+/// full of NUL bytes, but is still text
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    if bytes.starts_with(UTF16LE_BOM) || bytes.starts_with(UTF16BE_BOM) {
+        return false;
+    }
+    let start = sniffed(bytes);
+    if start.contains(&0) {
+        return true;
+    }
+    let invalid: usize = start.utf8_chunks().map(|c| c.invalid().len()).sum();
+    invalid * 10 > start.len()
+}
+
+/// Binary check of walks, which is only NUL near the start of file. Invalid UTF-8 may be
+/// text in other encoding, which is reported instead. NUL is valid UTF-8, so decoded text
+/// is checked too
+#[cfg(feature = "walk")]
+pub(crate) fn has_nul(bytes: &[u8]) -> bool {
+    sniffed(bytes).contains(&0)
+}
+
+fn sniffed(bytes: &[u8]) -> &[u8] {
+    &bytes[..bytes.len().min(SNIFF_LEN)]
+}
+
+/// Best effort decoding of possibly truncated start of file, used for language detection
+#[cfg(feature = "walk")]
+pub(crate) fn decode_lossy(bytes: &[u8]) -> String {
+    let unit: fn([u8; 2]) -> u16 = if bytes.starts_with(UTF16LE_BOM) {
+        u16::from_le_bytes
+    } else if bytes.starts_with(UTF16BE_BOM) {
+        u16::from_be_bytes
+    } else {
+        let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+        return String::from_utf8_lossy(bytes).into_owned();
+    };
+    char::decode_utf16(bytes[2..].chunks_exact(2).map(|c| unit([c[0], c[1]])))
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
@@ -0,0 +1,77 @@
+use crate::{process_str_with, CfgCommentError, CfgExpr, Data, LangDesc, Visitor};
+
+/// Value of predicate, with values of every operand of `all()`/`any()`/`not()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Evaluation {
+    /// Predicate in canonical form, i.e `feature = "a"`
+    pub predicate: String,
+    pub value: bool,
+    /// Operands in order of appearance, every one is evaluated, even past the one which
+    /// decided the result
+    pub operands: Vec<Evaluation>,
+}
+
+impl CfgExpr {
+    pub(crate) fn explain(&self, config: &Data) -> Evaluation {
+        let operands = match self {
+            Self::Predicate(_) => Vec::new(),
+            Self::All(v) | Self::Any(v) => v.iter().map(|p| p.explain(config)).collect(),
+            Self::Not(v) => vec![v.explain(config)],
+        };
+        Evaluation {
+            predicate: self.to_string(),
+            value: self.evaluate(config),
+            operands,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchKind {
+    /// `[cfg(...)]`
+    Start,
+    /// `[cfg(else if ...)]`
+    ElseIf,
+    /// `[cfg(else)]`
+    Else,
+    /// `[cfg_line(...)]`
+    Line,
+    /// `[cfg_file(...)]`
+    File,
+}
+
+/// Branch of cfg block with reasons of its state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    /// Line of tag starting the branch, 1-based
+    pub line: usize,
+    pub kind: BranchKind,
+    /// Number of blocks this one is nested in
+    pub nesting_depth: usize,
+    /// `None` for `[cfg(else)]`
+    pub evaluation: Option<Evaluation>,
+    /// Every enclosing branch is enabled
+    pub enclosing_enabled: bool,
+    /// One of preceding branches of the same block is enabled, which disables this one
+    pub earlier_branch_matched: bool,
+    /// Whether lines of branch are uncommented by processing with the same config
+    pub enabled: bool,
+}
+
+/// Lists every branch of file in order, with evaluation of its predicate against `config`.
+/// Meant for finding out why some block stays commented
+pub fn explain(
+    input: &str,
+    config: &Data,
+    desc: &LangDesc,
+) -> Result<Vec<Branch>, CfgCommentError> {
+    struct Collector(Vec<Branch>);
+    impl Visitor for Collector {
+        fn enter_branch(&mut self, branch: &Branch) {
+            self.0.push(branch.clone());
+        }
+    }
+    let mut collector = Collector(Vec::new());
+    process_str_with(input, config, desc, &mut collector)?;
+    Ok(collector.0)
+}
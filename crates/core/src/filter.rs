@@ -0,0 +1,125 @@
+use std::path::{Component, Path, PathBuf};
+
+use ignore::{
+    gitignore::Gitignore,
+    overrides::{Override, OverrideBuilder},
+    WalkBuilder,
+};
+
+use crate::CfgCommentError;
+
+/// Paths excluded from processing by glob patterns and extra ignore files. Applied both by
+/// directory walker and to explicit file lists
+#[derive(Clone, Debug)]
+pub struct PathFilter {
+    overrides: Override,
+    ignores: Vec<Gitignore>,
+}
+
+impl Default for PathFilter {
+    fn default() -> Self {
+        Self {
+            overrides: Override::empty(),
+            ignores: Vec::new(),
+        }
+    }
+}
+
+impl PathFilter {
+    /// Filter skipping paths matching any of `exclude` (i.e `vendor/**` or `*.min.js`), and
+    /// unless `only` is empty, files matching none of `only`. Patterns are in `.gitignore`
+    /// syntax relative to current directory, `exclude` wins over `only`.
+    ///
+    /// Paths ignored by any of `ignore_files` are skipped too, they are read like
+    /// `.gitignore`, relative to directory they are in
+    pub fn new(
+        exclude: &[String],
+        only: &[String],
+        ignore_files: &[PathBuf],
+    ) -> Result<Self, CfgCommentError> {
+        let overrides = if exclude.is_empty() && only.is_empty() {
+            Override::empty()
+        } else {
+            let mut builder = OverrideBuilder::new(std::env::current_dir()?);
+            for pattern in only {
+                builder.add(pattern)?;
+            }
+            for pattern in exclude {
+                builder.add(&format!("!{}", pattern))?;
+            }
+            builder.build()?
+        };
+        let mut ignores = Vec::new();
+        for path in ignore_files {
+            // Missing files are fine for walker, but not when asked for explicitly
+            std::fs::metadata(path).map_err(|source| CfgCommentError::ConfigRead {
+                path: path.clone(),
+                source,
+            })?;
+            let (ignore, error) = Gitignore::new(normalize(path)?);
+            if let Some(error) = error {
+                return Err(error.into());
+            }
+            ignores.push(ignore);
+        }
+        Ok(Self { overrides, ignores })
+    }
+
+    /// Whether file at `path`, or any directory containing it, is excluded
+    pub fn excludes(&self, path: &Path) -> bool {
+        self.overrides.matched(path, false).is_ignore()
+            || path
+                .ancestors()
+                .skip(1)
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .any(|dir| self.overrides.matched(dir, true).is_ignore())
+            || self.ignored(path, false, true)
+    }
+
+    fn ignored(&self, path: &Path, is_dir: bool, parents: bool) -> bool {
+        if self.ignores.is_empty() {
+            return false;
+        }
+        let path = match normalize(path) {
+            Ok(v) => v,
+            Err(_) => return false,
+        };
+        self.ignores
+            .iter()
+            .filter(|ignore| path.starts_with(ignore.path()))
+            .any(|ignore| match parents {
+                true => ignore
+                    .matched_path_or_any_parents(&path, is_dir)
+                    .is_ignore(),
+                false => ignore.matched(&path, is_dir).is_ignore(),
+            })
+    }
+
+    /// Walker doesn't descend into excluded directories
+    pub(crate) fn apply(&self, walk: &mut WalkBuilder) {
+        walk.overrides(self.overrides.clone());
+        if !self.ignores.is_empty() {
+            let filter = self.clone();
+            walk.filter_entry(move |entry| {
+                let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                !filter.ignored(entry.path(), is_dir, false)
+            });
+        }
+    }
+}
+
+/// Absolute path with `..` resolved lexically, so paths given relative to other directories
+/// can be compared
+fn normalize(path: &Path) -> std::io::Result<PathBuf> {
+    let mut out = PathBuf::new();
+    for component in std::path::absolute(path)?.components() {
+        match component {
+            Component::ParentDir => {
+                out.pop();
+            }
+            Component::CurDir => {}
+            other => out.push(other),
+        }
+    }
+    Ok(out)
+}
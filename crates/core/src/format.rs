@@ -0,0 +1,60 @@
+use crate::{join_lines, parse_tag, split_lines, CfgCommentError, CfgExpr, CfgTag, LangDesc};
+
+fn render(mut group: CfgExpr, sort: bool) -> String {
+    if sort {
+        group.sort_operands();
+    }
+    group.to_string()
+}
+
+/// Canonical form of tag, without indentation
+fn canonical_tag(tag: CfgTag, desc: &LangDesc, sort: bool) -> String {
+    let keyword = &desc.cfg_keyword;
+    let body = match tag {
+        CfgTag::Start(p, _) => format!("{}({})", keyword, render(p, sort)),
+        CfgTag::ElseIf(p, _) => format!("{}(else if {})", keyword, render(p, sort)),
+        CfgTag::Else => format!("{}(else)", keyword),
+        CfgTag::End(None) => format!("{}(end)", keyword),
+        CfgTag::End(Some(p)) => format!("{}(end {})", keyword, render(p, sort)),
+        CfgTag::Line(p, _) => format!("{}_line({})", keyword, render(p, sort)),
+        CfgTag::File(p, _) => format!("{}_file({})", keyword, render(p, sort)),
+    };
+    format!("{}{}{}", desc.cfg_prefix, body, desc.cfg_suffix)
+}
+
+/// Rewrites every cfg tag occupying whole line into canonical form, with single spaces
+/// around operators and after commas, and no trailing commas. Operands of `all()`/`any()`
+/// are sorted if `sort` is set. Tags inside of lines and everything else is kept as is
+///
+/// ```
+/// use cfgcomment_core::{format_tags, LangDesc};
+///
+/// let input = "  //[cfg(any(feature=\"b\",feature = \"a\",))]\n";
+/// let output = format_tags(input, &LangDesc::new("//"), true).unwrap();
+/// assert_eq!(output, "  //[cfg(any(feature = \"a\", feature = \"b\"))]\n");
+/// ```
+pub fn format_tags(input: &str, desc: &LangDesc, sort: bool) -> Result<String, CfgCommentError> {
+    let lines = split_lines(input);
+    let (_, formatted) = format_split(&lines, desc, sort)?;
+    Ok(join_lines(&lines, &formatted))
+}
+
+/// Formats lines of `split_lines`, returning original and formatted contents
+pub(crate) fn format_split(
+    lines: &[(&str, &str)],
+    desc: &LangDesc,
+    sort: bool,
+) -> Result<(Vec<String>, Vec<String>), CfgCommentError> {
+    let original: Vec<String> = lines.iter().map(|(l, _)| (*l).to_owned()).collect();
+    let formatted = original
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            Ok(match parse_tag(line, i, desc, false)? {
+                Some((ws, tag)) => format!("{}{}", ws, canonical_tag(tag, desc, sort)),
+                None => line.clone(),
+            })
+        })
+        .collect::<Result<_, CfgCommentError>>()?;
+    Ok((original, formatted))
+}
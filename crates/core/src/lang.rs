@@ -0,0 +1,212 @@
+#[cfg(feature = "walk")]
+use std::hash::{Hash, Hasher};
+use std::{collections::HashMap, path::Path};
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{CfgCommentError, LangDesc};
+
+/// Languages by file name and extension
+#[derive(Default, Clone)]
+pub struct LangRegistry {
+    /// Extensions without leading dot, may be compound, i.e `blade.php`.
+    /// Stored normalized, see [`normalize_extension`]
+    extensions: HashMap<String, LangDesc>,
+    /// Full file names, i.e `Makefile`
+    filenames: HashMap<String, LangDesc>,
+    /// Names for `cfgcomment: lang=python` overrides, extensions are accepted too
+    names: HashMap<String, LangDesc>,
+    /// Interpreters from `#!/usr/bin/env bash`, for files not matched by path
+    interpreters: HashMap<String, LangDesc>,
+    /// Whether shebang is checked for files not matched by path
+    detect_shebang: bool,
+}
+
+impl From<HashMap<String, LangDesc>> for LangRegistry {
+    /// Registry with languages by extension, as returned by [`LangDesc::default_list`]
+    fn from(extensions: HashMap<String, LangDesc>) -> Self {
+        Self {
+            extensions: extensions
+                .into_iter()
+                .map(|(ext, desc)| (normalize_extension(&ext), desc))
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Extensions are matched case-insensitively and in NFC form, so `MAIN.RS` is still Rust
+fn normalize_extension(extension: &str) -> String {
+    extension.nfc().collect::<String>().to_lowercase()
+}
+
+/// Language override on the first line of file, `# cfgcomment: lang=python`. Language is
+/// unknown yet, so marker only has to follow comment opening of any language. Mentions of it
+/// after code or in string literals are ignored
+pub fn magic_lang(first_line: &str) -> Option<&str> {
+    let (opening, rest) = first_line.split_once("cfgcomment:")?;
+    let opening = opening.trim();
+    let is_comment = !opening.is_empty()
+        && opening
+            .chars()
+            .all(|c| c.is_ascii_punctuation() && !matches!(c, '"' | '\'' | '`'));
+    if !is_comment {
+        return None;
+    }
+    rest.split_whitespace().find_map(|t| t.strip_prefix("lang="))
+}
+
+/// Interpreter name from shebang, `#!/usr/bin/env -S python3 -u` gives `python3`
+pub fn shebang_interpreter(first_line: &str) -> Option<&str> {
+    let mut args = first_line.strip_prefix("#!")?.split_whitespace();
+    let program = args.next()?.rsplit('/').next()?;
+    if program != "env" {
+        return Some(program);
+    }
+    // Skip env flags and variable assignments
+    args.find(|a| !a.starts_with('-') && !a.contains('='))
+}
+
+impl LangRegistry {
+    /// Hashes all languages in stable order, used to invalidate cache on config changes
+    #[cfg(feature = "walk")]
+    pub(crate) fn hash_into(&self, state: &mut impl Hasher) {
+        for map in [&self.extensions, &self.filenames, &self.names, &self.interpreters].iter() {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            entries.hash(state);
+        }
+        self.detect_shebang.hash(state);
+    }
+
+    /// [`LangDesc::default_list`] extensions, plus well-known file names
+    pub fn with_defaults() -> Self {
+        let Self { extensions, .. } = LangDesc::default_list().into();
+        let hash = extensions["toml"].clone();
+        let filenames = ["Makefile", "Dockerfile", "CMakeLists.txt"]
+            .iter()
+            .map(|name| (name.to_string(), hash.clone()))
+            .collect();
+        let interpreters = [
+            ("sh", "sh"),
+            ("bash", "sh"),
+            ("zsh", "sh"),
+            ("dash", "sh"),
+            ("python", "py"),
+            ("node", "js"),
+            ("deno", "ts"),
+        ]
+        .iter()
+        .map(|(name, ext)| (name.to_string(), extensions[*ext].clone()))
+        .collect();
+        let names = [
+            ("rust", "rs"),
+            ("javascript", "js"),
+            ("typescript", "ts"),
+            ("python", "py"),
+            ("shell", "sh"),
+            ("yaml", "yaml"),
+        ]
+        .iter()
+        .map(|(name, ext)| (name.to_string(), extensions[*ext].clone()))
+        .collect();
+        Self {
+            extensions,
+            filenames,
+            names,
+            interpreters,
+            detect_shebang: true,
+        }
+    }
+
+    /// Enables selecting language of files not matched by path by their shebang
+    pub fn with_shebang_detection(mut self, detect_shebang: bool) -> Self {
+        self.detect_shebang = detect_shebang;
+        self
+    }
+
+    /// Validates language and registers it for given extensions and file names,
+    /// replacing previous registrations
+    pub fn register(
+        &mut self,
+        desc: LangDesc,
+        extensions: &[&str],
+        filenames: &[&str],
+    ) -> Result<&mut Self, CfgCommentError> {
+        desc.validate()?;
+        for extension in extensions {
+            self.insert_extension(*extension, desc.clone());
+        }
+        for filename in filenames {
+            self.insert_filename(*filename, desc.clone());
+        }
+        Ok(self)
+    }
+
+    pub fn insert_extension(&mut self, extension: impl Into<String>, desc: LangDesc) {
+        self.extensions
+            .insert(normalize_extension(&extension.into()), desc);
+    }
+    pub fn insert_filename(&mut self, filename: impl Into<String>, desc: LangDesc) {
+        self.filenames.insert(filename.into(), desc);
+    }
+    pub fn insert_name(&mut self, name: impl Into<String>, desc: LangDesc) {
+        self.names.insert(name.into(), desc);
+    }
+    pub fn insert_interpreter(&mut self, interpreter: impl Into<String>, desc: LangDesc) {
+        self.interpreters.insert(interpreter.into(), desc);
+    }
+
+    /// Language by interpreter name, versions are ignored if there is no exact match,
+    /// so `python3.11` is `python`
+    pub fn get_by_interpreter(&self, interpreter: &str) -> Option<&LangDesc> {
+        self.interpreters.get(interpreter).or_else(|| {
+            self.interpreters
+                .get(interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.'))
+        })
+    }
+
+    /// Language by name or extension
+    pub fn get_by_name(&self, name: &str) -> Option<&LangDesc> {
+        self.names
+            .get(name)
+            .or_else(|| self.extensions.get(&normalize_extension(name)))
+    }
+
+    pub fn extensions(&self) -> impl Iterator<Item = (&str, &LangDesc)> {
+        self.extensions.iter().map(|(k, v)| (k.as_str(), v))
+    }
+    pub fn filenames(&self) -> impl Iterator<Item = (&str, &LangDesc)> {
+        self.filenames.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Full file name match wins, then the longest matching extension,
+    /// so `a.blade.php` prefers `blade.php` over `php`
+    pub fn get_for_path(&self, path: &Path) -> Option<&LangDesc> {
+        let name = path.file_name()?.to_str()?;
+        if let Some(desc) = self.filenames.get(name) {
+            return Some(desc);
+        }
+        name.match_indices('.')
+            // Leading dot is part of name, `.bashrc` has no extension
+            .filter(|(i, _)| *i != 0)
+            .find_map(|(i, _)| self.extensions.get(&normalize_extension(&name[i + 1..])))
+    }
+
+    /// Language declared by magic comment on the first line of file, or
+    /// matched by its path, or by its shebang otherwise
+    pub fn resolve(&self, path: &Path, first_line: &str) -> Result<Option<&LangDesc>, CfgCommentError> {
+        if let Some(name) = magic_lang(first_line) {
+            return self
+                .get_by_name(name)
+                .map(Some)
+                .ok_or_else(|| CfgCommentError::UnknownLang(name.to_owned()));
+        }
+        Ok(self.get_for_path(path).or_else(|| {
+            if !self.detect_shebang {
+                return None;
+            }
+            self.get_by_interpreter(shebang_interpreter(first_line)?)
+        }))
+    }
+}
@@ -1,80 +1,868 @@
 use std::{
-    cell::RefCell,
-    collections::{HashMap, HashSet},
-    fs::File,
-    io::{BufRead, BufReader, BufWriter, Write},
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, io,
+    ops::Range,
     path::PathBuf,
-    rc::Rc,
+    str::FromStr,
     sync::Arc,
 };
 
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "walk")]
+mod cache;
+mod config;
+mod diff;
+mod encoding;
+mod explain;
+#[cfg(feature = "walk")]
+mod filter;
+mod format;
+mod lang;
+mod manifest;
+#[cfg(feature = "walk")]
+mod mapped;
+mod spans;
+mod version;
+mod visit;
+#[cfg(feature = "walk")]
+mod walk;
+pub use config::{Config, LangEntry, Preset, CONFIG_FILE_NAMES};
+pub use diff::{diff_lines, unified_diff, DiffLine, Hunk};
+pub use encoding::{looks_binary, Encoding};
+pub use explain::{explain, Branch, BranchKind, Evaluation};
+#[cfg(feature = "walk")]
+pub use filter::PathFilter;
+pub use format::format_tags;
+pub use lang::{magic_lang, shebang_interpreter, LangRegistry};
+pub use manifest::Manifest;
+pub use spans::{spans, Span};
+pub use version::Version;
+pub use visit::{process_str_with, LineState, Visitor};
+use visit::{Dispatcher, Event, NoopVisitor};
+#[cfg(feature = "walk")]
+pub use walk::{
+    process_files, walkdir_parallel, ExclusiveConflict, Progress, WalkOptions, WalkSummary,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum CfgCommentError {
+    #[error("tag on line {line} is indented with {end:?}, but its start tag uses {start:?}")]
+    MismatchedIndent {
+        line: usize,
+        start: String,
+        end: String,
+    },
+    #[error("invalid cfg tag {text:?} on line {line}, column {column}: expected {expected}")]
+    InvalidTag {
+        line: usize,
+        column: usize,
+        expected: String,
+        /// Offending tag, without comment markers
+        text: String,
+    },
+    #[error("invalid cfg predicate {text:?}, column {column}: expected {expected}")]
+    InvalidPredicate {
+        column: usize,
+        expected: String,
+        text: String,
+    },
+    #[error("invalid feature glob {pattern:?}: {source}")]
+    InvalidGlob {
+        pattern: String,
+        source: globset::Error,
+    },
+    #[error("end tag on line {line} has no matching start tag")]
+    UnexpectedEnd { line: usize },
+    #[error("end tag on line {line} repeats `{end}`, but start tag on line {start_line} has `{start}`")]
+    MismatchedEnd {
+        line: usize,
+        start_line: usize,
+        start: String,
+        end: String,
+    },
+    #[error("else tag on line {line} has no matching start tag")]
+    UnexpectedElse { line: usize },
+    #[error("else tag on line {line} follows final else tag of the same block")]
+    DuplicateElse { line: usize },
+    #[error("line tag on line {line} isn't followed by a line it applies to")]
+    DanglingLineTag { line: usize },
+    #[error("file tag on line {line} is only allowed on the first line")]
+    MisplacedFileTag { line: usize },
+    #[error("start tag on line {line} is never closed{}", enclosing(.outer))]
+    Unclosed {
+        line: usize,
+        /// Start lines of enclosing blocks, which are left open too, outermost first
+        outer: Vec<usize>,
+    },
+    #[error("line {line} contains {close:?}, which would end comment disabling it early")]
+    CommentCloser { line: usize, close: String },
+    #[error("comment marker {0:?} can't be told apart from uncommented code")]
+    BlankComment(String),
+    #[error("line {line} is indented less than its start tag, expected {prefix:?} prefix")]
+    Underindented { line: usize, prefix: String },
+    #[error(
+        "feature {feature:?} on line {line} is not declared in project config{}",
+        did_you_mean(.suggestion)
+    )]
+    UndeclaredFeature {
+        line: usize,
+        feature: String,
+        /// Closest declared feature, if there is one similar enough
+        suggestion: Option<String>,
+    },
+    #[error("unknown language {0:?} in cfgcomment magic comment")]
+    UnknownLang(String),
+    #[error("unknown preset {0:?}, presets are defined in project config as [preset.<name>]")]
+    UnknownPreset(String),
+    #[error("features {} are mutually exclusive, only one of them may be enabled", .0.join(", "))]
+    ExclusiveFeatures(Vec<String>),
+    #[error("feature {feature:?} is not defined in {path}")]
+    UnknownManifestFeature { path: PathBuf, feature: String },
+    #[error("file is neither UTF-8, nor UTF-16 with byte order mark")]
+    UnknownEncoding,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[cfg(feature = "walk")]
+    #[error(transparent)]
+    Walk(#[from] ignore::Error),
+    #[error("failed to read {path}: {source}")]
+    ConfigRead { path: PathBuf, source: io::Error },
+    #[error("failed to parse {path}: {source}")]
+    ConfigParse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+}
+
+impl CfgCommentError {
+    /// Line of file error points at, 1-based
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Self::MismatchedIndent { line, .. }
+            | Self::InvalidTag { line, .. }
+            | Self::UnexpectedEnd { line }
+            | Self::MismatchedEnd { line, .. }
+            | Self::UnexpectedElse { line }
+            | Self::DuplicateElse { line }
+            | Self::DanglingLineTag { line }
+            | Self::MisplacedFileTag { line }
+            | Self::Unclosed { line, .. }
+            | Self::CommentCloser { line, .. }
+            | Self::Underindented { line, .. }
+            | Self::UndeclaredFeature { line, .. } => Some(*line),
+            _ => None,
+        }
+    }
+}
+
+fn enclosing(outer: &[usize]) -> String {
+    match outer {
+        [] => String::new(),
+        [line] => format!(", neither is enclosing one on line {}", line),
+        lines => {
+            let lines: Vec<_> = lines.iter().map(usize::to_string).collect();
+            format!(", neither are enclosing ones on lines {}", lines.join(", "))
+        }
+    }
+}
+
+fn did_you_mean(suggestion: &Option<String>) -> String {
+    match suggestion {
+        Some(s) => format!(", did you mean {:?}?", s),
+        None => String::new(),
+    }
+}
+
+/// Target platform description, used for `target_os = "linux"` and alike
+#[derive(Default, Clone, Hash, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Target {
+    pub os: Option<String>,
+    pub arch: Option<String>,
+    /// Target may belong to multiple families, i.e `unix` and `wasm`
+    pub family: Vec<String>,
+    pub env: Option<String>,
+    pub vendor: Option<String>,
+    pub endian: Option<String>,
+    pub pointer_width: Option<String>,
+}
+impl Target {
+    /// Platform this binary is built for
+    pub fn host() -> Self {
+        Self {
+            os: Some(std::env::consts::OS.to_owned()),
+            arch: Some(std::env::consts::ARCH.to_owned()),
+            family: vec![std::env::consts::FAMILY.to_owned()],
+            endian: Some(if cfg!(target_endian = "little") { "little" } else { "big" }.to_owned()),
+            pointer_width: Some((std::mem::size_of::<usize>() * 8).to_string()),
+            ..Default::default()
+        }
+    }
+    fn matches(&self, key: TargetKey, value: &str) -> bool {
+        let field = match key {
+            TargetKey::Os => &self.os,
+            TargetKey::Arch => &self.arch,
+            TargetKey::Family => return self.family.iter().any(|f| f == value),
+            TargetKey::Env => &self.env,
+            TargetKey::Vendor => &self.vendor,
+            TargetKey::Endian => &self.endian,
+            TargetKey::PointerWidth => &self.pointer_width,
+        };
+        field.as_deref() == Some(value)
+    }
+}
+
+/// Property of [`Target`], `os` in `target_os = "linux"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TargetKey {
+    Os,
+    Arch,
+    Family,
+    Env,
+    Vendor,
+    Endian,
+    PointerWidth,
+}
+impl TargetKey {
+    /// Name without `target_` prefix, as written in tags
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Os => "os",
+            Self::Arch => "arch",
+            Self::Family => "family",
+            Self::Env => "env",
+            Self::Vendor => "vendor",
+            Self::Endian => "endian",
+            Self::PointerWidth => "pointer_width",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "os" => Self::Os,
+            "arch" => Self::Arch,
+            "family" => Self::Family,
+            "env" => Self::Env,
+            "vendor" => Self::Vendor,
+            "endian" => Self::Endian,
+            "pointer_width" => Self::PointerWidth,
+            _ => return None,
+        })
+    }
+}
+
+/// Everything predicates are evaluated against. Serialized form is meant for config files
+/// and reports, with [`Data::cfg`] written like `--cfg` arguments: `name` or `name="value"`
+#[derive(Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Data {
     pub features: HashSet<String>,
     pub reset: bool,
+    /// Treat authoring mistakes (i.e misaligned end tags, trailing commas) as errors
+    pub strict: bool,
+    /// Environment used for cfg(env("NAME")), see [`Data::capture_env`]. Not serialized, as
+    /// it may hold secrets
+    #[serde(skip_serializing)]
+    pub env: HashMap<String, String>,
+    /// Platform used for cfg(target_os = "linux"), no target predicates match by default
+    pub target: Target,
+    /// Custom options, name with optional value, like rustc's `--cfg name="value"`
+    #[serde(with = "cfg_options")]
+    pub cfg: HashSet<(String, Option<String>)>,
+    /// Language/toolchain version for cfg(version("1.70")), no version predicates match if unset
+    pub version: Option<Version>,
+    /// Every valid feature name, tags referring to other features are errors. Anything goes
+    /// if unset
+    pub declared_features: Option<HashSet<String>>,
 }
 impl Data {
     fn has_feature(&self, feature: &str) -> bool {
         self.features.contains(feature)
     }
+    fn has_cfg(&self, name: &str, value: Option<&str>) -> bool {
+        self.cfg
+            .iter()
+            .any(|(n, v)| n == name && v.as_deref() == value)
+    }
+    /// Reports first feature named by tag which isn't declared
+    fn check_declared(&self, tag: &CfgTag, line: usize) -> Result<(), CfgCommentError> {
+        let (declared, group) = match (&self.declared_features, tag.predicate()) {
+            (Some(declared), Some(group)) => (declared, group),
+            _ => return Ok(()),
+        };
+        let mut undeclared = None;
+        group.each_predicate(&mut |p| match p {
+            Predicate::Feature(f) if undeclared.is_none() && !declared.contains(f) => {
+                undeclared = Some(f.clone())
+            }
+            _ => {}
+        });
+        match undeclared {
+            Some(feature) => Err(CfgCommentError::UndeclaredFeature {
+                line,
+                suggestion: closest(&feature, declared.iter()).cloned(),
+                feature,
+            }),
+            None => Ok(()),
+        }
+    }
+    /// Snapshot current process environment, non-unicode variables are skipped
+    pub fn capture_env(&mut self) {
+        self.env = std::env::vars_os()
+            .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+            .collect();
+    }
+    /// Snapshot only `names` of current process environment, unset and non-unicode
+    /// variables are skipped
+    pub fn capture_env_vars<'a>(&mut self, names: impl IntoIterator<Item = &'a str>) {
+        self.env = names
+            .into_iter()
+            .filter_map(|name| Some((name.to_owned(), std::env::var(name).ok()?)))
+            .collect();
+    }
 }
 
-enum Predicate {
+/// [`Data::cfg`] as sorted list of `name` and `name="value"` strings, `"` and `\` of value
+/// are escaped with `\`
+mod cfg_options {
+    use std::collections::HashSet;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        cfg: &HashSet<(String, Option<String>)>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut options: Vec<String> = cfg
+            .iter()
+            .map(|(name, value)| match value {
+                Some(value) => {
+                    let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+                    format!("{}=\"{}\"", name, value)
+                }
+                None => name.clone(),
+            })
+            .collect();
+        options.sort();
+        options.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashSet<(String, Option<String>)>, D::Error> {
+        let options = Vec::<String>::deserialize(deserializer)?;
+        Ok(options
+            .iter()
+            .map(|option| match option.split_once('=') {
+                Some((name, value)) => {
+                    let value = value.trim();
+                    let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                        Some(quoted) => unescape(quoted),
+                        None => value.to_owned(),
+                    };
+                    (name.trim().to_owned(), Some(value))
+                }
+                None => (option.trim().to_owned(), None),
+            })
+            .collect())
+    }
+
+    /// Drops `\` before escaped characters
+    fn unescape(quoted: &str) -> String {
+        let mut out = String::with_capacity(quoted.len());
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => out.extend(chars.next()),
+                c => out.push(c),
+            }
+        }
+        out
+    }
+}
+
+/// Edit distance with transpositions, `feautre` is a single edit away from `feature`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Rows for two previous prefixes of `a`, and the current one
+    let mut prev2 = vec![0; b.len() + 1];
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut cur = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            cur[j] = (prev[j] + 1).min(cur[j - 1] + 1).min(prev[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                cur[j] = cur[j].min(prev2[j - 2] + 1);
+            }
+        }
+        prev2 = std::mem::replace(&mut prev, cur);
+    }
+    prev[b.len()]
+}
+
+/// Candidate most similar to `name`, if it is close enough to be a typo
+fn closest<'c>(name: &str, candidates: impl Iterator<Item = &'c String>) -> Option<&'c String> {
+    let max = (name.chars().count() / 3).max(1);
+    candidates
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|(d, _)| *d <= max)
+        .min()
+        .map(|(_, c)| c)
+}
+
+fn feature_glob(pattern: &str) -> Result<globset::GlobMatcher, globset::Error> {
+    Ok(globset::GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()?
+        .compile_matcher())
+}
+
+/// Pattern of `feature ~ "backend-*"`, compiled once when constructed
+#[derive(Debug, Clone)]
+pub struct FeatureGlob {
+    pattern: String,
+    matcher: globset::GlobMatcher,
+}
+impl FeatureGlob {
+    pub fn new(pattern: impl Into<String>) -> Result<Self, CfgCommentError> {
+        let pattern = pattern.into();
+        match feature_glob(&pattern) {
+            Ok(matcher) => Ok(Self { pattern, matcher }),
+            Err(source) => Err(CfgCommentError::InvalidGlob { pattern, source }),
+        }
+    }
+    /// Pattern as written in tag
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+    pub fn is_match(&self, feature: &str) -> bool {
+        self.matcher.is_match(feature)
+    }
+}
+impl PartialEq for FeatureGlob {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+/// Single condition of cfg expression, i.e `feature = "a"`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
     Feature(String),
+    /// `feature ~ "backend-*"`, matches if any enabled feature matches glob
+    FeatureGlob(FeatureGlob),
+    Env { name: String, value: Option<String> },
+    /// `target_{key} = "value"`
+    Target { key: TargetKey, value: String },
+    /// Arbitrary `key = "value"`, matched against [`Data::cfg`]
+    KeyValue { key: String, value: String },
+    /// Bare name, i.e `test` or `unix`, matched against [`Data::cfg`]
+    Flag(String),
+    /// Minimum version, matched against [`Data::version`]
+    Version(Version),
 }
 impl Predicate {
     fn matches(&self, config: &Data) -> bool {
         match self {
             Self::Feature(f) => config.has_feature(f),
+            Self::FeatureGlob(glob) => config.features.iter().any(|f| glob.is_match(f)),
+            Self::Env { name, value } => match (config.env.get(name), value) {
+                (Some(_), None) => true,
+                (Some(v), Some(expected)) => v == expected,
+                (None, _) => false,
+            },
+            Self::Target { key, value } => config.target.matches(*key, value),
+            Self::KeyValue { key, value } => config.has_cfg(key, Some(value)),
+            // Same as rustc, unix and windows are implied by target family
+            Self::Flag(name) if name == "unix" || name == "windows" => {
+                config.has_cfg(name, None) || config.target.family.contains(name)
+            }
+            Self::Flag(name) => config.has_cfg(name, None),
+            Self::Version(min) => config.version.as_ref().is_some_and(|v| v >= min),
         }
     }
 }
 
-enum Group {
-    Option(Predicate),
+/// Parsed predicate of cfg tag, [`Display`](fmt::Display) renders it back in canonical form
+#[derive(Debug, Clone, PartialEq)]
+pub enum CfgExpr {
+    Predicate(Predicate),
     All(Vec<Self>),
     Any(Vec<Self>),
     Not(Box<Self>),
 }
 
-impl Group {
-    fn matches(&self, config: &Data) -> bool {
+impl CfgExpr {
+    /// `feature = "name"`
+    pub fn feature(name: impl Into<String>) -> Self {
+        Self::Predicate(Predicate::Feature(name.into()))
+    }
+    /// `feature ~ "pattern"`
+    pub fn feature_glob(pattern: impl Into<String>) -> Result<Self, CfgCommentError> {
+        FeatureGlob::new(pattern.into()).map(|glob| Self::Predicate(Predicate::FeatureGlob(glob)))
+    }
+    /// `env("name")`, holds if variable is set
+    pub fn env(name: impl Into<String>) -> Self {
+        Self::Predicate(Predicate::Env {
+            name: name.into(),
+            value: None,
+        })
+    }
+    /// `env("name") = "value"`
+    pub fn env_value(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self::Predicate(Predicate::Env {
+            name: name.into(),
+            value: Some(value.into()),
+        })
+    }
+    /// `target_{key} = "value"`
+    pub fn target(key: TargetKey, value: impl Into<String>) -> Self {
+        Self::Predicate(Predicate::Target {
+            key,
+            value: value.into(),
+        })
+    }
+    /// `version("min")`
+    pub fn version(min: Version) -> Self {
+        Self::Predicate(Predicate::Version(min))
+    }
+    /// Bare `name`, i.e `test`. Names of other predicates and ones which aren't identifiers
+    /// are rejected
+    pub fn flag(name: impl Into<String>) -> Result<Self, CfgCommentError> {
+        let name = name.into();
+        Self::checked(&name, Predicate::Flag(name.clone()))
+    }
+    /// `key = "value"`. `target_os` and other target keys give [`Predicate::Target`], names of
+    /// other predicates and ones which aren't identifiers are rejected
+    pub fn key_value(
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Self, CfgCommentError> {
+        let key = key.into();
+        let value = value.into();
+        Self::checked(&key, Predicate::KeyValue { key: key.clone(), value })
+    }
+    /// Parses rendered `predicate` back, so it is what the same tag would be
+    fn checked(name: &str, predicate: Predicate) -> Result<Self, CfgCommentError> {
+        let text = predicate.to_string();
+        // `feature = "a"` would be parsed back as feature predicate
+        if RESERVED_NAMES.contains(&name) {
+            return Err(CfgCommentError::InvalidPredicate {
+                column: 1,
+                expected: RESERVED_EXPECTED.to_owned(),
+                text,
+            });
+        }
+        parse_cfg(&text)
+    }
+    /// `not(expr)`, associated function to read as in tags: `CfgExpr::not(..)`
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(expr: Self) -> Self {
+        Self::Not(Box::new(expr))
+    }
+    pub fn all(exprs: impl IntoIterator<Item = Self>) -> Self {
+        Self::All(exprs.into_iter().collect())
+    }
+    pub fn any(exprs: impl IntoIterator<Item = Self>) -> Self {
+        Self::Any(exprs.into_iter().collect())
+    }
+    /// `all(self, other)`, extending `self` if it is `all()` already
+    pub fn and(self, other: Self) -> Self {
+        match self {
+            Self::All(mut v) => {
+                v.push(other);
+                Self::All(v)
+            }
+            expr => Self::All(vec![expr, other]),
+        }
+    }
+    /// `any(self, other)`, extending `self` if it is `any()` already
+    pub fn or(self, other: Self) -> Self {
         match self {
-            Self::Option(o) => o.matches(config),
-            Self::All(v) => v.iter().all(|p| p.matches(config)),
-            Self::Any(v) => v.iter().any(|p| p.matches(config)),
-            Self::Not(v) => !v.matches(config),
+            Self::Any(mut v) => {
+                v.push(other);
+                Self::Any(v)
+            }
+            expr => Self::Any(vec![expr, other]),
+        }
+    }
+
+    /// Whether expression holds for `config`, the same way it is checked for tags
+    pub fn evaluate(&self, config: &Data) -> bool {
+        match self {
+            Self::Predicate(o) => o.matches(config),
+            Self::All(v) => v.iter().all(|p| p.evaluate(config)),
+            Self::Any(v) => v.iter().any(|p| p.evaluate(config)),
+            Self::Not(v) => !v.evaluate(config),
+        }
+    }
+    fn each_predicate(&self, f: &mut impl FnMut(&Predicate)) {
+        match self {
+            Self::Predicate(p) => f(p),
+            Self::All(v) | Self::Any(v) => v.iter().for_each(|g| g.each_predicate(f)),
+            Self::Not(v) => v.each_predicate(f),
+        }
+    }
+    /// Feature names and glob patterns this group refers to, in order of appearance
+    fn features(&self, out: &mut Vec<String>) {
+        match self {
+            Self::Predicate(Predicate::Feature(f)) => {
+                if !out.contains(f) {
+                    out.push(f.clone());
+                }
+            }
+            Self::Predicate(Predicate::FeatureGlob(glob)) => {
+                if !out.iter().any(|f| f == glob.as_str()) {
+                    out.push(glob.as_str().to_owned());
+                }
+            }
+            Self::Predicate(_) => {}
+            Self::All(v) | Self::Any(v) => v.iter().for_each(|p| p.features(out)),
+            Self::Not(v) => v.features(out),
+        }
+    }
+    /// Features this group can't be true without, in order of appearance
+    fn required_features(&self) -> Vec<String> {
+        match self {
+            Self::Predicate(Predicate::Feature(f)) => vec![f.clone()],
+            Self::Predicate(_) | Self::Not(_) => Vec::new(),
+            Self::All(v) => {
+                let mut out = Vec::new();
+                for f in v.iter().flat_map(Self::required_features) {
+                    if !out.contains(&f) {
+                        out.push(f);
+                    }
+                }
+                out
+            }
+            Self::Any(v) => {
+                let required: Vec<Vec<String>> = v.iter().map(Self::required_features).collect();
+                match required.split_first() {
+                    Some((first, rest)) => first
+                        .iter()
+                        .filter(|f| rest.iter().all(|r| r.contains(f)))
+                        .cloned()
+                        .collect(),
+                    None => Vec::new(),
+                }
+            }
+        }
+    }
+    /// Orders operands of every `all()`/`any()` by their canonical form
+    fn sort_operands(&mut self) {
+        match self {
+            Self::Predicate(_) => {}
+            Self::All(v) | Self::Any(v) => {
+                v.iter_mut().for_each(Self::sort_operands);
+                v.sort_by_cached_key(|p| p.to_string());
+            }
+            Self::Not(v) => v.sort_operands(),
+        }
+    }
+}
+
+/// Renders predicate in canonical form, which parses back to the same predicate
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Feature(name) => write!(f, "feature = \"{}\"", name),
+            Self::FeatureGlob(glob) => write!(f, "feature ~ \"{}\"", glob.as_str()),
+            Self::Env { name, value: None } => write!(f, "env(\"{}\")", name),
+            Self::Env {
+                name,
+                value: Some(value),
+            } => write!(f, "env(\"{}\") = \"{}\"", name, value),
+            Self::Target { key, value } => write!(f, "target_{} = \"{}\"", key.name(), value),
+            Self::KeyValue { key, value } => write!(f, "{} = \"{}\"", key, value),
+            Self::Flag(name) => write!(f, "{}", name),
+            Self::Version(min) => write!(f, "version(\"{}\")", min),
+        }
+    }
+}
+
+impl fmt::Display for CfgExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list(f: &mut fmt::Formatter<'_>, name: &str, items: &[CfgExpr]) -> fmt::Result {
+            write!(f, "{}(", name)?;
+            for (i, item) in items.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", item)?;
+            }
+            write!(f, ")")
+        }
+        match self {
+            Self::Predicate(o) => write!(f, "{}", o),
+            Self::All(v) => list(f, "all", v),
+            Self::Any(v) => list(f, "any", v),
+            Self::Not(v) => write!(f, "not({})", v),
         }
     }
 }
 
 enum CfgTag {
-    Start(Group),
-    End,
+    /// Predicate and its span in parsed line
+    Start(CfgExpr, Range<usize>),
+    /// Same as start, but only enabled if no previous branch of chain is
+    ElseIf(CfgExpr, Range<usize>),
+    Else,
+    /// With optional predicate echoed from start tag, `[cfg(end feature = "a")]`
+    End(Option<CfgExpr>),
+    /// Applies only to the next non-blank line, no end tag needed
+    Line(CfgExpr, Range<usize>),
+    /// Applies to the rest of file, only allowed on the first line
+    File(CfgExpr, Range<usize>),
+}
+
+impl CfgTag {
+    fn predicate(&self) -> Option<&CfgExpr> {
+        match self {
+            Self::Start(g, _)
+            | Self::ElseIf(g, _)
+            | Self::Line(g, _)
+            | Self::File(g, _)
+            | Self::End(Some(g)) => Some(g),
+            Self::Else | Self::End(None) => None,
+        }
+    }
+    /// Adds names predicate refers to to `out`
+    fn record_names(&self, out: &mut Referenced) {
+        if let Some(group) = self.predicate() {
+            group.each_predicate(&mut |p| match p {
+                Predicate::Feature(f) if !out.features.contains(f) => {
+                    out.features.insert(f.clone());
+                }
+                Predicate::FeatureGlob(glob) if !out.features.contains(glob.as_str()) => {
+                    out.features.insert(glob.as_str().to_owned());
+                }
+                Predicate::Env { name, .. } if !out.env.contains(name) => {
+                    out.env.insert(name.clone());
+                }
+                _ => {}
+            });
+        }
+    }
+}
+
+/// Predicates with own syntax, which can't be used as names of flags or keys
+const RESERVED_NAMES: [&str; 3] = ["feature", "env", "version"];
+const RESERVED_EXPECTED: &str = "name other than feature, env or version";
+
+/// Name of flag or key, checked once whole predicate is parsed, so error points past it
+fn not_reserved(name: &str) -> Result<&str, &'static str> {
+    match RESERVED_NAMES.contains(&name) {
+        true => Err(RESERVED_EXPECTED),
+        false => Ok(name),
+    }
+}
+
+/// Names tags of file refer to, collected while processing it
+#[derive(Default)]
+pub(crate) struct Referenced {
+    /// Feature names and glob patterns
+    pub features: HashSet<String>,
+    /// Variables of `env("NAME")`
+    pub env: HashSet<String>,
 }
 
 peg::parser! {
     grammar cfg() for str {
-        pub(crate) rule cfg() -> CfgTag
-            = "[" _ "cfg" _ "(" _ "end" _ ")" _ "]" {CfgTag::End}
-            / "[" _ "cfg" _ "(" _ p:pred() _ ")" _ "]" {CfgTag::Start(p)}
+        pub(crate) rule cfg(desc: &LangDesc, strict: bool) -> CfgTag
+            = tag_open(desc) _ keyword(desc) _ "(" _ "end" _ ")" _ tag_close(desc) {CfgTag::End(None)}
+            / tag_open(desc) _ keyword(desc) _ "(" _ "end" [' ' | '\t']+ p:pred(strict) _ ")" _ tag_close(desc) {CfgTag::End(Some(p))}
+            / tag_open(desc) _ keyword(desc) _ "(" _ "else" _ ")" _ tag_close(desc) {CfgTag::Else}
+            / tag_open(desc) _ keyword(desc) _ "(" _ "else" [' ' | '\t']+ "if" [' ' | '\t']+ s:position!() p:pred(strict) e:position!() _ ")" _ tag_close(desc) {CfgTag::ElseIf(p, s..e)}
+            / tag_open(desc) _ keyword(desc) _ "(" _ s:position!() p:pred(strict) e:position!() _ ")" _ tag_close(desc) {CfgTag::Start(p, s..e)}
+            / tag_open(desc) _ suffixed_keyword(desc, "_line") _ "(" _ s:position!() p:pred(strict) e:position!() _ ")" _ tag_close(desc) {CfgTag::Line(p, s..e)}
+            / tag_open(desc) _ suffixed_keyword(desc, "_file") _ "(" _ s:position!() p:pred(strict) e:position!() _ ")" _ tag_close(desc) {CfgTag::File(p, s..e)}
+
+        pub(crate) rule expr() -> CfgExpr
+            = _ p:pred(false) _ {p}
+
+        pub(crate) rule tag_start(desc: &LangDesc)
+            = tag_open(desc) _ (keyword(desc) / suffixed_keyword(desc, "_line") / suffixed_keyword(desc, "_file")) _ "(" [_]*
+
+        rule tag_open(desc: &LangDesc)
+            = d:$([_]*<{desc.tag_open.chars().count()}>) {?
+                if d == desc.tag_open { Ok(()) } else { Err("cfg tag opening") }
+            }
+        rule tag_close(desc: &LangDesc)
+            = d:$([_]*<{desc.tag_close.chars().count()}>) {?
+                if d == desc.tag_close { Ok(()) } else { Err("cfg tag closing") }
+            }
+        rule keyword(desc: &LangDesc)
+            = k:$(['a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-']+) {?
+                if k == desc.cfg_keyword { Ok(()) } else { Err("cfg keyword") }
+            }
+        rule suffixed_keyword(desc: &LangDesc, suffix: &str)
+            = k:$(['a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-']+) {?
+                if k.strip_suffix(suffix) == Some(desc.cfg_keyword.as_str()) { Ok(()) } else { Err("cfg keyword with suffix") }
+            }
+
+        rule string() -> String
+            = "\"" s:$((!['"'] [_])*) "\"" {s.to_owned()}
 
         rule opt() -> Predicate
-            = "feature" _ "=" _ "\"" s:$((!['"'] [_])*) "\"" {Predicate::Feature(s.to_owned())}
+            = "feature" _ "=" _ s:string() {Predicate::Feature(s)}
+            / "feature" _ "~" _ s:string() {?
+                FeatureGlob::new(s).map(Predicate::FeatureGlob).map_err(|_| "valid glob pattern")
+            }
+            / "env" _ "(" _ name:string() _ ")" value:(_ "=" _ v:string() {v})? {Predicate::Env { name, value }}
+            / "version" _ "(" _ v:string() _ ")" {? v.parse().map(Predicate::Version).map_err(|_| "version number") }
+            / "target_" key:$("os" / "arch" / "family" / "env" / "vendor" / "endian" / "pointer_width") _ "=" _ value:string() {?
+                TargetKey::from_name(key).map(|key| Predicate::Target { key, value }).ok_or("target key")
+            }
+            / key:ident() _ "=" _ value:string() {?
+                not_reserved(key).map(|key| Predicate::KeyValue { key: key.to_owned(), value })
+            }
+            / name:ident() !(_ "(") {?
+                not_reserved(name).map(|name| Predicate::Flag(name.to_owned()))
+            }
+
+        rule ident() -> &'input str
+            = $(['a'..='z' | 'A'..='Z' | '_'] ['a'..='z' | 'A'..='Z' | '0'..='9' | '_']*)
 
-        rule pred() -> Group
-            = "any" _ "(" _ l:pred_list() _ ")" {Group::Any(l)}
-            / "all" _ "(" _ l:pred_list() _ ")" {Group::All(l)}
-            / "not" _ "(" _ p:pred() _ ")" {Group::Not(Box::new(p))}
-            / o:opt() {Group::Option(o)}
+        rule pred(strict: bool) -> CfgExpr
+            = "any" _ "(" _ l:pred_list(strict) _ ")" {CfgExpr::Any(l)}
+            / "all" _ "(" _ l:pred_list(strict) _ ")" {CfgExpr::All(l)}
+            / "not" _ "(" _ p:pred(strict) _ ")" {CfgExpr::Not(Box::new(p))}
+            / o:opt() {CfgExpr::Predicate(o)}
 
         rule list_sep() = _ "," _
-        rule pred_list() -> Vec<Group>
-            = l:pred()**list_sep() list_sep()? {l}
+        rule trailing_sep(strict: bool)
+            = list_sep() {? if strict { Err("no trailing comma") } else { Ok(()) } }
+        rule pred_list(strict: bool) -> Vec<CfgExpr>
+            = l:pred(strict)**list_sep() trailing_sep(strict)? {l}
 
             rule _ = [' ' | '\t']*
     }
 }
 
+/// Parses predicate as written inside of cfg tag, i.e `all(feature = "a", not(test))`
+///
+/// ```
+/// let expr = cfgcomment_core::parse_cfg("all( feature=\"a\", not(test), )").unwrap();
+/// assert_eq!(expr.to_string(), "all(feature = \"a\", not(test))");
+/// ```
+pub fn parse_cfg(src: &str) -> Result<CfgExpr, CfgCommentError> {
+    cfg::expr(src).map_err(|e| CfgCommentError::InvalidPredicate {
+        column: e.location.column,
+        expected: e.expected.to_string(),
+        text: src.to_owned(),
+    })
+}
+
+impl FromStr for CfgExpr {
+    type Err = CfgCommentError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_cfg(s)
+    }
+}
+
 fn split_at_ws_end(i: &str) -> (&str, &str) {
     let idx = i
         .bytes()
@@ -83,156 +871,1230 @@ fn split_at_ws_end(i: &str) -> (&str, &str) {
     i.split_at(idx)
 }
 
-#[derive(Default, Clone)]
-struct CfgState(Rc<RefCell<Vec<(bool, String)>>>);
+/// Text which starts like a cfg tag (`[cfg(`), but fails to parse is reported as error,
+/// anything else (`[derive(Debug)]`) is ordinary content
+fn invalid_tag(
+    text: &str,
+    err: peg::error::ParseError<peg::str::LineCol>,
+    desc: &LangDesc,
+    line_idx: usize,
+    column_offset: usize,
+) -> Option<CfgCommentError> {
+    cfg::tag_start(text, desc).ok()?;
+    Some(CfgCommentError::InvalidTag {
+        line: line_idx + 1,
+        column: column_offset + err.location.column,
+        expected: err.expected.to_string(),
+        text: text.to_owned(),
+    })
+}
+
+/// Returns leading whitespace and parsed tag, if line is a cfg tag.
+/// Lines which only look like tags (`//[derive(Debug)]`) are ordinary content,
+/// but malformed cfg tags are reported as errors
+fn parse_tag<'l>(
+    line: &'l str,
+    line_idx: usize,
+    desc: &LangDesc,
+    strict: bool,
+) -> Result<Option<(&'l str, CfgTag)>, CfgCommentError> {
+    let (ws, tag) = split_at_ws_end(line.trim_end());
+    if !tag.starts_with(&desc.cfg_prefix)
+        || !tag.ends_with(&desc.cfg_suffix)
+        || tag.len() < desc.cfg_prefix.len() + desc.cfg_suffix.len()
+    {
+        return Ok(None);
+    }
+    let offset = ws.len() + desc.cfg_prefix_comment_len;
+    let text = &tag[desc.cfg_prefix_comment_len..tag.len() - desc.cfg_suffix_comment_len];
+    let tag = match cfg::cfg(text, desc, strict) {
+        Ok(CfgTag::Start(group, span)) => {
+            CfgTag::Start(group, span.start + offset..span.end + offset)
+        }
+        Ok(CfgTag::ElseIf(group, span)) => {
+            CfgTag::ElseIf(group, span.start + offset..span.end + offset)
+        }
+        Ok(CfgTag::Line(group, span)) => {
+            CfgTag::Line(group, span.start + offset..span.end + offset)
+        }
+        Ok(CfgTag::File(group, span)) => {
+            CfgTag::File(group, span.start + offset..span.end + offset)
+        }
+        Ok(tag) => tag,
+        Err(e) => {
+            return match invalid_tag(text, e, desc, line_idx, line[..offset].chars().count()) {
+                Some(e) => Err(e),
+                None => Ok(None),
+            }
+        }
+    };
+    Ok(Some((ws, tag)))
+}
+
+struct Frame {
+    /// Whether current branch of block is enabled
+    enabled: bool,
+    /// Whether any branch of `if`/`else if` chain was enabled so far
+    matched: bool,
+    /// Indentation of start tag
+    prefix: String,
+    /// Inside of `[cfg(else)]` branch
+    in_else: bool,
+    /// Line of start tag, 1-based
+    line: usize,
+    /// Predicate of start tag, for `[cfg(end ...)]` verification
+    predicate: CfgExpr,
+    kind: FrameKind,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FrameKind {
+    /// Closed by `[cfg(end)]`
+    Block,
+    /// Started by `[cfg_line(...)]`, dropped after the next non-blank line
+    Line,
+    /// Started by `[cfg_file(...)]`, lasts until end of file
+    File,
+}
+
+impl Frame {
+    /// Switches frame to the branch started by `[cfg(else)]` or `[cfg(else if ...)]`
+    fn next_branch(&mut self, tag: &CfgTag, config: &Data, line: usize) -> Result<(), CfgCommentError> {
+        if self.in_else {
+            return Err(CfgCommentError::DuplicateElse { line });
+        }
+        self.enabled = !self.matched
+            && match tag {
+                CfgTag::ElseIf(c, _) => c.evaluate(config),
+                _ => true,
+            };
+        self.matched |= self.enabled;
+        self.in_else = matches!(tag, CfgTag::Else);
+        Ok(())
+    }
+    /// Verifies predicate repeated in `[cfg(end ...)]` against the start tag
+    fn check_end(&self, echo: Option<CfgExpr>, line: usize) -> Result<(), CfgCommentError> {
+        match echo {
+            Some(echo) if echo != self.predicate => Err(CfgCommentError::MismatchedEnd {
+                line,
+                start_line: self.line,
+                start: self.predicate.to_string(),
+                end: echo.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct CfgState {
+    frames: Vec<Frame>,
+    /// Names tags refer to so far
+    referenced: Referenced,
+    /// Branches and lines processed so far, only recorded for visitors
+    events: Option<VecDeque<Event>>,
+}
 impl CfgState {
+    /// Records event built from current state, if events are recorded at all
+    fn record(&mut self, event: impl FnOnce(&Self) -> Option<Event>) {
+        if self.events.is_some() {
+            let event = event(self);
+            self.events.get_or_insert_with(VecDeque::new).extend(event);
+        }
+    }
+    /// Branch of innermost frame, just started by tag on line `i`
+    fn branch(
+        &self,
+        i: usize,
+        kind: BranchKind,
+        group: Option<&CfgExpr>,
+        earlier_branch_matched: bool,
+        config: &Data,
+    ) -> Option<Event> {
+        let (frame, enclosing) = self.frames.split_last()?;
+        let enclosing_enabled = enclosing.iter().all(|f| f.enabled);
+        Some(Event::Enter(Branch {
+            line: i + 1,
+            kind,
+            nesting_depth: enclosing.len(),
+            evaluation: group.map(|g| g.explain(config)),
+            enclosing_enabled,
+            earlier_branch_matched,
+            enabled: enclosing_enabled && frame.enabled,
+        }))
+    }
     fn enabled(&self) -> bool {
-        self.0.borrow().iter().all(|(p, _)| *p)
+        self.frames.iter().all(|f| f.enabled)
     }
-    fn prefix(&self) -> String {
-        self.0
-            .borrow()
-            .iter()
+    fn prefix(&self) -> &str {
+        self.frames.last().map_or("", |f| &f.prefix)
+    }
+    fn push(&mut self, frame: Frame) {
+        self.frames.push(frame)
+    }
+    fn pop(&mut self) -> Option<Frame> {
+        self.frames.pop()
+    }
+    /// Line of `[cfg_line(...)]` tag still waiting for its line
+    fn pending_line(&self) -> Option<usize> {
+        self.frames
             .last()
-            .map(|(_, p)| p.to_owned())
-            .unwrap_or_else(|| "".to_owned())
+            .filter(|f| f.kind == FrameKind::Line)
+            .map(|f| f.line)
     }
-    fn push(&self, state: (bool, String)) {
-        self.0.borrow_mut().push(state)
+    fn top_kind(&self) -> Option<FrameKind> {
+        self.frames.last().map(|f| f.kind)
+    }
+    fn top_mut<T>(&mut self, f: impl FnOnce(&mut Frame) -> T) -> Option<T> {
+        self.frames.last_mut().map(f)
+    }
+    /// Reports innermost tag left open at the end of file
+    fn check_closed(&self) -> Result<(), CfgCommentError> {
+        if let Some(Frame {
+            kind: FrameKind::Line,
+            line,
+            ..
+        }) = self.frames.last()
+        {
+            return Err(CfgCommentError::DanglingLineTag { line: *line });
+        }
+        let mut open = self
+            .frames
+            .iter()
+            .filter(|f| f.kind == FrameKind::Block)
+            .map(|f| f.line);
+        match open.next_back() {
+            Some(line) => Err(CfgCommentError::Unclosed {
+                line,
+                outer: open.collect(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Misaligned tags are reported as warnings, or as errors in strict mode
+fn check_indent(start: &str, ws: &str, line: usize, strict: bool) -> Result<(), CfgCommentError> {
+    if start == ws {
+        return Ok(());
     }
-    fn pop(&self) -> Option<()> {
-        self.0.borrow_mut().pop().map(|_| ())
+    let err = CfgCommentError::MismatchedIndent {
+        line,
+        start: start.to_owned(),
+        end: ws.to_owned(),
+    };
+    if strict {
+        return Err(err);
     }
+    log::warn!("{}", err);
+    Ok(())
 }
 
-#[derive(Clone)]
+#[derive(Clone, Hash, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct LangDesc {
     pub cfg_prefix: String,
     pub cfg_prefix_comment_len: usize,
     pub cfg_suffix: String,
+    /// Length of comment syntax at the end of `cfg_suffix`, 2 for `]*/`
+    #[serde(default)]
+    pub cfg_suffix_comment_len: usize,
+    /// Tag keyword, `cfg` in `[cfg(feature = "a")]`
+    #[serde(default = "default_cfg_keyword")]
+    pub cfg_keyword: String,
+    /// Opening token of cfg tag, `[` in `[cfg(feature = "a")]`, may be empty for
+    /// `#pragma cfg(feature = "a")`-like syntax
+    #[serde(default = "default_tag_open")]
+    pub tag_open: String,
+    /// Closing token of cfg tag, `]` in `[cfg(feature = "a")]`
+    #[serde(default = "default_tag_close")]
+    pub tag_close: String,
+    /// Marker of disabled line, or opening marker of disabled run of lines if
+    /// `comment_end` is set
     pub comment: String,
+    /// Closing marker for languages without line comments, whole run of disabled lines
+    /// between two tags is wrapped in `comment`/`comment_end` pair
+    #[serde(default)]
+    pub comment_end: Option<String>,
+    /// Wrap every disabled line in `comment`/`comment_end` pair instead of the whole run,
+    /// i.e `<!--# <br> #-->`
+    #[serde(default)]
+    pub wrap_lines: bool,
+    /// Markers for cfg tags inside of line, `None` if language has no block comments
+    #[serde(default)]
+    pub inline: Option<InlineDesc>,
+}
+
+fn default_cfg_keyword() -> String {
+    "cfg".to_owned()
+}
+fn default_tag_open() -> String {
+    "[".to_owned()
+}
+fn default_tag_close() -> String {
+    "]".to_owned()
+}
+
+/// Block comment syntax used for inline regions, i.e `foo(/*[cfg(feature = "a")]*/ a, /*[cfg(end)]*/)`
+#[derive(Clone, Hash, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InlineDesc {
+    /// Opening of block comment, `/*`
+    pub open: String,
+    /// Closing of block comment, `*/`
+    pub close: String,
+    /// Written after `open` to mark disabled segment, `#` in `/*# a, */`
+    pub disabled: String,
+}
+
+impl InlineDesc {
+    /// Disabled segments are marked with `#`, i.e `/*# a, */`
+    pub fn new(open: impl Into<String>, close: impl Into<String>) -> Self {
+        Self {
+            open: open.into(),
+            close: close.into(),
+            disabled: "#".to_owned(),
+        }
+    }
 }
 
 impl LangDesc {
-    pub fn default_list() -> HashMap<String, Self> {
-        let c_like = LangDesc {
-            cfg_prefix: "//[".to_owned(),
-            cfg_prefix_comment_len: 2,
+    /// Language with line comments, `LangDesc::new("//")` uses `//[cfg(...)]` tags and
+    /// `//# ` for disabled lines
+    pub fn new(line_comment: impl Into<String>) -> Self {
+        let line_comment = line_comment.into();
+        Self {
+            cfg_prefix: format!("{}[", line_comment),
+            cfg_prefix_comment_len: line_comment.len(),
             cfg_suffix: "]".to_owned(),
-            comment: "//# ".to_owned(),
+            cfg_suffix_comment_len: 0,
+            cfg_keyword: "cfg".to_owned(),
+            tag_open: default_tag_open(),
+            tag_close: default_tag_close(),
+            comment: format!("{}# ", line_comment),
+            comment_end: None,
+            wrap_lines: false,
+            inline: None,
+        }
+    }
+
+    /// Language with block comments only, `LangDesc::block("/*", "*/")` uses `/*[cfg(...)]*/`
+    /// tags and wraps disabled runs in `/*# ` and ` #*/`
+    pub fn block(open: impl Into<String>, close: impl Into<String>) -> Self {
+        let (open, close) = (open.into(), close.into());
+        Self {
+            cfg_prefix: format!("{}[", open),
+            cfg_prefix_comment_len: open.len(),
+            cfg_suffix: format!("]{}", close),
+            cfg_suffix_comment_len: close.len(),
+            cfg_keyword: "cfg".to_owned(),
+            tag_open: default_tag_open(),
+            tag_close: default_tag_close(),
+            comment: format!("{}# ", open),
+            comment_end: Some(format!(" #{}", close)),
+            wrap_lines: false,
+            inline: None,
+        }
+    }
+
+    /// Token ending block comment, `*/` for `LangDesc::block("/*", "*/")`, taken from comment
+    /// part of `cfg_suffix`, or trimmed `comment_end` if suffix has none
+    fn comment_closer(&self) -> &str {
+        let suffix = self
+            .cfg_suffix
+            .len()
+            .checked_sub(self.cfg_suffix_comment_len)
+            .and_then(|start| self.cfg_suffix.get(start..))
+            .filter(|s| !s.is_empty());
+        suffix.unwrap_or_else(|| self.comment_end.as_deref().unwrap_or_default().trim())
+    }
+
+    pub fn with_cfg_prefix(mut self, cfg_prefix: impl Into<String>, comment_len: usize) -> Self {
+        self.cfg_prefix = cfg_prefix.into();
+        self.cfg_prefix_comment_len = comment_len;
+        self
+    }
+    pub fn with_cfg_suffix(mut self, cfg_suffix: impl Into<String>, comment_len: usize) -> Self {
+        self.cfg_suffix = cfg_suffix.into();
+        self.cfg_suffix_comment_len = comment_len;
+        self
+    }
+    pub fn with_keyword(mut self, keyword: impl Into<String>) -> Self {
+        self.cfg_keyword = keyword.into();
+        self
+    }
+    /// Replaces `[`/`]` around cfg tags, comment syntax of `cfg_prefix`/`cfg_suffix` is kept,
+    /// i.e `LangDesc::new("//").with_tag_delimiters("@", "")` uses `//@cfg(...)` tags
+    pub fn with_tag_delimiters(mut self, open: impl Into<String>, close: impl Into<String>) -> Self {
+        let (open, close) = (open.into(), close.into());
+        let prefix_comment = &self.cfg_prefix[..self.cfg_prefix_comment_len];
+        let suffix_comment = &self.cfg_suffix[self.cfg_suffix.len() - self.cfg_suffix_comment_len..];
+        self.cfg_prefix = format!("{}{}", prefix_comment, open);
+        self.cfg_suffix = format!("{}{}", close, suffix_comment);
+        self.tag_open = open;
+        self.tag_close = close;
+        self
+    }
+    pub fn with_comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = comment.into();
+        self
+    }
+    pub fn with_comment_end(mut self, comment_end: impl Into<String>) -> Self {
+        self.comment_end = Some(comment_end.into());
+        self
+    }
+    pub fn with_wrap_lines(mut self, wrap_lines: bool) -> Self {
+        self.wrap_lines = wrap_lines;
+        self
+    }
+    pub fn with_inline(mut self, inline: InlineDesc) -> Self {
+        self.inline = Some(inline);
+        self
+    }
+
+    /// Empty or whitespace-only comment marker makes disabled lines indistinguishable
+    /// from enabled ones. Processing fails with such language too
+    pub fn validate(&self) -> Result<(), CfgCommentError> {
+        for comment in std::iter::once(&self.comment).chain(&self.comment_end) {
+            if comment.trim().is_empty() {
+                return Err(CfgCommentError::BlankComment(comment.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn default_list() -> HashMap<String, Self> {
+        let c_like = LangDesc::new("//").with_inline(InlineDesc::new("/*", "*/"));
+        let hash = LangDesc::new("#").with_comment("#- ");
+        let markup = LangDesc::block("<!--", "-->").with_wrap_lines(true);
+        let langs: [(&[&str], LangDesc); 4] = [
+            (&["rs", "js", "ts", "c", "h", "cpp", "hpp"], c_like),
+            (&["toml", "yml", "yaml", "py", "sh"], hash),
+            (&["css"], LangDesc::block("/*", "*/")),
+            (&["html", "xml", "svg", "vue"], markup),
+        ];
+        IntoIterator::into_iter(langs)
+            .flat_map(|(exts, desc)| exts.iter().map(move |ext| (ext.to_string(), desc.clone())))
+            .collect()
+    }
+}
+
+/// Finds next inline tag, returning its span (including comment markers) and parsed tag
+fn next_inline_tag(
+    line: &str,
+    from: usize,
+    line_idx: usize,
+    inline: &InlineDesc,
+    desc: &LangDesc,
+    strict: bool,
+) -> Result<Option<(Range<usize>, CfgTag)>, CfgCommentError> {
+    for (start, _) in line[from..].match_indices(&inline.open) {
+        let start = from + start;
+        let text_start = start + inline.open.len();
+        let text_end = match line[text_start..].find(&inline.close) {
+            Some(v) => text_start + v,
+            None => break,
         };
-        std::array::IntoIter::new([
-            (
-                "rs".to_owned(),
-                c_like.clone(),
-            ),
-            (
-                "js".to_owned(),
-                c_like.clone(),
-            ),
-            (
-                "ts".to_owned(),
-                c_like.clone(),
-            ),
-            (
-                "toml".to_owned(),
-                LangDesc {
-                    cfg_prefix: "#[".to_owned(),
-                    cfg_prefix_comment_len: 1,
-                    cfg_suffix: "]".to_owned(),
-                    comment: "#- ".to_owned(),
-                },
-            ),
-        ])
-        .collect()
+        let text = &line[text_start..text_end];
+        match cfg::cfg(text, desc, strict) {
+            // Single-line tags make no sense inside of line
+            Ok(CfgTag::Line(_, _)) | Ok(CfgTag::File(_, _)) => {}
+            Ok(tag) => return Ok(Some((start..text_end + inline.close.len(), tag))),
+            Err(e) => {
+                let column_offset = line[..text_start].chars().count();
+                if let Some(e) = invalid_tag(text, e, desc, line_idx, column_offset) {
+                    return Err(e);
+                }
+            }
+        }
     }
+    Ok(None)
 }
 
-pub fn process(
-    read: impl Iterator<Item = String>,
+/// Comments or uncomments segment between two inline tags, surrounding whitespace is kept as is
+fn toggle_inline(
+    segment: &str,
+    line_idx: usize,
+    should_be: bool,
+    inline: &InlineDesc,
+) -> Result<String, CfgCommentError> {
+    let (before, rest) = split_at_ws_end(segment);
+    let core = rest.trim_end();
+    let after = &rest[core.len()..];
+    if core.is_empty() {
+        return Ok(segment.to_owned());
+    }
+    let disabled_start = format!("{}{} ", inline.open, inline.disabled);
+    let disabled_end = format!(" {}", inline.close);
+    let disabled = core
+        .strip_prefix(&disabled_start)
+        .and_then(|s| s.strip_suffix(&disabled_end));
+    Ok(match (disabled, should_be) {
+        (Some(inner), true) => format!("{}{}{}", before, inner, after),
+        (None, false) if core.contains(&inline.close) => {
+            return Err(CfgCommentError::CommentCloser {
+                line: line_idx + 1,
+                close: inline.close.clone(),
+            })
+        }
+        (None, false) => format!("{}{}{}{}{}", before, disabled_start, core, disabled_end, after),
+        _ => segment.to_owned(),
+    })
+}
+
+/// Toggles segments of line guarded by inline tags, tags should be closed on the same line
+fn process_inline<'a>(
+    line: Cow<'a, str>,
+    line_idx: usize,
+    outer_enabled: bool,
+    config: &Data,
+    desc: &LangDesc,
+    referenced: &mut Referenced,
+) -> Result<Cow<'a, str>, CfgCommentError> {
+    let inline = match &desc.inline {
+        Some(v) => v,
+        None => return Ok(line),
+    };
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut out = String::new();
+    let mut pos = 0;
+    while let Some((span, tag)) = next_inline_tag(&line, pos, line_idx, inline, desc, config.strict)? {
+        let segment = &line[pos..span.start];
+        if frames.is_empty() {
+            out.push_str(segment);
+        } else {
+            let should_be = config.reset || outer_enabled && frames.iter().all(|f| f.enabled);
+            out.push_str(&toggle_inline(segment, line_idx, should_be, inline)?);
+        }
+        out.push_str(&line[span.clone()]);
+        config.check_declared(&tag, line_idx + 1)?;
+        tag.record_names(referenced);
+        match tag {
+            CfgTag::Start(c, _) => {
+                let enabled = c.evaluate(config);
+                frames.push(Frame {
+                    enabled,
+                    matched: enabled,
+                    prefix: String::new(),
+                    in_else: false,
+                    line: line_idx + 1,
+                    predicate: c,
+                    kind: FrameKind::Block,
+                });
+            }
+            CfgTag::ElseIf(_, _) | CfgTag::Else => frames
+                .last_mut()
+                .ok_or(CfgCommentError::UnexpectedElse { line: line_idx + 1 })?
+                .next_branch(&tag, config, line_idx + 1)?,
+            CfgTag::End(echo) => frames
+                .pop()
+                .ok_or(CfgCommentError::UnexpectedEnd { line: line_idx + 1 })?
+                .check_end(echo, line_idx + 1)?,
+            CfgTag::Line(_, _) | CfgTag::File(_, _) => unreachable!("filtered by next_inline_tag"),
+        }
+        pos = span.end;
+    }
+    if !frames.is_empty() {
+        return Err(CfgCommentError::Unclosed {
+            line: line_idx + 1,
+            outer: Vec::new(),
+        });
+    }
+    if pos == 0 {
+        return Ok(line);
+    }
+    out.push_str(&line[pos..]);
+    Ok(Cow::Owned(out))
+}
+
+/// Content lines between two tags, buffered for languages with block comments,
+/// as the whole run is wrapped in a single comment
+#[derive(Default)]
+struct Run<'a> {
+    /// Index of first buffered line
+    start: usize,
+    lines: Vec<Cow<'a, str>>,
+    prefix: String,
+    should_be: bool,
+}
+impl<'a> Run<'a> {
+    fn push(&mut self, i: usize, line: Cow<'a, str>, state: &CfgState, config: &Data) {
+        if self.lines.is_empty() {
+            self.start = i;
+            self.prefix = state.prefix().to_owned();
+            self.should_be = config.reset || state.enabled();
+        }
+        self.lines.push(line);
+    }
+    /// Wraps buffered lines in `open`/`close` markers, or unwraps them
+    fn flush(
+        &mut self,
+        desc: &LangDesc,
+        close: &str,
+        out: &mut Vec<Cow<'a, str>>,
+    ) -> Result<(), CfgCommentError> {
+        let open = desc.comment.as_str();
+        let mut lines = std::mem::take(&mut self.lines);
+        let mut non_blank = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty())
+            .map(|(i, _)| i);
+        let first = match non_blank.next() {
+            Some(v) => v,
+            None => {
+                out.extend(lines);
+                return Ok(());
+            }
+        };
+        let last = non_blank.next_back().unwrap_or(first);
+        let prefix = self.prefix.len();
+        let body = lines[first]
+            .strip_prefix(&self.prefix)
+            .ok_or_else(|| CfgCommentError::Underindented {
+                line: self.start + first + 1,
+                prefix: self.prefix.clone(),
+            })?;
+        let enabled = !(body.starts_with(open)
+            && lines[last].ends_with(close)
+            && (first != last || body.len() >= open.len() + close.len()));
+
+        if !enabled && self.should_be {
+            let len = lines[last].len();
+            lines[last].to_mut().truncate(len - close.len());
+            lines[first].to_mut().replace_range(prefix..prefix + open.len(), "");
+        } else if enabled && !self.should_be {
+            let closer = desc.comment_closer();
+            if let Some(i) = lines.iter().position(|l| l.contains(closer)) {
+                return Err(CfgCommentError::CommentCloser {
+                    line: self.start + i + 1,
+                    close: closer.to_owned(),
+                });
+            }
+            lines[first].to_mut().insert_str(prefix, open);
+            lines[last].to_mut().push_str(close);
+        }
+        out.extend(lines);
+        Ok(())
+    }
+}
+
+fn process_tag(
+    i: usize,
+    ws: &str,
+    parsed: CfgTag,
+    state: &mut CfgState,
+    config: &Data,
+) -> Result<(), CfgCommentError> {
+    if let Some(line) = state.pending_line() {
+        return Err(CfgCommentError::DanglingLineTag { line });
+    }
+    config.check_declared(&parsed, i + 1)?;
+    parsed.record_names(&mut state.referenced);
+    match parsed {
+        CfgTag::File(_, _) if i != 0 => Err(CfgCommentError::MisplacedFileTag { line: i + 1 }),
+        CfgTag::Start(ref c, _) | CfgTag::Line(ref c, _) | CfgTag::File(ref c, _) => {
+            let enabled = c.evaluate(config);
+            let (kind, branch) = match parsed {
+                CfgTag::Line(_, _) => (FrameKind::Line, BranchKind::Line),
+                CfgTag::File(_, _) => (FrameKind::File, BranchKind::File),
+                _ => (FrameKind::Block, BranchKind::Start),
+            };
+            state.push(Frame {
+                enabled,
+                matched: enabled,
+                prefix: ws.to_owned(),
+                in_else: false,
+                line: i + 1,
+                predicate: c.clone(),
+                kind,
+            });
+            state.record(|state| state.branch(i, branch, Some(c), false, config));
+            Ok(())
+        }
+        CfgTag::ElseIf(_, _) | CfgTag::Else if state.top_kind() == Some(FrameKind::File) => {
+            Err(CfgCommentError::UnexpectedElse { line: i + 1 })
+        }
+        CfgTag::End(_) if state.top_kind() == Some(FrameKind::File) => {
+            Err(CfgCommentError::UnexpectedEnd { line: i + 1 })
+        }
+        CfgTag::ElseIf(_, _) | CfgTag::Else => {
+            let (start, matched) = state
+                .top_mut(|frame| {
+                    let matched = frame.matched;
+                    frame
+                        .next_branch(&parsed, config, i + 1)
+                        .map(|()| (frame.prefix.clone(), matched))
+                })
+                .ok_or(CfgCommentError::UnexpectedElse { line: i + 1 })??;
+            let (kind, group) = match &parsed {
+                CfgTag::ElseIf(c, _) => (BranchKind::ElseIf, Some(c)),
+                _ => (BranchKind::Else, None),
+            };
+            state.record(|_| Some(Event::Exit));
+            state.record(|state| state.branch(i, kind, group, matched, config));
+            check_indent(&start, ws, i + 1, config.strict)
+        }
+        CfgTag::End(echo) => {
+            let start = state
+                .pop()
+                .ok_or(CfgCommentError::UnexpectedEnd { line: i + 1 })?;
+            check_indent(&start.prefix, ws, i + 1, config.strict)?;
+            start.check_end(echo, i + 1)
+        }
+    }
+}
+
+/// Pushes processed lines to `out`, which may be delayed until the end of run for
+/// block comments. Unchanged lines are passed through without copying
+fn process_line<'a>(
+    i: usize,
+    s: Cow<'a, str>,
+    state: &mut CfgState,
+    run: &mut Run<'a>,
+    config: &Data,
+    desc: &LangDesc,
+    out: &mut Vec<Cow<'a, str>>,
+) -> Result<(), CfgCommentError> {
+    let enabled = config.reset || state.enabled();
+    if let Some((ws, parsed)) = parse_tag(&s, i, desc, config.strict)? {
+        if let Some(close) = &desc.comment_end {
+            run.flush(desc, close, out)?;
+        }
+        let end = matches!(parsed, CfgTag::End(_));
+        process_tag(i, ws, parsed, state, config)?;
+        state.record(|_| Some(Event::Line { tag: true, enabled }));
+        if end {
+            state.record(|_| Some(Event::Exit));
+        }
+        out.push(s);
+        return Ok(());
+    }
+    state.record(|_| Some(Event::Line { tag: false, enabled }));
+    if let Some(close) = &desc.comment_end {
+        let blank = s.trim().is_empty();
+        run.push(i, s, state, config);
+        if !blank && state.pending_line().is_some() {
+            state.pop();
+            state.record(|_| Some(Event::Exit));
+            return run.flush(desc, close, out);
+        }
+        if !blank && desc.wrap_lines {
+            return run.flush(desc, close, out);
+        }
+        return Ok(());
+    }
+
+    if s.trim().is_empty() {
+        out.push(s);
+        return Ok(());
+    }
+    let prefix = state.prefix();
+    let trimmed = s
+        .strip_prefix(prefix)
+        .ok_or_else(|| CfgCommentError::Underindented {
+            line: i + 1,
+            prefix: prefix.to_owned(),
+        })?;
+    let enabled = !trimmed.starts_with(&desc.comment);
+    let should_be = config.reset || state.enabled();
+
+    log::trace!("{} {:?} {:?}", trimmed, enabled, should_be);
+    let line = if !enabled && should_be {
+        Cow::Owned(format!("{}{}", prefix, &trimmed[desc.comment.len()..]))
+    } else if enabled && !should_be {
+        Cow::Owned(format!("{}{}{}", prefix, desc.comment, trimmed))
+    } else {
+        s
+    };
+    let enabled = state.enabled();
+    out.push(process_inline(line, i, enabled, config, desc, &mut state.referenced)?);
+    if state.pending_line().is_some() {
+        state.pop();
+        state.record(|_| Some(Event::Exit));
+    }
+    Ok(())
+}
+
+/// Line-by-line state machine behind [`process`], `process_async` and the memory-mapped
+/// file path
+#[derive(Default)]
+struct Processor<'a> {
+    state: CfgState,
+    run: Run<'a>,
+    line: usize,
+}
+impl<'a> Processor<'a> {
+    /// Pushes processed lines to `out`, which may be delayed until the end of run for block
+    /// comments
+    fn feed(
+        &mut self,
+        s: Cow<'a, str>,
+        config: &Data,
+        desc: &LangDesc,
+        out: &mut Vec<Cow<'a, str>>,
+    ) -> Result<(), CfgCommentError> {
+        let i = self.line;
+        self.line += 1;
+        process_line(i, s, &mut self.state, &mut self.run, config, desc, out)
+    }
+    /// Pushes lines of last unfinished run, and reports tags left open
+    fn finish(&mut self, desc: &LangDesc, out: &mut Vec<Cow<'a, str>>) -> Result<(), CfgCommentError> {
+        if let Some(close) = &desc.comment_end {
+            self.run.flush(desc, close, out)?;
+        }
+        self.state.check_closed()
+    }
+}
+
+/// Iterator returned by [`process`], owns all of its state and is [`Send`] if the
+/// input is, so whole pipeline may be moved to another thread
+struct Processed<I> {
+    read: I,
+    processor: Processor<'static>,
     config: Arc<Data>,
-    desc: Rc<LangDesc>,
-) -> impl Iterator<Item = String> {
-    let state = CfgState::default();
-    read.map(move |s| {
-        let state = state.clone();
-        if s.trim_start().starts_with(&desc.cfg_prefix) && s.trim_end().ends_with(&desc.cfg_suffix)
-        {
-            let (ws, cfg) = split_at_ws_end(&s);
-            let parsed = cfg::cfg(&cfg[desc.cfg_prefix_comment_len..]).unwrap();
-            match parsed {
-                CfgTag::Start(c) => {
-                    state.push((c.matches(&config), ws.to_owned()));
-                    s
+    desc: Arc<LangDesc>,
+    /// Output of last fed line, yielded starting from `pos`
+    pending: Vec<Cow<'static, str>>,
+    pos: usize,
+    finished: bool,
+}
+impl<I: Iterator<Item = String>> Iterator for Processed<I> {
+    type Item = Result<String, CfgCommentError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.pending.get_mut(self.pos) {
+                self.pos += 1;
+                return Some(Ok(std::mem::take(line).into_owned()));
+            }
+            if self.finished {
+                return None;
+            }
+            self.pending.clear();
+            self.pos = 0;
+            let result = match self.read.next() {
+                Some(s) => {
+                    let (config, desc) = (&self.config, &self.desc);
+                    self.processor.feed(Cow::Owned(s), config, desc, &mut self.pending)
                 }
-                CfgTag::End => {
-                    state.pop().expect("unexpected end");
-                    s
+                None => {
+                    self.finished = true;
+                    self.processor.finish(&self.desc, &mut self.pending)
                 }
+            };
+            if let Err(e) = result {
+                self.pending.clear();
+                return Some(Err(e));
             }
+        }
+    }
+}
+
+pub fn process(
+    read: impl Iterator<Item = String>,
+    config: Arc<Data>,
+    desc: Arc<LangDesc>,
+) -> impl Iterator<Item = Result<String, CfgCommentError>> {
+    Processed {
+        read,
+        processor: Processor::default(),
+        config,
+        desc,
+        pending: Vec::new(),
+        pos: 0,
+        finished: false,
+    }
+}
+
+/// Async counterpart of [`process`], every output line is terminated with `\n`
+#[cfg(feature = "tokio")]
+pub async fn process_async<R, W>(
+    read: R,
+    mut write: W,
+    config: &Data,
+    desc: &LangDesc,
+) -> Result<(), CfgCommentError>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    let mut processor = Processor::default();
+    let mut out = Vec::new();
+    let mut lines = read.lines();
+    let mut finished = false;
+    while !finished {
+        match lines.next_line().await? {
+            Some(line) => processor.feed(Cow::Owned(line), config, desc, &mut out)?,
+            None => {
+                finished = true;
+                processor.finish(desc, &mut out)?
+            }
+        }
+        for line in out.drain(..) {
+            write.write_all(line.as_bytes()).await?;
+            write.write_all(b"\n").await?;
+        }
+    }
+    write.flush().await?;
+    Ok(())
+}
+
+/// Block of lines guarded by a cfg tag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    /// Line of start tag, 1-based
+    pub start: usize,
+    /// Line of end tag, 1-based. For `[cfg_line(...)]` tags, line they apply to,
+    /// for `[cfg_file(...)]` tags, last line of file
+    pub end: usize,
+    /// Predicate as written in start tag, i.e `feature = "a"`
+    pub predicate_src: String,
+    /// Number of regions this one is nested in
+    pub nesting_depth: usize,
+    /// Features named by predicate, glob patterns are included as written
+    pub features: Vec<String>,
+    /// Features which are necessarily enabled when predicate holds, i.e `a` for
+    /// `all(feature = "a", not(feature = "b"))`
+    pub required_features: Vec<String>,
+    /// Whether the first branch is currently commented out, judged by its first line,
+    /// or by its first nested region if it has no own lines. `false` for empty regions
+    pub commented: bool,
+    /// Non-blank lines guarded by region, including nested regions, without tags
+    pub lines: usize,
+    /// Part of `lines` currently commented out
+    pub commented_lines: usize,
+}
+
+fn group_features(group: &CfgExpr) -> Vec<String> {
+    let mut features = Vec::new();
+    group.features(&mut features);
+    features
+}
+
+/// Lists cfg regions of file, ordered by start line, without processing it
+pub fn regions(
+    input: &str,
+    desc: &LangDesc,
+    strict: bool,
+) -> Result<Vec<Region>, CfgCommentError> {
+    let mut regions: Vec<Region> = Vec::new();
+    // Whether first own line of first branch is commented, per region
+    let mut commented: Vec<Option<bool>> = Vec::new();
+    // Regions past their first branch, or nesting other regions before any own line
+    let mut settled: Vec<bool> = Vec::new();
+    let mut first_child: Vec<Option<usize>> = Vec::new();
+    let mut open = Vec::new();
+    // Region of `[cfg_line(...)]` tag, waiting for its line
+    let mut pending_line: Option<usize> = None;
+    // `[cfg_file(...)]` region wraps every other region
+    let mut depth_base = 0;
+    // Whole run of lines between tags is wrapped in a single block comment
+    let block_runs = desc.comment_end.is_some() && !desc.wrap_lines;
+    let mut run_commented = None;
+    for (i, line) in input.lines().enumerate() {
+        let tag = parse_tag(line, i, desc, strict)?;
+        let line_commented = if tag.is_some() {
+            run_commented = None;
+            None
+        } else if line.trim().is_empty() {
+            None
         } else {
-            if s.trim().is_empty() {
-                return s;
-            }
-            let prefix = state.prefix();
-            let trimmed = s.strip_prefix(&prefix).expect("wrong prefix");
-            let enabled = !trimmed.starts_with(&desc.comment);
-            let should_be = config.reset || state.enabled();
-
-            log::trace!("{} {:?} {:?}", trimmed, enabled, should_be);
-            if !enabled && should_be {
-                format!("{}{}", prefix, &trimmed[desc.comment.len()..])
-            } else if enabled && !should_be {
-                format!("{}{}{}", prefix, desc.comment, trimmed)
+            let marked = line.trim_start().starts_with(&desc.comment);
+            Some(if block_runs {
+                *run_commented.get_or_insert(marked)
             } else {
-                s
+                marked
+            })
+        };
+        if let Some(line_commented) = line_commented {
+            let file = if depth_base == 1 { Some(0) } else { None };
+            for &idx in open.iter().chain(&file).chain(&pending_line) {
+                regions[idx].lines += 1;
+                regions[idx].commented_lines += usize::from(line_commented);
             }
         }
-    })
+        if let Some(idx) = pending_line {
+            if tag.is_some() {
+                return Err(CfgCommentError::DanglingLineTag {
+                    line: regions[idx].start,
+                });
+            }
+            if line_commented.is_some() {
+                regions[idx].end = i + 1;
+                commented[idx] = line_commented;
+                pending_line = None;
+            }
+            continue;
+        }
+        // Innermost region this line belongs to
+        let current = open.last().copied().or(if depth_base == 1 { Some(0) } else { None });
+        if let Some(idx) = current {
+            match &tag {
+                None if line_commented.is_some() && !settled[idx] => {
+                    commented[idx] = line_commented;
+                    settled[idx] = true;
+                }
+                Some((_, CfgTag::Start(_, _) | CfgTag::Line(_, _)))
+                    if !settled[idx] && first_child[idx].is_none() =>
+                {
+                    first_child[idx] = Some(regions.len());
+                }
+                Some((_, CfgTag::Else | CfgTag::ElseIf(_, _))) if open.last() == Some(&idx) => {
+                    settled[idx] = true;
+                }
+                _ => {}
+            }
+        }
+        if let Some((_, CfgTag::File(_, _) | CfgTag::Line(_, _) | CfgTag::Start(_, _))) = tag {
+            commented.push(None);
+            settled.push(false);
+            first_child.push(None);
+        }
+        match tag {
+            Some((_, CfgTag::File(group, span))) if i == 0 => {
+                depth_base = 1;
+                regions.push(Region {
+                    start: 1,
+                    end: input.lines().count(),
+                    predicate_src: line[span].to_owned(),
+                    nesting_depth: 0,
+                    features: group_features(&group),
+                    required_features: group.required_features(),
+                    commented: false,
+                    lines: 0,
+                    commented_lines: 0,
+                });
+            }
+            Some((_, CfgTag::File(_, _))) => {
+                return Err(CfgCommentError::MisplacedFileTag { line: i + 1 });
+            }
+            Some((_, CfgTag::Line(group, span))) => {
+                pending_line = Some(regions.len());
+                regions.push(Region {
+                    start: i + 1,
+                    end: 0,
+                    predicate_src: line[span].to_owned(),
+                    nesting_depth: depth_base + open.len(),
+                    features: group_features(&group),
+                    required_features: group.required_features(),
+                    commented: false,
+                    lines: 0,
+                    commented_lines: 0,
+                });
+            }
+            Some((_, CfgTag::Start(group, span))) => {
+                open.push(regions.len());
+                regions.push(Region {
+                    start: i + 1,
+                    end: 0,
+                    predicate_src: line[span].to_owned(),
+                    nesting_depth: depth_base + open.len() - 1,
+                    features: group_features(&group),
+                    required_features: group.required_features(),
+                    commented: false,
+                    lines: 0,
+                    commented_lines: 0,
+                });
+            }
+            Some((_, CfgTag::Else)) | Some((_, CfgTag::ElseIf(_, _))) if open.is_empty() => {
+                return Err(CfgCommentError::UnexpectedElse { line: i + 1 });
+            }
+            Some((_, CfgTag::End(_))) => {
+                let idx = open
+                    .pop()
+                    .ok_or(CfgCommentError::UnexpectedEnd { line: i + 1 })?;
+                regions[idx].end = i + 1;
+            }
+            Some((_, CfgTag::Else)) | Some((_, CfgTag::ElseIf(_, _))) | None => {}
+        }
+    }
+    if let Some(idx) = pending_line {
+        return Err(CfgCommentError::DanglingLineTag {
+            line: regions[idx].start,
+        });
+    }
+    if let Some(idx) = open.pop() {
+        return Err(CfgCommentError::Unclosed {
+            line: regions[idx].start,
+            outer: open.iter().map(|&i| regions[i].start).collect(),
+        });
+    }
+    // Nested regions always follow their parent
+    for idx in (0..regions.len()).rev() {
+        regions[idx].commented = commented[idx]
+            .or_else(|| first_child[idx].map(|c| regions[c].commented))
+            .unwrap_or(false);
+    }
+    Ok(regions)
 }
 
-pub fn walkdir_parallel(paths: Vec<PathBuf>, config: Data, lang_config: HashMap<String, LangDesc>) {
-    let mut walk = ignore::WalkBuilder::new(paths[0].clone());
-    for dir in paths.iter().skip(1) {
-        walk.add(dir);
+/// Lists every problem of file, unlike processing, which stops at the first one. Tags are
+/// checked for syntax, balance and consistent indentation, as in strict mode
+pub fn verify(input: &str, desc: &LangDesc) -> Vec<CfgCommentError> {
+    let config = Data {
+        reset: true,
+        strict: true,
+        ..Default::default()
+    };
+    let mut processor = Processor::default();
+    let mut out = Vec::new();
+    let mut problems = Vec::new();
+    for line in input.lines() {
+        if let Err(e) = processor.feed(Cow::Borrowed(line), &config, desc, &mut out) {
+            problems.push(e);
+        }
+        out.clear();
     }
-    walk.add_custom_ignore_filename(".cfgignore");
+    let mut closed = processor.finish(desc, &mut out);
+    loop {
+        match closed {
+            Ok(()) => break,
+            // Every unclosed block is a problem of its own
+            Err(CfgCommentError::Unclosed { line, outer }) => {
+                problems.extend(outer.into_iter().chain(std::iter::once(line)).map(|line| {
+                    CfgCommentError::Unclosed {
+                        line,
+                        outer: Vec::new(),
+                    }
+                }));
+                break;
+            }
+            Err(e @ CfgCommentError::DanglingLineTag { .. }) => {
+                problems.push(e);
+                processor.state.pop();
+            }
+            Err(e) => problems.push(e),
+        }
+        closed = processor.state.check_closed();
+    }
+    problems.sort_by_key(|e| e.line());
+    problems
+}
 
-    let config = Arc::new(config);
-    let lang_config = Arc::new(lang_config);
+/// Splits input into lines and their terminators, `\n`, `\r\n`, or empty for the last line
+/// without newline
+fn split_lines(input: &str) -> Vec<(&str, &str)> {
+    input.split_inclusive('\n').map(line_ending).collect()
+}
 
-    walk.build_parallel().run(move || {
-        let config = config.clone();
-        let lang_config = lang_config.clone();
-        Box::new(move |path| {
-            let path = path.unwrap();
-            // Skip dirs/symlinks
-            if !path.file_type().map(|f| f.is_file()).unwrap_or(false) {
-                return ignore::WalkState::Continue;
-            }
-            let extension = match path.path().extension() {
-                Some(v) => v,
-                None => return ignore::WalkState::Continue,
-            };
-            let extension = extension.to_string_lossy().to_string();
-            let desc = match lang_config.get(&extension) {
-                Some(v) => v,
-                None => return ignore::WalkState::Continue,
-            };
-            let desc = Rc::new(desc.clone());
+/// Splits line with its terminator into content and terminator
+fn line_ending(line: &str) -> (&str, &str) {
+    let content = match line.strip_suffix('\n') {
+        Some(l) => l.strip_suffix('\r').unwrap_or(l),
+        None => line,
+    };
+    (content, &line[content.len()..])
+}
 
-            let file = BufReader::new(File::open(path.path()).unwrap());
-            let mut out = BufWriter::new(
-                tempfile::NamedTempFile::new_in(path.path().parent().unwrap()).unwrap(),
-            );
+/// Processes lines of [`split_lines`], returning original and processed contents. Names
+/// referred to by tags are added to `referenced`, branches and lines are passed to `visitor`
+/// as soon as they are processed
+fn process_split(
+    lines: &[(&str, &str)],
+    config: &Data,
+    desc: &LangDesc,
+    referenced: &mut Referenced,
+    visitor: &mut dyn Visitor,
+) -> Result<(Vec<String>, Vec<String>), CfgCommentError> {
+    // Registries accept languages without validation, blank markers would corrupt files
+    desc.validate()?;
+    let original: Vec<String> = lines.iter().map(|(l, _)| (*l).to_owned()).collect();
+    let mut processor = Processor::default();
+    let mut events = VecDeque::new();
+    processor.state.events = Some(VecDeque::new());
+    let mut dispatcher = Dispatcher::default();
+    let mut processed = Vec::with_capacity(original.len());
+    for (line, _) in lines {
+        processor.feed(Cow::Borrowed(*line), config, desc, &mut processed)?;
+        if let Some(recorded) = &mut processor.state.events {
+            events.append(recorded);
+        }
+        dispatcher.dispatch(&mut events, lines, &mut processed, visitor);
+    }
+    processor.finish(desc, &mut processed)?;
+    dispatcher.dispatch(&mut events, lines, &mut processed, visitor);
+    dispatcher.finish(visitor);
+    let processed = processed.into_iter().map(Cow::into_owned).collect();
+    referenced.features.extend(processor.state.referenced.features);
+    referenced.env.extend(processor.state.referenced.env);
+    Ok((original, processed))
+}
 
-            for line in process(file.lines().map(|l| l.unwrap()), config.clone(), desc) {
-                writeln!(out, "{}", line).unwrap();
-            }
+/// Joins processed lines back, reusing terminators of original lines
+fn join_lines(lines: &[(&str, &str)], processed: &[String]) -> String {
+    let mut out = String::with_capacity(processed.iter().map(|l| l.len() + 2).sum());
+    for ((_, ending), line) in lines.iter().zip(processed) {
+        out.push_str(line);
+        out.push_str(ending);
+    }
+    out
+}
 
-            out.into_inner().unwrap().persist(path.path()).unwrap();
+/// Process whole file contents in memory, line terminators (`\n` or `\r\n`) and presence
+/// of final newline are preserved
+///
+/// ```
+/// use cfgcomment_core::{process_str, Data, LangDesc};
+///
+/// let input = "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n";
+/// let output = process_str(input, &Data::default(), &LangDesc::new("//")).unwrap();
+/// assert_eq!(output, "//[cfg(feature = \"a\")]\n//# a();\n//[cfg(end)]\n");
+/// ```
+pub fn process_str(input: &str, config: &Data, desc: &LangDesc) -> Result<String, CfgCommentError> {
+    process_str_with(input, config, desc, &mut NoopVisitor)
+}
 
-            ignore::WalkState::Continue
-        })
-    });
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> CfgExpr {
+        match cfg::cfg(&format!("[cfg({})]", src), &LangDesc::new("//"), false).unwrap() {
+            CfgTag::Start(group, _) => group,
+            _ => panic!("not a start tag"),
+        }
+    }
+
+    #[test]
+    fn render_canonical() {
+        for (src, canonical) in [
+            ("feature=\"a\"", "feature = \"a\""),
+            ("env( \"CI\" )", "env(\"CI\")"),
+            ("feature~\"backend-*\"", "feature ~ \"backend-*\""),
+            ("env(\"P\")=\"release\"", "env(\"P\") = \"release\""),
+            ("target_os=\"linux\"", "target_os = \"linux\""),
+            ("target_pointer_width = \"64\"", "target_pointer_width = \"64\""),
+            ("panic=\"abort\"", "panic = \"abort\""),
+            ("all(test,debug_assertions)", "all(test, debug_assertions)"),
+            ("version( \"1.70\" )", "version(\"1.70\")"),
+            ("all(feature = \"a\",feature = \"b\",)", "all(feature = \"a\", feature = \"b\")"),
+            ("any()", "any()"),
+            (
+                "not( any(feature = \"a\", all(env(\"X\"), not(feature = \"b\"))) )",
+                "not(any(feature = \"a\", all(env(\"X\"), not(feature = \"b\"))))",
+            ),
+        ] {
+            let group = parse(src);
+            assert_eq!(group.to_string(), canonical);
+            assert_eq!(parse(&group.to_string()), group);
+        }
+    }
+
+    #[test]
+    fn render_deeply_nested() {
+        let mut src = "feature = \"a\"".to_owned();
+        for i in 0..64 {
+            src = match i % 3 {
+                0 => format!("not({})", src),
+                1 => format!("any({}, feature = \"{}\")", src, i),
+                _ => format!("all(env(\"{}\"), {})", i, src),
+            };
+        }
+        let group = parse(&src);
+        assert_eq!(group.to_string(), src);
+        assert_eq!(parse(&group.to_string()), group);
+    }
 }
@@ -0,0 +1,137 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::CfgCommentError;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Dependencies {
+    dependencies: BTreeMap<String, toml::Value>,
+    #[serde(rename = "build-dependencies")]
+    build_dependencies: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Deserialize)]
+struct RawManifest {
+    #[serde(default)]
+    features: HashMap<String, Vec<String>>,
+    #[serde(flatten)]
+    dependencies: Dependencies,
+    #[serde(default)]
+    target: BTreeMap<String, Dependencies>,
+}
+
+/// Feature table of Cargo.toml
+pub struct Manifest {
+    path: PathBuf,
+    /// Features with what they enable, including implicit features of optional dependencies
+    features: HashMap<String, Vec<String>>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self, CfgCommentError> {
+        let data = std::fs::read_to_string(path).map_err(|source| CfgCommentError::ConfigRead {
+            path: path.to_owned(),
+            source,
+        })?;
+        let raw: RawManifest =
+            toml::from_str(&data).map_err(|source| CfgCommentError::ConfigParse {
+                path: path.to_owned(),
+                source,
+            })?;
+        Ok(Self::new(path.to_owned(), raw))
+    }
+
+    /// Closest Cargo.toml, starting from `start` (or its parent, if `start` is a file) and
+    /// going upward, like cargo does
+    pub fn discover(start: &Path) -> Result<Option<Self>, CfgCommentError> {
+        let start = std::path::absolute(start)?;
+        let start = if start.is_file() {
+            start.parent().unwrap_or(&start)
+        } else {
+            &start
+        };
+        for dir in start.ancestors() {
+            let path = dir.join("Cargo.toml");
+            if path.is_file() {
+                return Self::load(&path).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn new(path: PathBuf, raw: RawManifest) -> Self {
+        let mut features = raw.features;
+        // Optional dependency is a feature by itself, unless it is only referred to as `dep:name`
+        let explicit: HashSet<&str> = features
+            .values()
+            .flatten()
+            .filter_map(|f| f.strip_prefix("dep:"))
+            .collect();
+        let optional: Vec<String> = std::iter::once(&raw.dependencies)
+            .chain(raw.target.values())
+            .flat_map(|d| d.dependencies.iter().chain(&d.build_dependencies))
+            .filter(|(_, dep)| dep.get("optional").and_then(|v| v.as_bool()) == Some(true))
+            .map(|(name, _)| name.clone())
+            .filter(|name| !explicit.contains(name.as_str()))
+            .collect();
+        for name in optional {
+            features.entry(name).or_default();
+        }
+        Self { path, features }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every declared feature, including implicit features of optional dependencies
+    pub fn features(&self) -> impl Iterator<Item = &str> {
+        self.features.keys().map(String::as_str)
+    }
+
+    /// Every feature cargo would enable when building with `requested` features, with or
+    /// without `default` ones. Features of dependencies (`dep/feature`) are not included
+    pub fn resolve(
+        &self,
+        requested: &[String],
+        default: bool,
+    ) -> Result<HashSet<String>, CfgCommentError> {
+        let mut enabled = HashSet::new();
+        let mut queue = requested.to_vec();
+        for feature in &queue {
+            if !self.features.contains_key(feature) {
+                return Err(CfgCommentError::UnknownManifestFeature {
+                    path: self.path.clone(),
+                    feature: feature.clone(),
+                });
+            }
+        }
+        if default && self.features.contains_key("default") {
+            queue.push("default".to_owned());
+        }
+        while let Some(feature) = queue.pop() {
+            if !enabled.insert(feature.clone()) {
+                continue;
+            }
+            for item in self.features.get(&feature).into_iter().flatten() {
+                // `dep?/feature` doesn't enable the dependency, `dep/feature` does, and it is
+                // a feature if dependency is optional
+                let item = match item.split_once('/') {
+                    Some((dep, _)) if dep.ends_with('?') => continue,
+                    Some((dep, _)) => dep,
+                    None if item.starts_with("dep:") => continue,
+                    None => item,
+                };
+                if self.features.contains_key(item) {
+                    queue.push(item.to_owned());
+                }
+            }
+        }
+        Ok(enabled)
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    encoding::has_nul,
+    line_ending,
+    walk::{backup, replace_file, sibling_temp, FileChanges},
+    CfgCommentError, Data, LangDesc, Processor, WalkOptions,
+};
+
+/// Files this large are processed by [`process_mapped`]
+pub(crate) const MMAP_THRESHOLD: u64 = 1 << 20;
+
+/// Line contents without terminators, as in [`crate::split_lines`], found without collecting
+struct Lines<'a> {
+    text: &'a str,
+    pos: usize,
+}
+impl<'a> Iterator for Lines<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.text[self.pos..];
+        if rest.is_empty() {
+            return None;
+        }
+        let len = memchr::memchr(b'\n', rest.as_bytes()).map_or(rest.len(), |i| i + 1);
+        self.pos += len;
+        Some(line_ending(&rest[..len]).0)
+    }
+}
+
+/// Output is only written if something changed, untouched regions between changed lines
+/// are copied as is
+struct Output<'a> {
+    text: &'a str,
+    /// Start of region not yet written
+    clean: usize,
+    out: Option<BufWriter<tempfile::NamedTempFile>>,
+}
+impl Output<'_> {
+    /// Replaces content of `original` line, which is a slice of `text`
+    fn replace(&mut self, path: &Path, original: &str, line: &str) -> std::io::Result<()> {
+        if self.out.is_none() {
+            self.out = Some(BufWriter::new(sibling_temp(path)?));
+        }
+        let out = self.out.as_mut().unwrap();
+        let start = original.as_ptr() as usize - self.text.as_ptr() as usize;
+        out.write_all(&self.text.as_bytes()[self.clean..start])?;
+        out.write_all(line.as_bytes())?;
+        self.clean = start + original.len();
+        Ok(())
+    }
+}
+
+/// Memory-mapped variant of `process_file` for plain UTF-8 files, which allocates only for
+/// changed lines. `None` if file needs decoding, and should be processed as usual
+pub(crate) fn process_mapped(
+    path: &Path,
+    desc: &LangDesc,
+    config: &Data,
+    options: &WalkOptions,
+) -> Result<Option<FileChanges>, CfgCommentError> {
+    desc.validate()?;
+    let file = File::open(path)?;
+    // SAFETY: file may be modified by other process while mapped, which can't be prevented.
+    // Same tradeoff is made by ripgrep and other tools, concurrent modification of processed
+    // tree is already a race
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    let text = match std::str::from_utf8(&map) {
+        Ok(v) if !v.starts_with('\u{feff}') => v,
+        _ => return Ok(None),
+    };
+    if has_nul(text.as_bytes()) {
+        log::debug!("{}: binary file skipped", path.display());
+        return Ok(Some(FileChanges::default()));
+    }
+
+    let mut changes = FileChanges::default();
+    let mut output = Output {
+        text,
+        clean: 0,
+        out: None,
+    };
+    let mut original = Lines { text, pos: 0 }.zip(1..);
+    let mut processor = Processor::default();
+    let mut processed = Vec::new();
+    let mut input = Lines { text, pos: 0 };
+    loop {
+        let finished = match input.next() {
+            Some(content) => {
+                processor.feed(Cow::Borrowed(content), config, desc, &mut processed)?;
+                false
+            }
+            None => {
+                processor.finish(desc, &mut processed)?;
+                true
+            }
+        };
+        // Processing never adds or removes lines, output lines are matched by position
+        for line in processed.drain(..) {
+            let (content, i) = original.next().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "output has more lines than input")
+            })?;
+            if matches!(line, Cow::Borrowed(_)) || line == content {
+                continue;
+            }
+            changes.lines.push(i);
+            if line.len() > content.len() {
+                changes.commented += 1;
+            }
+            if !options.check {
+                output.replace(path, content, &line)?;
+            }
+        }
+        if finished {
+            break;
+        }
+    }
+    changes.features = processor.state.referenced.features;
+    changes.env = processor.state.referenced.env;
+
+    let mut out = match output.out {
+        Some(v) => v,
+        None => return Ok(Some(changes)),
+    };
+    out.write_all(&text.as_bytes()[output.clean..])?;
+    let tmp = out.into_inner().map_err(|e| e.into_error())?;
+    drop(map);
+    if let Some(suffix) = &options.backup_suffix {
+        backup(path, suffix, options.force)?;
+    }
+    replace_file(path, tmp)?;
+    Ok(Some(changes))
+}
@@ -0,0 +1,122 @@
+use std::ops::Range;
+
+use crate::{
+    process_str_with, Branch, BranchKind, CfgCommentError, Data, LangDesc, LineState, Visitor,
+};
+
+/// Lines of single branch of cfg block, for gutter markers and dimming in editors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub kind: BranchKind,
+    /// Number of blocks this one is nested in
+    pub nesting_depth: usize,
+    /// Predicate in canonical form, `None` for `[cfg(else)]`
+    pub predicate: Option<String>,
+    /// Line of tag starting the branch, 1-based
+    pub start_line: usize,
+    /// Last line of branch, inclusive. Line before the next branch tag, end tag for the last
+    /// branch of block, so branches of block cover it without gaps
+    pub end_line: usize,
+    /// Byte offsets of lines in input, without line break after the last one
+    pub bytes: Range<usize>,
+    /// Whether processing with the same config leaves lines of branch uncommented
+    pub enabled: bool,
+    /// Whether lines of branch are commented out now, judged by its first own line, or by
+    /// its first nested branch if it has no own lines. `false` for empty branches
+    pub commented: bool,
+}
+
+/// Collects spans from branches and lines of single processing pass
+struct Collector<'a> {
+    comment: &'a str,
+    spans: Vec<Span>,
+    /// Whether first own line is commented, and first nested branch, per span
+    commented: Vec<Option<bool>>,
+    first_child: Vec<Option<usize>>,
+    open: Vec<usize>,
+    /// Last visited line
+    line: usize,
+}
+
+impl Visitor for Collector<'_> {
+    fn enter_branch(&mut self, branch: &Branch) {
+        let idx = self.spans.len();
+        if let Some(&parent) = self.open.last() {
+            if self.commented[parent].is_none() && self.first_child[parent].is_none() {
+                self.first_child[parent] = Some(idx);
+            }
+        }
+        self.open.push(idx);
+        self.commented.push(None);
+        self.first_child.push(None);
+        self.spans.push(Span {
+            kind: branch.kind,
+            nesting_depth: branch.nesting_depth,
+            predicate: branch.evaluation.as_ref().map(|e| e.predicate.clone()),
+            start_line: branch.line,
+            end_line: branch.line,
+            bytes: 0..0,
+            enabled: branch.enabled,
+            commented: false,
+        });
+    }
+
+    fn exit_branch(&mut self, _branch: &Branch) {
+        if let Some(idx) = self.open.pop() {
+            self.spans[idx].end_line = self.line.max(self.spans[idx].start_line);
+        }
+    }
+
+    fn visit_line(&mut self, line: &LineState<'_>, _output: &mut String) {
+        self.line = line.line;
+        if line.tag || line.original.trim().is_empty() {
+            return;
+        }
+        if let Some(&idx) = self.open.last() {
+            if self.commented[idx].is_none() && self.first_child[idx].is_none() {
+                self.commented[idx] = Some(line.original.trim_start().starts_with(self.comment));
+            }
+        }
+    }
+}
+
+/// Lists every branch of file in order, with its lines, byte offsets and state. Input is
+/// processed in memory, and fails to list the same way
+pub fn spans(input: &str, config: &Data, desc: &LangDesc) -> Result<Vec<Span>, CfgCommentError> {
+    let mut collector = Collector {
+        comment: &desc.comment,
+        spans: Vec::new(),
+        commented: Vec::new(),
+        first_child: Vec::new(),
+        open: Vec::new(),
+        line: 0,
+    };
+    process_str_with(input, config, desc, &mut collector)?;
+
+    let Collector {
+        mut spans,
+        commented,
+        first_child,
+        ..
+    } = collector;
+    // Nested spans always follow their parent
+    for idx in (0..spans.len()).rev() {
+        spans[idx].commented = commented[idx]
+            .or_else(|| first_child[idx].map(|c| spans[c].commented))
+            .unwrap_or(false);
+    }
+    let mut line_starts = Vec::new();
+    let mut line_ends = Vec::new();
+    let mut offset = 0;
+    for line in input.split_inclusive('\n') {
+        let text = line.strip_suffix('\n').unwrap_or(line);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        line_starts.push(offset);
+        line_ends.push(offset + text.len());
+        offset += line.len();
+    }
+    for span in &mut spans {
+        span.bytes = line_starts[span.start_line - 1]..line_ends[span.end_line - 1];
+    }
+    Ok(spans)
+}
@@ -0,0 +1,77 @@
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Dotted version number, missing components are treated as zeroes, so `1.70 == 1.70.0`
+#[derive(Debug, Clone, Eq)]
+pub struct Version(Vec<u64>);
+
+impl Version {
+    fn component(&self, i: usize) -> u64 {
+        self.0.get(i).copied().unwrap_or(0)
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    /// Parses `1.70.0`, pre-release suffixes (`1.72.0-nightly`) are ignored
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let trimmed = trimmed.split_once('-').map(|(v, _)| v).unwrap_or(trimmed);
+        trimmed
+            .split('.')
+            .map(|c| c.parse())
+            .collect::<Result<_, _>>()
+            .map(Self)
+            .map_err(|_| format!("invalid version: {:?}", s))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (0..self.0.len().max(other.0.len()))
+            .map(|i| self.component(i).cmp(&other.component(i)))
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, c) in self.0.iter().enumerate() {
+            if i != 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+/// Written as string, i.e `"1.70"`
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,112 @@
+use std::{borrow::Cow, collections::VecDeque};
+
+use crate::{
+    join_lines, process_split, split_lines, Branch, CfgCommentError, Data, LangDesc, Referenced,
+};
+
+/// Line of input with its state, as seen by [`Visitor::visit_line`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineState<'a> {
+    /// 1-based
+    pub line: usize,
+    /// Line as it was in input, without terminator
+    pub original: &'a str,
+    /// Line is a cfg tag, which is never changed by processing
+    pub tag: bool,
+    /// Line is left uncommented by processing. For tags, whether enclosing branch is
+    pub enabled: bool,
+    /// Processing commented or uncommented this line
+    pub changed: bool,
+}
+
+/// Callbacks of [`process_str_with`], every one does nothing by default
+pub trait Visitor {
+    /// Branch starts, before its tag line is visited
+    fn enter_branch(&mut self, _branch: &Branch) {}
+    /// Branch ends, after its last line is visited: end tag of block, line guarded by
+    /// `[cfg_line(...)]`, or last line of file for `[cfg_file(...)]`. Branches followed by
+    /// `else` ones end before the next branch starts
+    fn exit_branch(&mut self, _branch: &Branch) {}
+    /// Every line in order, `output` is its processed form and may be rewritten
+    fn visit_line(&mut self, _line: &LineState<'_>, _output: &mut String) {}
+}
+
+/// Visitor of [`crate::process_str`]
+pub(crate) struct NoopVisitor;
+impl Visitor for NoopVisitor {}
+
+/// Recorded by processor in order of input, see [`crate::CfgState::record`]
+pub(crate) enum Event {
+    Enter(Branch),
+    Exit,
+    Line { tag: bool, enabled: bool },
+}
+
+/// Passes recorded events to visitor, each line once its output is known. Lines of block
+/// comment runs are only known once the run ends, events after them wait until then
+#[derive(Default)]
+pub(crate) struct Dispatcher {
+    open: Vec<Branch>,
+    line: usize,
+}
+impl Dispatcher {
+    pub fn dispatch(
+        &mut self,
+        events: &mut VecDeque<Event>,
+        lines: &[(&str, &str)],
+        processed: &mut [Cow<'_, str>],
+        visitor: &mut dyn Visitor,
+    ) {
+        while let Some(event) = events.front() {
+            if matches!(event, Event::Line { .. }) && self.line >= processed.len() {
+                return;
+            }
+            match events.pop_front() {
+                Some(Event::Enter(branch)) => {
+                    visitor.enter_branch(&branch);
+                    self.open.push(branch);
+                }
+                Some(Event::Exit) => {
+                    if let Some(branch) = self.open.pop() {
+                        visitor.exit_branch(&branch);
+                    }
+                }
+                Some(Event::Line { tag, enabled }) => {
+                    let original = lines.get(self.line).map_or("", |(l, _)| l);
+                    let output = processed[self.line].to_mut();
+                    let state = LineState {
+                        line: self.line + 1,
+                        original,
+                        tag,
+                        enabled,
+                        changed: output != original,
+                    };
+                    visitor.visit_line(&state, output);
+                    self.line += 1;
+                }
+                None => return,
+            }
+        }
+    }
+    /// `[cfg_file(...)]` branch spans until the end
+    pub fn finish(&mut self, visitor: &mut dyn Visitor) {
+        while let Some(branch) = self.open.pop() {
+            visitor.exit_branch(&branch);
+        }
+    }
+}
+
+/// Same as [`crate::process_str`], but reports every branch and line to `visitor`. Callbacks
+/// are called in order of input while it is processed, so only some of them are if
+/// processing fails
+pub fn process_str_with(
+    input: &str,
+    config: &Data,
+    desc: &LangDesc,
+    visitor: &mut impl Visitor,
+) -> Result<String, CfgCommentError> {
+    let lines = split_lines(input);
+    let referenced = &mut Referenced::default();
+    let (_, processed) = process_split(&lines, config, desc, referenced, visitor)?;
+    Ok(join_lines(&lines, &processed))
+}
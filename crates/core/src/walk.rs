@@ -0,0 +1,642 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{File, FileTimes, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    cache::Cache,
+    diff_lines,
+    encoding::{self, has_nul},
+    feature_glob,
+    format::format_split,
+    join_lines,
+    mapped::{process_mapped, MMAP_THRESHOLD},
+    process_split, regions, split_lines, verify,
+    visit::NoopVisitor,
+    CfgCommentError, Data, Encoding, Hunk, LangDesc, LangRegistry, PathFilter, Referenced, Region,
+};
+
+#[derive(Default, Clone)]
+pub struct WalkOptions {
+    /// Copy original file to `<name><suffix>` before rewriting it, only done for changed files
+    pub backup_suffix: Option<String>,
+    /// Overwrite already existing backups
+    pub force: bool,
+    /// Only report files which need changes, never write anything
+    pub check: bool,
+    /// Collect diff of every changed file into [`WalkSummary::diffs`], never write anything
+    pub diff: bool,
+    /// Leave files which are neither UTF-8, nor UTF-16 with byte order mark untouched,
+    /// instead of reporting them as errors
+    pub skip_unknown_encoding: bool,
+    /// Count cfg blocks per feature into [`WalkSummary::feature_blocks`]
+    pub stats: bool,
+    /// Collect cfg regions of every file into [`WalkSummary::regions`]
+    pub regions: bool,
+    /// Report every problem of a file into [`WalkSummary::errors`] instead of processing
+    /// it, see [`verify`]. Nothing is written
+    pub verify: bool,
+    /// Rewrite cfg tags into canonical form instead of processing files, see [`format_tags`]
+    pub format_tags: bool,
+    /// Sort operands of `all()`/`any()` when formatting tags
+    pub sort_operands: bool,
+    /// Number of threads processing files, 0 picks number of cores
+    pub threads: usize,
+    /// File recording already processed files, which are skipped if neither they nor
+    /// features and language config changed since. Not used for reading with `stats` or
+    /// `regions`
+    pub cache: Option<PathBuf>,
+    /// Paths which are never processed, even if listed explicitly
+    pub filter: PathFilter,
+    /// Walk into hidden files and directories
+    pub hidden: bool,
+    /// Don't respect any ignore files, including `.cfgignore`
+    pub no_ignore: bool,
+    /// Don't respect `.gitignore`, global git excludes and `.git/info/exclude`
+    pub no_ignore_vcs: bool,
+    /// Walk into symlinked directories and process symlinked files, rewriting their targets.
+    /// Symlinks are skipped otherwise. Symlink loops are skipped either way
+    pub follow_symlinks: bool,
+    /// Files larger than this many bytes are skipped, even if listed explicitly
+    pub max_filesize: Option<u64>,
+    /// Called after every visited file, from walker threads
+    pub progress: Option<Arc<dyn Fn(Progress) + Send + Sync>>,
+}
+impl WalkOptions {
+    fn needs_regions(&self) -> bool {
+        self.stats || self.regions
+    }
+    /// Whole contents are inspected or rewritten beyond processing, which rules out cache
+    fn inspects(&self) -> bool {
+        self.needs_regions() || self.verify || self.format_tags
+    }
+}
+
+/// Counters passed to [`WalkOptions::progress`]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Files found so far, for explicit file lists this is known upfront
+    pub discovered: usize,
+    /// Files visited so far, including ones in unknown languages
+    pub processed: usize,
+    /// Files which were changed, or would be changed in check mode
+    pub changed: usize,
+}
+
+/// Counters are updated together, so every reported snapshot is consistent
+#[derive(Default)]
+struct ProgressCounter(Mutex<Progress>);
+impl ProgressCounter {
+    fn discovered(&self, count: usize) {
+        self.0.lock().unwrap().discovered += count;
+    }
+    fn processed(&self, changed: bool, options: &WalkOptions) {
+        let snapshot = {
+            let mut progress = self.0.lock().unwrap();
+            progress.processed += 1;
+            progress.changed += changed as usize;
+            *progress
+        };
+        if let Some(progress) = &options.progress {
+            progress(snapshot);
+        }
+    }
+}
+
+/// Outcome of [`walkdir_parallel`], sorted by path
+#[derive(Default, Debug)]
+pub struct WalkSummary {
+    /// Files which were changed, or would be changed in check mode
+    pub changed: Vec<PathBuf>,
+    /// Lines of every changed file which differ from processed output, 1-based
+    pub changed_lines: HashMap<PathBuf, Vec<usize>>,
+    /// Diff of every changed file, only collected with [`WalkOptions::diff`]
+    pub diffs: HashMap<PathBuf, Vec<Hunk>>,
+    /// Files skipped because of errors, left untouched
+    pub errors: Vec<(PathBuf, CfgCommentError)>,
+    /// Files in known language, including unchanged and failed ones
+    pub scanned: usize,
+    /// Paths of files counted in [`WalkSummary::scanned`]
+    pub files: Vec<PathBuf>,
+    /// Changed lines which were commented out
+    pub commented_lines: usize,
+    /// Changed lines which were uncommented
+    pub uncommented_lines: usize,
+    /// Number of cfg blocks naming every feature, only collected with [`WalkOptions::stats`]
+    pub feature_blocks: BTreeMap<String, usize>,
+    /// Regions of every file having any, only collected with [`WalkOptions::regions`]
+    pub regions: HashMap<PathBuf, Vec<Region>>,
+    /// Feature names and glob patterns named by tags of processed files. Files skipped as
+    /// cached are not read, so their tags are missing
+    pub referenced_features: HashSet<String>,
+    /// Variables named by `env(...)` predicates of processed files. Unlike
+    /// [`WalkSummary::referenced_features`], names of files skipped as cached are included
+    pub referenced_env: HashSet<String>,
+}
+
+/// Copies file to `<path><suffix>`, preserving permissions and timestamps
+pub(crate) fn backup(path: &Path, suffix: &str, force: bool) -> io::Result<()> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(suffix);
+    let backup = PathBuf::from(backup);
+    if !force && backup.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("backup {} already exists", backup.display()),
+        ));
+    }
+    let meta = std::fs::metadata(path)?;
+    std::fs::copy(path, &backup)?;
+    OpenOptions::new().write(true).open(&backup)?.set_times(
+        FileTimes::new()
+            .set_accessed(meta.accessed()?)
+            .set_modified(meta.modified()?),
+    )
+}
+
+/// Copies ownership, where allowed, and permissions of original file to its replacement
+fn copy_metadata(original: &std::fs::Metadata, path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // Changing owner requires privileges, otherwise file stays owned by current user
+        let _ = std::os::unix::fs::chown(path, Some(original.uid()), Some(original.gid()));
+    }
+    std::fs::set_permissions(path, original.permissions())
+}
+
+/// Changes made to a single file, or which would be made in check mode
+#[derive(Default)]
+pub(crate) struct FileChanges {
+    pub lines: Vec<usize>,
+    /// Changed lines which got comment markers added
+    pub commented: usize,
+    pub diff: Vec<Hunk>,
+    /// Only listed with `stats` or `regions`
+    pub regions: Vec<Region>,
+    /// Found by [`verify`]
+    pub problems: Vec<CfgCommentError>,
+    /// Feature names and glob patterns named by tags
+    pub features: HashSet<String>,
+    /// Variables named by `env("NAME")` predicates
+    pub env: HashSet<String>,
+}
+
+fn process_file(
+    path: &Path,
+    desc: &LangDesc,
+    config: &Arc<Data>,
+    options: &WalkOptions,
+) -> Result<FileChanges, CfgCommentError> {
+    // Diff and regions need whole contents anyway
+    let whole = options.diff || options.inspects();
+    if !whole && std::fs::metadata(path)?.len() >= MMAP_THRESHOLD {
+        if let Some(changes) = process_mapped(path, desc, config, options)? {
+            return Ok(changes);
+        }
+    }
+    let bytes = std::fs::read(path)?;
+    let (encoding, input) = match Encoding::decode(&bytes) {
+        Some((_, input)) if has_nul(input.as_bytes()) => {
+            log::debug!("{}: binary file skipped", path.display());
+            return Ok(FileChanges::default());
+        }
+        Some(v) => v,
+        None if has_nul(&bytes) => {
+            log::debug!("{}: binary file skipped", path.display());
+            return Ok(FileChanges::default());
+        }
+        None if options.skip_unknown_encoding => return Ok(FileChanges::default()),
+        None => return Err(CfgCommentError::UnknownEncoding),
+    };
+    if options.verify {
+        return Ok(FileChanges {
+            problems: verify(&input, desc),
+            ..Default::default()
+        });
+    }
+    let split = split_lines(&input);
+    let mut referenced = Referenced::default();
+    let (lines, processed) = if options.format_tags {
+        format_split(&split, desc, options.sort_operands)?
+    } else {
+        process_split(&split, config, desc, &mut referenced, &mut NoopVisitor)?
+    };
+    let changed: Vec<(usize, bool)> = lines
+        .iter()
+        .zip(&processed)
+        .enumerate()
+        .filter(|(_, (original, processed))| original != processed)
+        // Commenting only ever adds markers, uncommenting removes them
+        .map(|(i, (original, processed))| (i + 1, processed.len() > original.len()))
+        .collect();
+    let changes = FileChanges {
+        lines: changed.iter().map(|(i, _)| *i).collect(),
+        commented: changed.iter().filter(|(_, commented)| *commented).count(),
+        diff: if options.diff {
+            diff_lines(&lines, &processed, 3)
+        } else {
+            Vec::new()
+        },
+        regions: if options.needs_regions() {
+            regions(&input, desc, config.strict)?
+        } else {
+            Vec::new()
+        },
+        problems: Vec::new(),
+        features: referenced.features,
+        env: referenced.env,
+    };
+    if options.check || options.diff {
+        return Ok(changes);
+    }
+
+    // Rewriting unchanged file would only bump its mtime, triggering rebuilds
+    let output = encoding.encode(&join_lines(&split, &processed));
+    if output == bytes {
+        return Ok(changes);
+    }
+    if let Some(suffix) = &options.backup_suffix {
+        backup(path, suffix, options.force)?;
+    }
+
+    let mut out = BufWriter::new(sibling_temp(path)?);
+    out.write_all(&output)?;
+    replace_file(path, out.into_inner().map_err(|e| e.into_error())?)?;
+
+    Ok(changes)
+}
+
+/// File which is rewritten for `path`, symlinks are replaced by their targets, as renaming
+/// over them would replace link itself
+fn link_target(path: &Path) -> io::Result<Cow<'_, Path>> {
+    Ok(match std::fs::symlink_metadata(path)?.file_type().is_symlink() {
+        true => Cow::Owned(std::fs::canonicalize(path)?),
+        false => Cow::Borrowed(path),
+    })
+}
+
+/// Temporary file in the same directory, so it can be atomically renamed over `path`
+pub(crate) fn sibling_temp(path: &Path) -> io::Result<tempfile::NamedTempFile> {
+    let path = link_target(path)?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    tempfile::NamedTempFile::new_in(dir)
+}
+
+/// Moves fully written temporary file over `path`, keeping its owner and permissions
+pub(crate) fn replace_file(path: &Path, tmp: tempfile::NamedTempFile) -> io::Result<()> {
+    let path = link_target(path)?;
+    copy_metadata(&std::fs::metadata(&path)?, tmp.path())?;
+    tmp.persist(&*path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// Start of file, enough to find `cfgcomment: lang=...` magic comment
+fn first_line(path: &Path) -> io::Result<String> {
+    let mut start = Vec::new();
+    File::open(path)?.take(512).read_to_end(&mut start)?;
+    let mut text = encoding::decode_lossy(&start);
+    if let Some(end) = text.find('\n') {
+        text.truncate(end + 1);
+    }
+    Ok(text)
+}
+
+fn walk_error_path(e: &ignore::Error) -> Option<PathBuf> {
+    match e {
+        ignore::Error::WithPath { path, .. } => Some(path.clone()),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            walk_error_path(err)
+        }
+        _ => None,
+    }
+}
+
+fn is_loop(e: &ignore::Error) -> bool {
+    match e {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => is_loop(err),
+        _ => false,
+    }
+}
+
+/// Detects language of file and processes it, recording outcome in summary,
+/// returns true if file was changed
+fn visit_file(
+    path: PathBuf,
+    config: &Arc<Data>,
+    lang_config: &LangRegistry,
+    options: &WalkOptions,
+    cache: Option<&Cache>,
+    summary: &Mutex<WalkSummary>,
+) -> bool {
+    if let Some(max) = options.max_filesize {
+        if std::fs::metadata(&path).is_ok_and(|meta| meta.len() > max) {
+            log::debug!("{}: larger than {} bytes, skipped", path.display(), max);
+            return false;
+        }
+    }
+    if let Some(cache) = cache.filter(|_| !options.inspects()) {
+        if std::fs::metadata(&path).is_ok_and(|meta| cache.is_fresh(&path, &meta)) {
+            let mut summary = summary.lock().unwrap();
+            summary.scanned += 1;
+            summary.files.push(path);
+            return false;
+        }
+    }
+    let desc = match first_line(&path)
+        .map_err(CfgCommentError::from)
+        .and_then(|line| lang_config.resolve(&path, &line))
+    {
+        Ok(Some(v)) => v,
+        Ok(None) => {
+            // Not cached, fresh entries are counted as scanned
+            if let Some(cache) = cache {
+                cache.update(&path, false);
+            }
+            return false;
+        }
+        Err(e) => {
+            summary.lock().unwrap().errors.push((path, e));
+            return false;
+        }
+    };
+
+    let result = process_file(&path, desc, config, options);
+    if let Some(cache) = cache {
+        if let Ok(changes) = &result {
+            cache.reference_env(&changes.env);
+        }
+        cache.update(&path, result.is_ok());
+    }
+    let mut summary = summary.lock().unwrap();
+    summary.scanned += 1;
+    summary.files.push(path.clone());
+    match result {
+        Ok(changes) => {
+            summary.commented_lines += changes.commented;
+            summary.uncommented_lines += changes.lines.len() - changes.commented;
+            if options.stats {
+                for feature in changes.regions.iter().flat_map(|r| &r.features) {
+                    *summary.feature_blocks.entry(feature.clone()).or_default() += 1;
+                }
+            }
+            if options.regions && !changes.regions.is_empty() {
+                summary.regions.insert(path.clone(), changes.regions);
+            }
+            let problems = changes.problems.into_iter().map(|e| (path.clone(), e));
+            summary.errors.extend(problems);
+            summary.referenced_features.extend(changes.features);
+            summary.referenced_env.extend(changes.env);
+            if changes.lines.is_empty() {
+                return false;
+            }
+            summary.changed.push(path.clone());
+            if options.diff {
+                summary.diffs.insert(path.clone(), changes.diff);
+            }
+            summary.changed_lines.insert(path, changes.lines);
+            true
+        }
+        Err(e) => {
+            summary.errors.push((path, e));
+            false
+        }
+    }
+}
+
+fn open_cache(
+    config: &Data,
+    lang_config: &LangRegistry,
+    options: &WalkOptions,
+    summary: &Mutex<WalkSummary>,
+) -> Option<Cache> {
+    let path = options.cache.clone()?;
+    let read_only = options.check || options.diff;
+    match Cache::open(path.clone(), config, lang_config, read_only) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            summary.lock().unwrap().errors.push((path, e.into()));
+            None
+        }
+    }
+}
+
+fn save_cache(cache: Option<Cache>, summary: &Mutex<WalkSummary>) {
+    if let Some(cache) = cache {
+        summary.lock().unwrap().referenced_env.extend(cache.referenced_env());
+        if let Err(e) = cache.save() {
+            let path = cache.path().to_owned();
+            summary.lock().unwrap().errors.push((path, e.into()));
+        }
+    }
+}
+
+/// Uncommented blocks of mutually exclusive features, found by
+/// [`WalkSummary::exclusive_conflicts`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExclusiveConflict {
+    /// First active block of every feature of the group having any, by feature name
+    pub active: Vec<(String, PathBuf, usize)>,
+}
+
+impl WalkSummary {
+    /// Groups of mutually exclusive features, which have active blocks of more than one
+    /// feature. Block is of a feature if it can't be active without it, see
+    /// [`Region::required_features`]. Needs [`WalkOptions::regions`]
+    pub fn exclusive_conflicts(&self, groups: &[Vec<String>]) -> Vec<ExclusiveConflict> {
+        let mut files: Vec<_> = self.regions.iter().collect();
+        files.sort_by(|a, b| a.0.cmp(b.0));
+        let first_active = |feature: &String| {
+            files.iter().find_map(|(path, regions)| {
+                let region = regions.iter().find(|r| {
+                    !r.commented && r.lines != 0 && r.required_features.contains(feature)
+                })?;
+                Some((feature.clone(), path.to_path_buf(), region.start))
+            })
+        };
+        groups
+            .iter()
+            .map(|group| group.iter().filter_map(first_active).collect::<Vec<_>>())
+            .filter(|active| active.len() > 1)
+            .map(|active| ExclusiveConflict { active })
+            .collect()
+    }
+
+    /// Features of `requested` which no tag of processed files names, either directly or
+    /// by glob pattern, sorted
+    pub fn unreferenced_features<'r>(
+        &self,
+        requested: impl IntoIterator<Item = &'r String>,
+    ) -> Vec<&'r String> {
+        let globs: Vec<globset::GlobMatcher> = self
+            .referenced_features
+            .iter()
+            .filter_map(|pattern| feature_glob(pattern).ok())
+            .collect();
+        let mut unreferenced: Vec<&String> = requested
+            .into_iter()
+            .filter(|f| !self.referenced_features.contains(*f))
+            .filter(|f| !globs.iter().any(|g| g.is_match(f)))
+            .collect();
+        unreferenced.sort();
+        unreferenced.dedup();
+        unreferenced
+    }
+}
+
+fn sort_summary(mut summary: WalkSummary) -> WalkSummary {
+    summary.changed.sort();
+    summary.files.sort();
+    summary.errors.sort_by(|(a, _), (b, _)| a.cmp(b));
+    summary
+}
+
+/// Processes exactly the listed files on all cores, bypassing directory walker and ignore
+/// files. Listed directories, and files excluded by [`WalkOptions::filter`] are skipped
+pub fn process_files(
+    mut paths: Vec<PathBuf>,
+    config: Data,
+    lang_config: LangRegistry,
+    options: WalkOptions,
+) -> WalkSummary {
+    paths.retain(|path| !options.filter.excludes(path));
+    let summary = Mutex::new(WalkSummary::default());
+    let cache = open_cache(&config, &lang_config, &options, &summary);
+    let config = Arc::new(config);
+    let next = AtomicUsize::new(0);
+    let threads = match options.threads {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let progress = ProgressCounter::default();
+    progress.discovered(paths.len());
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads.min(paths.len()) {
+            scope.spawn(|| {
+                while let Some(path) = paths.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let changed = match std::fs::metadata(path) {
+                        Ok(meta) if !meta.is_file() => false,
+                        Ok(_) => visit_file(
+                            path.clone(),
+                            &config,
+                            &lang_config,
+                            &options,
+                            cache.as_ref(),
+                            &summary,
+                        ),
+                        Err(e) => {
+                            summary.lock().unwrap().errors.push((path.clone(), e.into()));
+                            false
+                        }
+                    };
+                    progress.processed(changed, &options);
+                }
+            });
+        }
+    });
+
+    save_cache(cache, &summary);
+    sort_summary(summary.into_inner().unwrap())
+}
+
+pub fn walkdir_parallel(
+    paths: Vec<PathBuf>,
+    config: Data,
+    lang_config: LangRegistry,
+    options: WalkOptions,
+) -> WalkSummary {
+    let (first, rest) = match paths.split_first() {
+        Some(v) => v,
+        None => return WalkSummary::default(),
+    };
+    let mut walk = ignore::WalkBuilder::new(first);
+    for dir in rest {
+        walk.add(dir);
+    }
+    if !options.no_ignore {
+        walk.add_custom_ignore_filename(".cfgignore");
+    }
+    let vcs = !options.no_ignore && !options.no_ignore_vcs;
+    walk.follow_links(options.follow_symlinks);
+    walk.hidden(!options.hidden)
+        .parents(!options.no_ignore)
+        .ignore(!options.no_ignore)
+        .git_ignore(vcs)
+        .git_global(vcs)
+        .git_exclude(vcs);
+    options.filter.apply(&mut walk);
+    walk.threads(options.threads);
+
+    let summary = Mutex::new(WalkSummary::default());
+    let cache = open_cache(&config, &lang_config, &options, &summary);
+    let config = Arc::new(config);
+    let lang_config = Arc::new(lang_config);
+    let options = Arc::new(options);
+    let progress = ProgressCounter::default();
+    // Canonical paths of visited files, the same file may be reached through several links
+    let visited = Mutex::new(HashSet::new());
+
+    walk.build_parallel().run(|| {
+        let config = config.clone();
+        let lang_config = lang_config.clone();
+        let options = options.clone();
+        let summary = &summary;
+        let cache = cache.as_ref();
+        let progress = &progress;
+        let visited = &visited;
+        Box::new(move |path| {
+            let path = match path {
+                Ok(v) => v,
+                Err(e) if is_loop(&e) => {
+                    log::warn!("{}, skipped", e);
+                    return ignore::WalkState::Continue;
+                }
+                Err(e) => {
+                    let path = walk_error_path(&e).unwrap_or_default();
+                    summary.lock().unwrap().errors.push((path, e.into()));
+                    return ignore::WalkState::Continue;
+                }
+            };
+            if path.path_is_symlink() && !options.follow_symlinks {
+                log::debug!("{}: symlink skipped", path.path().display());
+                return ignore::WalkState::Continue;
+            }
+            // Skip dirs, symlinks are already resolved here
+            if !path.file_type().map(|f| f.is_file()).unwrap_or(false) {
+                return ignore::WalkState::Continue;
+            }
+            if let Some(canonical) = options
+                .follow_symlinks
+                .then(|| std::fs::canonicalize(path.path()).ok())
+                .flatten()
+            {
+                if !visited.lock().unwrap().insert(canonical) {
+                    log::debug!("{}: already visited through other path", path.path().display());
+                    return ignore::WalkState::Continue;
+                }
+            }
+            progress.discovered(1);
+            let changed =
+                visit_file(path.into_path(), &config, &lang_config, &options, cache, summary);
+            progress.processed(changed, &options);
+            ignore::WalkState::Continue
+        })
+    });
+
+    save_cache(cache, &summary);
+    sort_summary(summary.into_inner().unwrap())
+}
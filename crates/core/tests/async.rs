@@ -0,0 +1,56 @@
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use cfgcomment_core::{process_async, process_str, CfgCommentError, Data, LangDesc};
+use common::data;
+
+async fn apply(input: &str, config: &Data, desc: &LangDesc) -> Result<String, CfgCommentError> {
+    let mut out = Vec::new();
+    process_async(input.as_bytes(), &mut out, config, desc).await?;
+    Ok(String::from_utf8(out).unwrap())
+}
+
+const INPUT: &str = "\
+fn main() {
+    //[cfg(feature = \"a\")]
+    a();
+    //[cfg(else)]
+    b(/*[cfg(feature = \"c\")]*/ c /*[cfg(end)]*/);
+    //[cfg(end)]
+}
+";
+
+#[tokio::test]
+async fn matches_sync_processing() {
+    let list = LangDesc::default_list();
+    for features in [&[][..], &["a"], &["c"], &["a", "c"]].iter() {
+        let config = data(features);
+        for desc in [&list["rs"], &list["css"]].iter() {
+            let sync = process_str(INPUT, &config, desc).unwrap();
+            assert_eq!(apply(INPUT, &config, desc).await.unwrap(), sync);
+        }
+    }
+}
+
+#[tokio::test]
+async fn reports_unclosed_blocks() {
+    let err = apply("//[cfg(feature = \"a\")]\na();\n", &data(&[]), &LangDesc::new("//"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, CfgCommentError::Unclosed { line: 1, .. }), "{:?}", err);
+}
+
+#[tokio::test]
+async fn future_is_send() {
+    let desc = LangDesc::new("//");
+    let handle = tokio::spawn(async move {
+        let mut out = Vec::new();
+        process_async(INPUT.as_bytes(), &mut out, &Data::default(), &desc)
+            .await
+            .map(|()| out)
+    });
+    let out = handle.await.unwrap().unwrap();
+    let sync = process_str(INPUT, &Data::default(), &LangDesc::new("//")).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), sync);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use cfgcomment_core::CfgCommentError;
+use common::apply;
+
+#[test]
+fn unclosed_block() {
+    let input = "//[cfg(feature = \"a\")]\n//[cfg(feature = \"b\")]\nb\n//[cfg(end)]\n";
+    assert!(matches!(
+        apply(input, "rs", &[]),
+        Err(CfgCommentError::Unclosed { line: 1, .. })
+    ));
+    assert!(matches!(
+        apply("//[cfg(feature = \"a\")]\n//[cfg(feature = \"b\")]\n", "rs", &[]),
+        Err(CfgCommentError::Unclosed { line: 2, .. })
+    ));
+}
+
+#[test]
+fn every_unclosed_block_is_reported() {
+    let input = "//[cfg(feature = \"a\")]\n//[cfg(feature = \"b\")]\n//[cfg(feature = \"c\")]\n";
+    let err = apply(input, "rs", &[]).unwrap_err();
+    match &err {
+        CfgCommentError::Unclosed { line: 3, outer } => assert_eq!(outer, &[1, 2]),
+        e => panic!("unexpected error: {:?}", e),
+    }
+    assert_eq!(
+        err.to_string(),
+        "start tag on line 3 is never closed, neither are enclosing ones on lines 1, 2"
+    );
+}
+
+#[test]
+fn stray_end() {
+    assert!(matches!(
+        apply("a\n//[cfg(feature = \"a\")]\n//[cfg(end)]\n//[cfg(end)]\n", "rs", &[]),
+        Err(CfgCommentError::UnexpectedEnd { line: 4 })
+    ));
+}
+
+#[test]
+fn dangling_line_tag_at_eof() {
+    assert!(matches!(
+        apply("a\n//[cfg_line(feature = \"a\")]\n\n", "rs", &[]),
+        Err(CfgCommentError::DanglingLineTag { line: 2 })
+    ));
+}
+
+#[test]
+fn end_echo_matches_start() {
+    let input = "\
+//[cfg(any(feature = \"a\", feature = \"b\"))]
+    //[cfg(feature = \"c\")]
+    c
+    //[cfg(end feature=\"c\")]
+//[cfg(end any(feature = \"a\", feature = \"b\"))]
+";
+    assert!(apply(input, "rs", &[]).is_ok());
+}
+
+#[test]
+fn end_echo_mismatch() {
+    let input = "\
+//[cfg(feature = \"a\")]
+    //[cfg(feature = \"b\")]
+    //[cfg(end feature = \"a\")]
+//[cfg(end)]
+";
+    match apply(input, "rs", &[]) {
+        Err(CfgCommentError::MismatchedEnd {
+            line: 3,
+            start_line: 2,
+            start,
+            end,
+        }) => assert_eq!((start.as_str(), end.as_str()), ("feature = \"b\"", "feature = \"a\"")),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use cfgcomment_core::{process_str, CfgCommentError, Data, LangDesc};
+use common::apply;
+
+const INPUT: &str = "\
+body {}
+/*[cfg(feature = \"dark\")]*/
+body {
+    color: white;
+}
+    /*[cfg(feature = \"contrast\")]*/
+    a { color: yellow; }
+    /*[cfg(end)]*/
+
+a { color: gray; }
+/*[cfg(else)]*/
+body { color: black; }
+/*[cfg(end)]*/
+";
+
+const LIGHT: &str = "\
+body {}
+/*[cfg(feature = \"dark\")]*/
+/*# body {
+    color: white;
+} #*/
+    /*[cfg(feature = \"contrast\")]*/
+    /*# a { color: yellow; } #*/
+    /*[cfg(end)]*/
+
+/*# a { color: gray; } #*/
+/*[cfg(else)]*/
+body { color: black; }
+/*[cfg(end)]*/
+";
+
+#[test]
+fn disabled_run_is_wrapped_once() {
+    assert_eq!(apply(INPUT, "css", &["contrast"]).unwrap(), LIGHT);
+    assert_eq!(apply(LIGHT, "css", &["contrast"]).unwrap(), LIGHT);
+}
+
+#[test]
+fn wrapped_run_is_unwrapped() {
+    let dark = apply(LIGHT, "css", &["dark", "contrast"]).unwrap();
+    assert_eq!(
+        dark,
+        INPUT.replace("body { color: black; }", "/*# body { color: black; } #*/")
+    );
+    let reset = Data {
+        reset: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        process_str(&dark, &reset, &LangDesc::default_list()["css"]).unwrap(),
+        INPUT
+    );
+}
+
+#[test]
+fn line_tag_wraps_single_line() {
+    let input = "/*[cfg_line(feature = \"dark\")]*/\n\na { color: white; }\nb {}\n";
+    assert_eq!(
+        apply(input, "css", &[]).unwrap(),
+        "/*[cfg_line(feature = \"dark\")]*/\n\n/*# a { color: white; } #*/\nb {}\n"
+    );
+}
+
+#[test]
+fn blank_comment_end_is_rejected() {
+    assert!(matches!(
+        LangDesc::block("/*", "*/").with_comment_end(" ").validate(),
+        Err(CfgCommentError::BlankComment(_))
+    ));
+}
+
+#[test]
+fn comment_closer_in_disabled_run_is_refused() {
+    let input = "/*[cfg(feature = \"a\")]*/\na { color: red; } /* note */\n/*[cfg(end)]*/\n";
+    let data = Data::default();
+    assert!(matches!(
+        process_str(input, &data, &LangDesc::default_list()["css"]),
+        Err(CfgCommentError::CommentCloser { line: 2, ref close }) if close == "*/"
+    ));
+}
@@ -0,0 +1,142 @@
+mod common;
+
+use cfgcomment_core::{parse_cfg, CfgExpr, Data, FeatureGlob, Predicate, Target, TargetKey};
+use common::data;
+
+#[test]
+fn builds_canonical_expression() {
+    let expr = CfgExpr::feature("a")
+        .and(CfgExpr::not(CfgExpr::feature("b")))
+        .and(
+            CfgExpr::flag("test")
+                .unwrap()
+                .or(CfgExpr::key_value("panic", "abort").unwrap()),
+        );
+    assert_eq!(
+        expr.to_string(),
+        "all(feature = \"a\", not(feature = \"b\"), any(test, panic = \"abort\"))"
+    );
+    assert_eq!(parse_cfg(&expr.to_string()).unwrap(), expr);
+}
+
+#[test]
+fn builds_every_predicate() {
+    let expr = CfgExpr::all(vec![
+        CfgExpr::feature_glob("backend-*").unwrap(),
+        CfgExpr::env("CI"),
+        CfgExpr::env_value("PROFILE", "release"),
+        CfgExpr::target(TargetKey::PointerWidth, "64"),
+        CfgExpr::version("1.70".parse().unwrap()),
+    ]);
+    let text = "all(feature ~ \"backend-*\", env(\"CI\"), env(\"PROFILE\") = \"release\", \
+                target_pointer_width = \"64\", version(\"1.70\"))";
+    assert_eq!(expr.to_string(), text);
+    assert_eq!(parse_cfg(text).unwrap(), expr);
+    assert!(CfgExpr::feature_glob("[").is_err());
+}
+
+#[test]
+fn keys_are_checked() {
+    assert_eq!(
+        CfgExpr::key_value("target_os", "linux").unwrap(),
+        CfgExpr::target(TargetKey::Os, "linux")
+    );
+    assert!(CfgExpr::key_value("a b", "c").is_err());
+    assert!(CfgExpr::key_value("feature", "a").is_err());
+    assert!(CfgExpr::key_value("a", "\"").is_err());
+    assert!(CfgExpr::flag("a b").is_err());
+    assert!(CfgExpr::flag("feature").is_err());
+    assert!(CfgExpr::flag("").is_err());
+}
+
+#[test]
+fn evaluates_against_hypothetical_features() {
+    let expr = CfgExpr::feature("a").and(CfgExpr::not(CfgExpr::feature("b")));
+    let combinations: [&[&str]; 4] = [&[], &["a"], &["b"], &["a", "b"]];
+    let enabling: Vec<&[&str]> = combinations
+        .iter()
+        .copied()
+        .filter(|features| expr.evaluate(&data(features)))
+        .collect();
+    assert_eq!(enabling, [&["a"]]);
+    assert!(CfgExpr::all(Vec::new()).evaluate(&data(&[])));
+    assert!(!CfgExpr::any(Vec::new()).evaluate(&data(&[])));
+}
+
+/// Every variant, so new ones can't be missed
+fn variant_name(predicate: &Predicate) -> &'static str {
+    match predicate {
+        Predicate::Feature(_) => "feature",
+        Predicate::FeatureGlob(_) => "feature glob",
+        Predicate::Env { .. } => "env",
+        Predicate::Target { .. } => "target",
+        Predicate::KeyValue { .. } => "key value",
+        Predicate::Flag(_) => "flag",
+        Predicate::Version(_) => "version",
+    }
+}
+
+#[test]
+fn every_predicate_evaluates() {
+    let keys = [
+        TargetKey::Os,
+        TargetKey::Arch,
+        TargetKey::Family,
+        TargetKey::Env,
+        TargetKey::Vendor,
+        TargetKey::Endian,
+        TargetKey::PointerWidth,
+    ];
+    let mut predicates = vec![
+        Predicate::Feature(String::new()),
+        Predicate::Feature("a".to_owned()),
+        Predicate::FeatureGlob(FeatureGlob::new("*").unwrap()),
+        Predicate::FeatureGlob(FeatureGlob::new("").unwrap()),
+        Predicate::Env {
+            name: String::new(),
+            value: None,
+        },
+        Predicate::Env {
+            name: "A".to_owned(),
+            value: Some(String::new()),
+        },
+        Predicate::KeyValue {
+            key: "a b".to_owned(),
+            value: "\"".to_owned(),
+        },
+        Predicate::Flag(String::new()),
+        Predicate::Flag("unix".to_owned()),
+        Predicate::Version("0".parse().unwrap()),
+        Predicate::Version("1.70.0-nightly".parse().unwrap()),
+    ];
+    for key in keys.iter() {
+        for value in ["", "linux"].iter() {
+            predicates.push(Predicate::Target {
+                key: *key,
+                value: value.to_string(),
+            });
+        }
+    }
+    let populated = Data {
+        features: ["a", ""].iter().map(|f| f.to_string()).collect(),
+        env: std::iter::once(("A".to_owned(), String::new())).collect(),
+        cfg: std::iter::once(("a b".to_owned(), Some("\"".to_owned()))).collect(),
+        target: Target {
+            os: Some("linux".to_owned()),
+            family: vec!["unix".to_owned()],
+            ..Default::default()
+        },
+        version: Some("1.70".parse().unwrap()),
+        ..Default::default()
+    };
+    let mut seen = std::collections::HashSet::new();
+    for predicate in predicates {
+        seen.insert(variant_name(&predicate));
+        let expr = CfgExpr::Predicate(predicate);
+        for config in [Data::default(), populated.clone()].iter() {
+            // Result doesn't matter, only that there is one
+            let _: bool = expr.evaluate(config);
+        }
+    }
+    assert_eq!(seen.len(), 7);
+}
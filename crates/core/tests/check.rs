@@ -0,0 +1,171 @@
+#![cfg(feature = "walk")]
+
+mod common;
+
+use cfgcomment_core::{walkdir_parallel, LangRegistry, WalkOptions};
+use common::{data, walk};
+
+const INPUT: &str = "\
+//[cfg(feature = \"a\")]
+a();
+//[cfg(end)]
+b();
+//[cfg(not(feature = \"a\"))]
+c();
+d();
+//[cfg(end)]
+";
+
+#[test]
+fn reports_mismatching_lines_without_writing() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("lib.rs");
+    let clean = dir.path().join("clean.rs");
+    std::fs::write(&file, INPUT).unwrap();
+    std::fs::write(&clean, "b();\n").unwrap();
+
+    let summary = walk(
+        dir.path(),
+        WalkOptions {
+            check: true,
+            ..Default::default()
+        },
+    );
+    assert!(summary.errors.is_empty());
+    assert_eq!(summary.changed, vec![file.clone()]);
+    assert_eq!(summary.changed_lines[&file], vec![2]);
+    assert!(!summary.changed_lines.contains_key(&clean));
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), INPUT);
+
+    let summary = walkdir_parallel(
+        vec![dir.path().to_owned()],
+        data(&["a"]),
+        LangRegistry::with_defaults(),
+        WalkOptions {
+            check: true,
+            ..Default::default()
+        },
+    );
+    assert_eq!(summary.changed_lines[&file], vec![6, 7]);
+}
+
+#[test]
+fn stats() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("lib.rs"), INPUT).unwrap();
+    std::fs::write(
+        dir.path().join("other.rs"),
+        "//[cfg(feature = \"a\")]\n//# x\n//[cfg(end)]\n",
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("broken.rs"), "//[cfg(feature = \"b\")]\n").unwrap();
+    std::fs::write(dir.path().join("data.bin"), "").unwrap();
+
+    let summary = walkdir_parallel(
+        vec![dir.path().to_owned()],
+        data(&["a"]),
+        LangRegistry::with_defaults(),
+        WalkOptions {
+            check: true,
+            stats: true,
+            ..Default::default()
+        },
+    );
+    assert_eq!(summary.scanned, 3);
+    assert_eq!(summary.errors.len(), 1);
+    assert_eq!(summary.errors[0].1.line(), Some(1));
+    assert_eq!((summary.commented_lines, summary.uncommented_lines), (2, 1));
+    assert_eq!(summary.feature_blocks.get("a"), Some(&3));
+    assert_eq!(summary.feature_blocks.get("b"), None);
+}
+
+#[test]
+fn regions_of_every_file() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("lib.rs"), INPUT).unwrap();
+    std::fs::write(dir.path().join("plain.rs"), "b();\n").unwrap();
+
+    let summary = walk(
+        dir.path(),
+        WalkOptions {
+            check: true,
+            regions: true,
+            ..Default::default()
+        },
+    );
+    assert_eq!(summary.regions.len(), 1);
+    let regions = &summary.regions[&dir.path().join("lib.rs")];
+    let found: Vec<_> = regions
+        .iter()
+        .map(|r| (r.start, r.end, r.predicate_src.as_str(), r.commented))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (1, 3, "feature = \"a\"", false),
+            (5, 8, "not(feature = \"a\")", false),
+        ]
+    );
+    // Regions are listed regardless of pending changes
+    assert_eq!(summary.changed.len(), 1);
+}
+
+#[test]
+fn unreferenced_features() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("lib.rs"), INPUT).unwrap();
+    std::fs::write(
+        dir.path().join("inline.rs"),
+        "f(/*[cfg(feature ~ \"backend-*\")]*/ x /*[cfg(end)]*/);\n",
+    )
+    .unwrap();
+
+    let summary = walk(
+        dir.path(),
+        WalkOptions {
+            check: true,
+            ..Default::default()
+        },
+    );
+    let requested: Vec<String> = ["zz", "a", "backend-gl", "b"]
+        .iter()
+        .map(|f| f.to_string())
+        .collect();
+    assert_eq!(summary.unreferenced_features(&requested), ["b", "zz"]);
+}
+
+#[test]
+fn exclusive_conflicts() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("gl.rs"),
+        "//[cfg(feature = \"gl\")]\ngl();\n//[cfg(end)]\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("vk.rs"),
+        "//[cfg(all(unix, feature = \"vk\"))]\nvk();\n//[cfg(end)]\n\
+         //[cfg(feature = \"metal\")]\n//# metal();\n//[cfg(end)]\n",
+    )
+    .unwrap();
+
+    let summary = walk(
+        dir.path(),
+        WalkOptions {
+            check: true,
+            regions: true,
+            ..Default::default()
+        },
+    );
+    let groups = |list: &[&str]| vec![list.iter().map(|f| f.to_string()).collect()];
+    assert!(summary.exclusive_conflicts(&groups(&["gl", "metal"])).is_empty());
+    let conflicts = summary.exclusive_conflicts(&groups(&["gl", "vk", "metal"]));
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(
+        conflicts[0].active,
+        [
+            ("gl".to_owned(), dir.path().join("gl.rs"), 1),
+            ("vk".to_owned(), dir.path().join("vk.rs"), 1),
+        ]
+    );
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::apply;
+
+#[test]
+fn tag_like_comments_are_content() {
+    let input = "//[derive(Debug)]\n//[see docs]\n//[cfgx(feature = \"a\")]\n//[]\n";
+    assert_eq!(apply(input, "rs", &[]).unwrap(), input);
+}
+
+#[test]
+fn tag_like_comments_inside_block_are_toggled() {
+    let input = "//[cfg(feature = \"a\")]\n//[derive(Debug)]\n//[cfg(end)]\n";
+    let disabled = "//[cfg(feature = \"a\")]\n//# //[derive(Debug)]\n//[cfg(end)]\n";
+    assert_eq!(apply(input, "rs", &[]).unwrap(), disabled);
+    assert_eq!(apply(disabled, "rs", &["a"]).unwrap(), input);
+}
+
+#[test]
+fn nested_tags_are_never_commented() {
+    let input = "\
+//[cfg(feature = \"a\")]
+//[cfg(feature = \"b\")]
+b
+//[cfg(end)]
+//[cfg(end)]
+";
+    let disabled = "\
+//[cfg(feature = \"a\")]
+//[cfg(feature = \"b\")]
+//# b
+//[cfg(end)]
+//[cfg(end)]
+";
+    assert_eq!(apply(input, "rs", &["b"]).unwrap(), disabled);
+}
+
+#[test]
+fn trailing_whitespace_after_tag() {
+    let input = "//[cfg(feature = \"a\")]  \nb\n//[cfg(end)]\t\n";
+    assert_eq!(
+        apply(input, "rs", &[]).unwrap(),
+        "//[cfg(feature = \"a\")]  \n//# b\n//[cfg(end)]\t\n"
+    );
+}
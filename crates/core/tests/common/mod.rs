@@ -0,0 +1,33 @@
+//! Fixtures shared by integration tests, every test crate uses only some of them
+#![allow(dead_code)]
+
+#[cfg(feature = "walk")]
+use std::path::Path;
+
+use cfgcomment_core::{process_str, CfgCommentError, Data, LangDesc};
+#[cfg(feature = "walk")]
+use cfgcomment_core::{walkdir_parallel, LangRegistry, WalkOptions, WalkSummary};
+
+/// Config with only `features` enabled
+pub fn data(features: &[&str]) -> Data {
+    Data {
+        features: features.iter().map(|f| f.to_string()).collect(),
+        ..Default::default()
+    }
+}
+
+/// Processes `input` in default language for `ext` with only `features` enabled
+pub fn apply(input: &str, ext: &str, features: &[&str]) -> Result<String, CfgCommentError> {
+    process_str(input, &data(features), &LangDesc::default_list()[ext])
+}
+
+/// Walks `dir` with default config and languages
+#[cfg(feature = "walk")]
+pub fn walk(dir: &Path, options: WalkOptions) -> WalkSummary {
+    walkdir_parallel(
+        vec![dir.to_owned()],
+        Data::default(),
+        LangRegistry::with_defaults(),
+        options,
+    )
+}
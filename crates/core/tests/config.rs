@@ -0,0 +1,137 @@
+use cfgcomment_core::{process_str, CfgCommentError, Config, Data};
+
+const LANGUAGES: &str = r##"
+[languages.py]
+cfg_prefix = "#["
+cfg_prefix_comment_len = 1
+cfg_suffix = "]"
+comment = "#- "
+
+[languages.rs]
+cfg_prefix = "//["
+cfg_prefix_comment_len = 2
+cfg_suffix = "]"
+comment = "//~ "
+"##;
+
+#[test]
+fn discovered_upward() {
+    let dir = tempfile::tempdir().unwrap();
+    let nested = dir.path().join("src/module");
+    std::fs::create_dir_all(&nested).unwrap();
+    std::fs::write(dir.path().join("cfgcomment.toml"), LANGUAGES).unwrap();
+    std::fs::write(nested.join("lib.py"), "").unwrap();
+
+    let config = Config::discover(&nested.join("lib.py")).unwrap().unwrap();
+    let languages = config.lang_config();
+    let lang = |path: &str| languages.get_for_path(path.as_ref()).unwrap();
+    assert_eq!(lang("a.rs").comment, "//~ ");
+    assert_eq!(lang("Cargo.toml").comment, "#- ");
+    assert_eq!(
+        process_str("#[cfg(feature = \"a\")]\na\n#[cfg(end)]\n", &Data::default(), lang("a.py"))
+            .unwrap(),
+        "#[cfg(feature = \"a\")]\n#- a\n#[cfg(end)]\n"
+    );
+    assert_eq!(
+        Config::discover_path(&nested).unwrap(),
+        Some(std::path::absolute(dir.path().join("cfgcomment.toml")).unwrap())
+    );
+}
+
+#[test]
+fn extensions_and_filenames() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("cfgcomment.toml"),
+        r##"
+[languages.shell]
+extensions = ["sh", "bash"]
+filenames = ["build"]
+cfg_prefix = "#["
+cfg_prefix_comment_len = 1
+cfg_suffix = "]"
+comment = "#~ "
+"##,
+    )
+    .unwrap();
+    let languages = Config::discover(dir.path()).unwrap().unwrap().lang_config();
+    for path in ["a.sh", "a.bash", "scripts/build"] {
+        assert_eq!(languages.get_for_path(path.as_ref()).unwrap().comment, "#~ ");
+    }
+    assert!(languages.get_for_path("a.shell".as_ref()).is_none());
+}
+
+#[test]
+fn dotfile_takes_precedence() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("cfgcomment.toml"), LANGUAGES).unwrap();
+    std::fs::write(dir.path().join(".cfgcomment.toml"), "committed_features = [\"a\"]").unwrap();
+
+    let config = Config::discover(dir.path()).unwrap().unwrap();
+    assert!(config.languages.is_empty());
+    assert!(config.committed_features.unwrap().contains("a"));
+}
+
+#[test]
+fn invalid_language_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("cfgcomment.toml"),
+        LANGUAGES.replace("#- ", " "),
+    )
+    .unwrap();
+    assert!(matches!(
+        Config::discover(dir.path()),
+        Err(CfgCommentError::BlankComment(_))
+    ));
+}
+
+#[test]
+fn unknown_language_field_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("cfgcomment.toml"),
+        "[languages.foo]\ncfg_prefix = \"//[\"\ncfg_prefix_comment_len = 2\ncfg_suffix = \"]\"\n\
+         cfg_suffix_comment_len = 0\ncomment = \"//# \"\nextensions = [\"foo\"]\ncoment_end = \"x\"\n",
+    )
+    .unwrap();
+    match Config::discover(dir.path()) {
+        Err(CfgCommentError::ConfigParse { source, .. }) => {
+            assert!(source.to_string().contains("coment_end"), "{}", source)
+        }
+        r => panic!("unexpected result: {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn presets() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("cfgcomment.toml"),
+        "[preset.embedded]\nfeatures = [\"no-std\", \"defmt\"]\n",
+    )
+    .unwrap();
+    let config = Config::discover(dir.path()).unwrap().unwrap();
+    assert_eq!(config.preset("embedded").unwrap().features, ["no-std", "defmt"]);
+    assert!(matches!(
+        config.preset("desktop"),
+        Err(CfgCommentError::UnknownPreset(name)) if name == "desktop"
+    ));
+}
+
+#[test]
+fn exclusive_features() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("cfgcomment.toml"),
+        "exclusive_features = [[\"gl\", \"vk\", \"metal\"]]\n",
+    )
+    .unwrap();
+    let config = Config::discover(dir.path()).unwrap().unwrap();
+    let features = |list: &[&str]| list.iter().map(|f| f.to_string()).collect();
+    assert!(config.check_exclusive(&features(&["gl", "std"])).is_ok());
+    assert!(matches!(
+        config.check_exclusive(&features(&["vk", "std", "metal"])),
+        Err(CfgCommentError::ExclusiveFeatures(enabled)) if enabled == ["vk", "metal"]
+    ));
+}
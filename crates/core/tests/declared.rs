@@ -0,0 +1,46 @@
+use cfgcomment_core::{process_str, CfgCommentError, Data, LangDesc};
+
+fn data(declared: &[&str]) -> Data {
+    Data {
+        declared_features: Some(declared.iter().map(|f| f.to_string()).collect()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn typo_is_reported() {
+    let input = "//[cfg(feature = \"std\")]\na\n//[cfg(else if not(feature = \"feautre-x\"))]\nb\n//[cfg(end)]\n";
+    let err = process_str(input, &data(&["std", "feature-x"]), &LangDesc::new("//")).unwrap_err();
+    assert!(
+        matches!(
+            &err,
+            CfgCommentError::UndeclaredFeature { line: 3, feature, suggestion: Some(s) }
+                if feature == "feautre-x" && s == "feature-x"
+        ),
+        "{:?}",
+        err
+    );
+    assert_eq!(
+        err.to_string(),
+        "feature \"feautre-x\" on line 3 is not declared in project config, did you mean \"feature-x\"?"
+    );
+}
+
+#[test]
+fn unrelated_name_has_no_suggestion() {
+    let input = "a(/*[cfg(feature = \"gpu\")]*/ b /*[cfg(end)]*/);\n";
+    let err = process_str(input, &data(&["std"]), &LangDesc::default_list()["rs"]).unwrap_err();
+    assert!(
+        matches!(err, CfgCommentError::UndeclaredFeature { line: 1, suggestion: None, .. }),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn declared_and_unchecked() {
+    let input = "//[cfg(all(feature = \"std\", feature ~ \"backend-*\"))]\na\n//[cfg(end)]\n";
+    process_str(input, &data(&["std"]), &LangDesc::new("//")).unwrap();
+    let input = "//[cfg(feature = \"anything\")]\na\n//[cfg(end)]\n";
+    process_str(input, &Data::default(), &LangDesc::new("//")).unwrap();
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "walk")]
+
+mod common;
+
+use cfgcomment_core::{
+    process_str, walkdir_parallel, CfgCommentError, Data, LangDesc, LangRegistry, WalkOptions,
+};
+use common::walk;
+
+const MALFORMED: &str = "a\n    //[cfg(feature = b)]\nb\n//[cfg(end)]\n";
+
+#[test]
+fn malformed_tag_is_reported() {
+    match process_str(MALFORMED, &Data::default(), &LangDesc::default_list()["rs"]) {
+        Err(CfgCommentError::InvalidTag {
+            line: 2,
+            column: 22,
+            text,
+            ..
+        }) => assert_eq!(text, "[cfg(feature = b)]"),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn walker_continues_after_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let broken = dir.path().join("broken.rs");
+    let fine = dir.path().join("fine.rs");
+    std::fs::write(&broken, MALFORMED).unwrap();
+    std::fs::write(&fine, "//[cfg(feature = \"a\")]\na\n//[cfg(end)]\n").unwrap();
+
+    let summary = walk(dir.path(), WalkOptions::default());
+    assert_eq!(summary.changed, vec![fine.clone()]);
+    assert_eq!(summary.errors.len(), 1);
+    assert_eq!(summary.errors[0].0, broken);
+    assert_eq!(std::fs::read_to_string(&broken).unwrap(), MALFORMED);
+    assert_eq!(
+        std::fs::read_to_string(&fine).unwrap(),
+        "//[cfg(feature = \"a\")]\n//# a\n//[cfg(end)]\n"
+    );
+}
+
+#[test]
+fn underindented_line_is_reported() {
+    let input = "    //[cfg(feature = \"a\")]\n  a\n    //[cfg(end)]\n";
+    assert!(matches!(
+        process_str(input, &Data::default(), &LangDesc::default_list()["rs"]),
+        Err(CfgCommentError::Underindented { line: 2, .. })
+    ));
+}
+
+#[test]
+fn missing_path_is_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing");
+    let summary = walkdir_parallel(
+        vec![missing.clone()],
+        Data::default(),
+        LangRegistry::with_defaults(),
+        WalkOptions::default(),
+    );
+    assert_eq!(summary.errors.len(), 1);
+    assert_eq!(summary.errors[0].0, missing);
+    assert!(matches!(summary.errors[0].1, CfgCommentError::Walk(_)));
+}
@@ -0,0 +1,92 @@
+#![cfg(feature = "walk")]
+
+mod common;
+
+use cfgcomment_core::{
+    diff_lines, process_str, unified_diff, Data, DiffLine, LangDesc, WalkOptions,
+};
+use common::walk;
+
+fn lines(s: &str) -> Vec<String> {
+    s.lines().map(|l| l.to_owned()).collect()
+}
+
+#[test]
+fn processed_file() {
+    let input = "a\nb\nc\nd\n//[cfg(feature = \"x\")]\ne\n//[cfg(end)]\nf\ng\nh\ni\n";
+    let output = process_str(input, &Data::default(), &LangDesc::new("//")).unwrap();
+    let hunks = diff_lines(&lines(input), &lines(&output), 3);
+    assert_eq!(hunks.len(), 1);
+    assert_eq!(
+        unified_diff("a/lib.rs", "b/lib.rs", &hunks),
+        "\
+--- a/lib.rs
++++ b/lib.rs
+@@ -3,7 +3,7 @@
+ c
+ d
+ //[cfg(feature = \"x\")]
+-e
++//# e
+ //[cfg(end)]
+ f
+ g
+"
+    );
+}
+
+#[test]
+fn close_changes_share_hunk() {
+    let old = lines("1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14\n15\n16\n");
+    let mut new = old.clone();
+    new[1] = "two".to_owned();
+    new[6] = "seven".to_owned();
+    new[14] = "fifteen".to_owned();
+    let hunks = diff_lines(&old, &new, 2);
+    assert_eq!(
+        hunks
+            .iter()
+            .map(|h| (h.old_start, h.old_len, h.new_start, h.new_len))
+            .collect::<Vec<_>>(),
+        vec![(1, 9, 1, 9), (13, 4, 13, 4)]
+    );
+    assert!(diff_lines(&old, &old, 3).is_empty());
+    assert_eq!(unified_diff("a", "b", &[]), "");
+}
+
+#[test]
+fn different_lengths() {
+    let hunks = diff_lines(&lines("a\nb\n"), &lines("a\nc\nd\n"), 1);
+    assert_eq!(hunks.len(), 1);
+    assert_eq!((hunks[0].old_len, hunks[0].new_len), (2, 3));
+    assert_eq!(
+        hunks[0].lines,
+        vec![
+            DiffLine::Context("a".to_owned()),
+            DiffLine::Removed("b".to_owned()),
+            DiffLine::Added("c".to_owned()),
+            DiffLine::Added("d".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn walker_collects_diffs_without_writing() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("lib.rs");
+    let input = "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n";
+    std::fs::write(&file, input).unwrap();
+    let summary = walk(
+        dir.path(),
+        WalkOptions {
+            diff: true,
+            ..Default::default()
+        },
+    );
+    assert_eq!(summary.changed, vec![file.clone()]);
+    assert_eq!(
+        summary.diffs[&file][0].lines[1],
+        DiffLine::Removed("a();".to_owned())
+    );
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), input);
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use cfgcomment_core::CfgCommentError;
+use common::apply;
+
+const INPUT: &str = "\
+//[cfg(feature = \"a\")]
+a
+//[cfg(else)]
+not a
+//[cfg(end)]
+";
+
+#[test]
+fn else_branch_is_flipped() {
+    assert_eq!(
+        apply(INPUT, "rs", &["a"]).unwrap(),
+        "//[cfg(feature = \"a\")]\na\n//[cfg(else)]\n//# not a\n//[cfg(end)]\n"
+    );
+    assert_eq!(
+        apply(INPUT, "rs", &[]).unwrap(),
+        "//[cfg(feature = \"a\")]\n//# a\n//[cfg(else)]\nnot a\n//[cfg(end)]\n"
+    );
+}
+
+#[test]
+fn else_of_disabled_outer_block_stays_disabled() {
+    let input = "\
+//[cfg(feature = \"outer\")]
+//[cfg(feature = \"a\")]
+a
+//[cfg(else)]
+not a
+//[cfg(end)]
+//[cfg(end)]
+";
+    assert!(apply(input, "rs", &[])
+        .unwrap()
+        .lines()
+        .all(|l| l.starts_with("//")));
+}
+
+#[test]
+fn misplaced_else() {
+    assert!(matches!(
+        apply("a\n//[cfg(else)]\n", "rs", &[]),
+        Err(CfgCommentError::UnexpectedElse { line: 2 })
+    ));
+    let input = "//[cfg(feature = \"a\")]\n//[cfg(else)]\n//[cfg(else)]\n//[cfg(end)]\n";
+    assert!(matches!(
+        apply(input, "rs", &[]),
+        Err(CfgCommentError::DuplicateElse { line: 3 })
+    ));
+}
+
+const CHAIN: &str = "\
+//[cfg(feature = \"a\")]
+a
+//[cfg(else if feature = \"b\")]
+b
+//[cfg(else if any(feature = \"a\", feature = \"c\"))]
+c
+//[cfg(else)]
+none
+//[cfg(end)]
+";
+
+fn enabled_lines(output: &str) -> Vec<&str> {
+    output.lines().filter(|l| !l.starts_with("//")).collect()
+}
+
+#[test]
+fn else_if_chain_enables_first_match() {
+    for (features, enabled) in [
+        (&["a", "b", "c"][..], "a"),
+        (&["b", "c"][..], "b"),
+        (&["c"][..], "c"),
+        (&[][..], "none"),
+    ] {
+        assert_eq!(enabled_lines(&apply(CHAIN, "rs", features).unwrap()), [enabled]);
+    }
+}
+
+#[test]
+fn else_if_after_else() {
+    let input = "//[cfg(feature = \"a\")]\n//[cfg(else)]\n//[cfg(else if feature = \"b\")]\n//[cfg(end)]\n";
+    assert!(matches!(
+        apply(input, "rs", &[]),
+        Err(CfgCommentError::DuplicateElse { line: 3 })
+    ));
+}
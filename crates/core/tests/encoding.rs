@@ -0,0 +1,116 @@
+#![cfg(feature = "walk")]
+
+mod common;
+
+use cfgcomment_core::{
+    looks_binary, CfgCommentError, Encoding, WalkOptions,
+    WalkSummary,
+};
+use common::walk;
+
+const INPUT: &str = "//[cfg(feature = \"a\")]\r\na(\"é\");\r\n//[cfg(end)]\r\n";
+const DISABLED: &str = "//[cfg(feature = \"a\")]\r\n//# a(\"é\");\r\n//[cfg(end)]\r\n";
+
+fn apply(file: &str, contents: &[u8], options: WalkOptions) -> (WalkSummary, Vec<u8>) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(file);
+    std::fs::write(&path, contents).unwrap();
+    let summary = walk(dir.path(), options);
+    (summary, std::fs::read(&path).unwrap())
+}
+
+#[test]
+fn roundtrip() {
+    for encoding in [
+        Encoding::Utf8,
+        Encoding::Utf8Bom,
+        Encoding::Utf16Le,
+        Encoding::Utf16Be,
+    ]
+    .iter()
+    {
+        let bytes = encoding.encode(INPUT);
+        assert_eq!(
+            Encoding::decode(&bytes),
+            Some((*encoding, INPUT.to_owned()))
+        );
+    }
+    assert_eq!(Encoding::decode(b"caf\xe9"), None);
+    assert_eq!(Encoding::decode(b"\xff\xfea"), None);
+}
+
+#[test]
+fn byte_order_mark_is_kept() {
+    for encoding in [Encoding::Utf8Bom, Encoding::Utf16Le, Encoding::Utf16Be].iter() {
+        let (summary, written) = apply("lib.rs", &encoding.encode(INPUT), WalkOptions::default());
+        assert!(summary.errors.is_empty(), "{:?}", summary.errors);
+        assert_eq!(written, encoding.encode(DISABLED));
+    }
+}
+
+#[test]
+fn shebang_after_byte_order_mark() {
+    let script = "#!/bin/sh\n#[cfg(feature = \"a\")]\necho a\n#[cfg(end)]\n";
+    let (summary, written) = apply(
+        "run",
+        &Encoding::Utf8Bom.encode(script),
+        WalkOptions::default(),
+    );
+    assert_eq!(summary.changed.len(), 1);
+    assert_eq!(
+        written,
+        Encoding::Utf8Bom.encode("#!/bin/sh\n#[cfg(feature = \"a\")]\n#- echo a\n#[cfg(end)]\n")
+    );
+}
+
+#[test]
+fn unknown_encoding() {
+    let latin1 = b"//[cfg(feature = \"a\")]\na(\"\xe9\");\n//[cfg(end)]\n";
+    let (summary, written) = apply("lib.rs", latin1, WalkOptions::default());
+    assert!(matches!(
+        summary.errors[..],
+        [(_, CfgCommentError::UnknownEncoding)]
+    ));
+    assert_eq!(written, latin1);
+
+    let options = WalkOptions {
+        skip_unknown_encoding: true,
+        ..Default::default()
+    };
+    let (summary, written) = apply("lib.rs", latin1, options);
+    assert!(summary.errors.is_empty());
+    assert!(summary.changed.is_empty());
+    assert_eq!(written, latin1);
+}
+
+#[test]
+fn mostly_non_ascii_latin1_is_unknown_encoding() {
+    let latin1 = b"//[cfg(feature = \"a\")]\n\xe9\xe8\xe0\xe7\xf4\xfb\xee\xe2\xe9\xe8\xe0\xe7\xf4\xfb\xee\xe2\n//[cfg(end)]\n";
+    assert!(looks_binary(latin1));
+    // Files this large are memory mapped
+    let large = latin1.repeat((1 << 20) / latin1.len() + 1);
+    for input in [&latin1[..], &large].iter() {
+        let (summary, written) = apply("lib.rs", input, WalkOptions::default());
+        assert!(matches!(
+            summary.errors[..],
+            [(_, CfgCommentError::UnknownEncoding)]
+        ));
+        assert_eq!(&written, input);
+    }
+}
+
+#[test]
+fn binary_files_are_skipped() {
+    // MPEG transport stream packet, with `.ts` extension
+    let mut stream = vec![0x47, 0x40, 0x00, 0x10, 0x00];
+    stream.extend(b"//[cfg(feature = \"a\")]\na\n//[cfg(end)]\n");
+    let (summary, written) = apply("video.ts", &stream, WalkOptions::default());
+    assert!(summary.errors.is_empty());
+    assert!(summary.changed.is_empty());
+    assert_eq!(written, stream);
+
+    assert!(looks_binary(&[0x89, b'P', b'N', b'G', 0xff, 0xd8, 0xfe, 0xc0, 0xa1]));
+    assert!(!looks_binary(b"plain text"));
+    assert!(!looks_binary("caf\u{e9} au lait".as_bytes()));
+    assert!(!looks_binary(&Encoding::Utf16Le.encode("text")));
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use cfgcomment_core::{explain, Branch, BranchKind, Evaluation, LangDesc};
+use common::data;
+
+fn leaf(predicate: &str, value: bool) -> Evaluation {
+    Evaluation {
+        predicate: predicate.to_owned(),
+        value,
+        operands: vec![],
+    }
+}
+
+const INPUT: &str = "\
+//[cfg(any(feature = \"a\", not(feature = \"b\")))]
+a();
+  //[cfg(feature = \"c\")]
+  c();
+  //[cfg(end)]
+//[cfg(else)]
+b();
+//[cfg(end)]
+";
+
+#[test]
+fn sub_expressions() {
+    let branches = explain(INPUT, &data(&["b", "c"]), &LangDesc::new("//")).unwrap();
+    assert_eq!(
+        branches,
+        vec![
+            Branch {
+                line: 1,
+                kind: BranchKind::Start,
+                nesting_depth: 0,
+                evaluation: Some(Evaluation {
+                    predicate: "any(feature = \"a\", not(feature = \"b\"))".to_owned(),
+                    value: false,
+                    operands: vec![
+                        leaf("feature = \"a\"", false),
+                        Evaluation {
+                            predicate: "not(feature = \"b\")".to_owned(),
+                            value: false,
+                            operands: vec![leaf("feature = \"b\"", true)],
+                        },
+                    ],
+                }),
+                enclosing_enabled: true,
+                earlier_branch_matched: false,
+                enabled: false,
+            },
+            Branch {
+                line: 3,
+                kind: BranchKind::Start,
+                nesting_depth: 1,
+                evaluation: Some(leaf("feature = \"c\"", true)),
+                enclosing_enabled: false,
+                earlier_branch_matched: false,
+                enabled: false,
+            },
+            Branch {
+                line: 6,
+                kind: BranchKind::Else,
+                nesting_depth: 0,
+                evaluation: None,
+                enclosing_enabled: true,
+                earlier_branch_matched: false,
+                enabled: true,
+            },
+        ]
+    );
+}
+
+#[test]
+fn earlier_branch_matched() {
+    let branches = explain(INPUT, &data(&["a"]), &LangDesc::new("//")).unwrap();
+    let states: Vec<_> = branches
+        .iter()
+        .map(|b| (b.line, b.enabled, b.earlier_branch_matched))
+        .collect();
+    assert_eq!(states, vec![(1, true, false), (3, false, false), (6, false, true)]);
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use cfgcomment_core::{regions, CfgCommentError, LangDesc};
+use common::apply;
+
+const INPUT: &str = "\
+//[cfg_file(feature = \"gui\")]
+use gui::Window;
+
+//[cfg(feature = \"dark\")]
+fn dark() {}
+//[cfg(end)]
+";
+
+#[test]
+fn file_tag_toggles_whole_file() {
+    assert_eq!(apply(INPUT, "rs", &["gui", "dark"]).unwrap(), INPUT);
+    assert_eq!(
+        apply(INPUT, "rs", &["dark"]).unwrap(),
+        "//[cfg_file(feature = \"gui\")]\n//# use gui::Window;\n\n//[cfg(feature = \"dark\")]\n//# fn dark() {}\n//[cfg(end)]\n"
+    );
+}
+
+#[test]
+fn file_tag_region_wraps_others() {
+    let regions = regions(INPUT, &LangDesc::default_list()["rs"], false).unwrap();
+    assert_eq!((regions[0].start, regions[0].end), (1, 6));
+    assert_eq!(regions[1].nesting_depth, 1);
+}
+
+#[test]
+fn file_tag_must_be_first() {
+    assert!(matches!(
+        apply("a\n//[cfg_file(feature = \"gui\")]\n", "rs", &[]),
+        Err(CfgCommentError::MisplacedFileTag { line: 2 })
+    ));
+    assert!(matches!(
+        apply("//[cfg_file(feature = \"gui\")]\n//[cfg(end)]\n", "rs", &[]),
+        Err(CfgCommentError::UnexpectedEnd { line: 2 })
+    ));
+}
@@ -0,0 +1,39 @@
+use cfgcomment_core::{format_tags, LangDesc};
+
+#[test]
+fn canonical_tags() {
+    let input = "\
+//[ cfg( feature=\"a\" ) ]
+  //[cfg(else if any(feature = \"c\",feature= \"b\",))]\r
+//# x(/*[cfg(feature=\"a\")]*/ y /*[cfg(end)]*/);
+//[cfg_line(not( env( \"CI\" )))]
+//[cfg(end   feature=\"a\")]
+//[derive(Debug)]
+";
+    let desc = LangDesc::default_list()["rs"].clone();
+    assert_eq!(
+        format_tags(input, &desc, false).unwrap(),
+        "\
+//[cfg(feature = \"a\")]
+  //[cfg(else if any(feature = \"c\", feature = \"b\"))]\r
+//# x(/*[cfg(feature=\"a\")]*/ y /*[cfg(end)]*/);
+//[cfg_line(not(env(\"CI\")))]
+//[cfg(end feature = \"a\")]
+//[derive(Debug)]
+"
+    );
+    let sorted = format_tags(input, &desc, true).unwrap();
+    assert_eq!(
+        sorted.split('\n').nth(1),
+        Some("  //[cfg(else if any(feature = \"b\", feature = \"c\"))]\r")
+    );
+}
+
+#[test]
+fn custom_delimiters() {
+    let desc = LangDesc::block("/*", "*/").with_tag_delimiters("{", "}");
+    assert_eq!(
+        format_tags("/*{ cfg(all(b,a)) }*/\n", &desc, true).unwrap(),
+        "/*{cfg(all(a, b))}*/\n"
+    );
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use cfgcomment_core::{CfgCommentError, CfgExpr, FeatureGlob, Predicate};
+use common::apply;
+
+const INPUT: &str = "//[cfg(feature ~ \"backend-*\")]\nbackend\n//[cfg(end)]\n";
+const DISABLED: &str = "//[cfg(feature ~ \"backend-*\")]\n//# backend\n//[cfg(end)]\n";
+
+#[test]
+fn glob_matches_any_feature() {
+    assert_eq!(apply(INPUT, "rs", &["default", "backend-gl"]).unwrap(), INPUT);
+    assert_eq!(apply(INPUT, "rs", &["backend-vk", "backend-gl"]).unwrap(), INPUT);
+    assert_eq!(apply(INPUT, "rs", &["default", "backend"]).unwrap(), DISABLED);
+    assert_eq!(apply(INPUT, "rs", &[]).unwrap(), DISABLED);
+}
+
+#[test]
+fn glob_is_validated_on_construction() {
+    assert!(matches!(
+        FeatureGlob::new("["),
+        Err(CfgCommentError::InvalidGlob { ref pattern, .. }) if pattern == "["
+    ));
+    let glob = CfgExpr::Predicate(Predicate::FeatureGlob(FeatureGlob::new("backend-*").unwrap()));
+    assert!(glob.evaluate(&common::data(&["backend-gl"])));
+    assert_eq!(glob.to_string(), "feature ~ \"backend-*\"");
+}
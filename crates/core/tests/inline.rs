@@ -0,0 +1,56 @@
+mod common;
+
+use cfgcomment_core::CfgCommentError;
+use common::apply;
+
+const INPUT: &str = "foo(1, /*[cfg(feature = \"x\")]*/ extra_arg, /*[cfg(end)]*/ 2);\n";
+const DISABLED: &str = "foo(1, /*[cfg(feature = \"x\")]*/ /*# extra_arg, */ /*[cfg(end)]*/ 2);\n";
+
+#[test]
+fn inline_segment_is_toggled() {
+    assert_eq!(apply(INPUT, "rs", &["x"]).unwrap(), INPUT);
+    assert_eq!(apply(INPUT, "rs", &[]).unwrap(), DISABLED);
+    assert_eq!(apply(DISABLED, "rs", &["x"]).unwrap(), INPUT);
+}
+
+#[test]
+fn inline_else() {
+    let input = "let v = /*[cfg(feature = \"x\")]*/ 1 /*[cfg(else)]*/ 2 /*[cfg(end)]*/;\n";
+    assert_eq!(
+        apply(input, "rs", &["x"]).unwrap(),
+        "let v = /*[cfg(feature = \"x\")]*/ 1 /*[cfg(else)]*/ /*# 2 */ /*[cfg(end)]*/;\n"
+    );
+}
+
+#[test]
+fn inline_inside_disabled_block() {
+    let input = "//[cfg(feature = \"a\")]\nfoo(/*[cfg(feature = \"x\")]*/ x /*[cfg(end)]*/);\n//[cfg(end)]\n";
+    assert_eq!(
+        apply(input, "rs", &["x"]).unwrap(),
+        "//[cfg(feature = \"a\")]\n//# foo(/*[cfg(feature = \"x\")]*/ /*# x */ /*[cfg(end)]*/);\n//[cfg(end)]\n"
+    );
+}
+
+#[test]
+fn ordinary_block_comments_are_content() {
+    let input = "foo(/* [not a tag] */ 1);\n";
+    assert_eq!(apply(input, "rs", &[]).unwrap(), input);
+}
+
+#[test]
+fn inline_region_must_close_on_same_line() {
+    assert!(matches!(
+        apply("foo(/*[cfg(feature = \"x\")]*/ 1,\n2);\n", "rs", &[]),
+        Err(CfgCommentError::Unclosed { line: 1, .. })
+    ));
+}
+
+#[test]
+fn comment_closer_in_disabled_segment_is_refused() {
+    let input = "foo(1, /*[cfg(feature = \"x\")]*/ a /* note */, /*[cfg(end)]*/ 2);\n";
+    assert!(matches!(
+        apply(input, "rs", &[]),
+        Err(CfgCommentError::CommentCloser { line: 1, ref close }) if close == "*/"
+    ));
+    assert!(apply(input, "rs", &["x"]).is_ok());
+}
@@ -0,0 +1,183 @@
+#![cfg(feature = "walk")]
+
+mod common;
+
+use cfgcomment_core::{
+    magic_lang, process_str, shebang_interpreter, walkdir_parallel, CfgCommentError, LangDesc,
+    LangRegistry, WalkOptions,
+};
+use common::{data, walk};
+
+#[test]
+fn blank_comment_is_rejected() {
+    for comment in ["", " ", "\t "] {
+        assert!(matches!(
+            LangDesc::new("--").with_comment(comment).validate(),
+            Err(CfgCommentError::BlankComment(c)) if c == comment
+        ));
+    }
+    assert!(LangDesc::new("--").validate().is_ok());
+}
+
+#[test]
+fn blank_comment_is_rejected_on_processing() {
+    let desc = LangDesc::new("--").with_comment("  ");
+    let input = "--[cfg(feature = \"a\")]\na\n--[cfg(end)]\n";
+    assert!(matches!(
+        process_str(input, &data(&[]), &desc),
+        Err(CfgCommentError::BlankComment(_))
+    ));
+
+    // Large file is processed memory-mapped
+    let large = input.repeat((2 << 20) / input.len());
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.lua"), input).unwrap();
+    std::fs::write(dir.path().join("large.lua"), &large).unwrap();
+    let mut registry = LangRegistry::default();
+    registry.insert_extension("lua", desc);
+    let summary = walkdir_parallel(
+        vec![dir.path().to_owned()],
+        data(&[]),
+        registry,
+        WalkOptions::default(),
+    );
+    assert_eq!(summary.errors.len(), 2);
+    for (_, e) in &summary.errors {
+        assert!(matches!(e, CfgCommentError::BlankComment(_)));
+    }
+    assert_eq!(std::fs::read_to_string(dir.path().join("a.lua")).unwrap(), input);
+    assert_eq!(std::fs::read_to_string(dir.path().join("large.lua")).unwrap(), large);
+}
+
+#[test]
+fn default_list_is_valid() {
+    for desc in LangDesc::default_list().values() {
+        desc.validate().unwrap();
+    }
+}
+
+#[test]
+fn registry_matches_paths() {
+    let registry = LangRegistry::with_defaults();
+    let comment = |path: &str| registry.get_for_path(path.as_ref()).map(|d| d.comment.as_str());
+    assert_eq!(comment("src/Makefile"), Some("#- "));
+    assert_eq!(comment("CMakeLists.txt"), Some("#- "));
+    assert_eq!(comment("ci.yml"), Some("#- "));
+    assert_eq!(comment("include/a.hpp"), Some("//# "));
+    assert_eq!(comment("Makefile.am"), None);
+    assert_eq!(comment(".rs"), None);
+
+    let mut registry = registry.clone();
+    registry
+        .register(LangDesc::block("{{--", "--}}"), &["blade.php"], &[])
+        .unwrap()
+        .register(LangDesc::new("//"), &["php"], &[])
+        .unwrap();
+    assert_eq!(registry.get_for_path("v.blade.php".as_ref()).unwrap().comment, "{{--# ");
+    assert_eq!(registry.get_for_path("v.php".as_ref()).unwrap().comment, "//# ");
+}
+
+#[test]
+fn magic_comment_overrides_path() {
+    assert_eq!(magic_lang("# cfgcomment: lang=shell"), Some("shell"));
+    assert_eq!(magic_lang("<!-- cfgcomment: lang=html -->"), Some("html"));
+    assert_eq!(magic_lang("# cfgcomment"), None);
+    assert_eq!(magic_lang("--cfgcomment: lang=sql"), Some("sql"));
+    // Only comment opening may precede marker
+    assert_eq!(magic_lang("cfgcomment: lang=toml"), None);
+    assert_eq!(magic_lang("let s = \"// cfgcomment: lang=toml\";"), None);
+    assert_eq!(magic_lang("\"cfgcomment: lang=toml\""), None);
+    assert_eq!(magic_lang("fn main() {} // cfgcomment: lang=toml"), None);
+
+    let registry = LangRegistry::with_defaults();
+    let resolve = |path: &str, line: &str| {
+        registry
+            .resolve(path.as_ref(), line)
+            .map(|d| d.map(|d| d.comment.clone()))
+    };
+    assert_eq!(resolve("build", "# cfgcomment: lang=python\n").unwrap().as_deref(), Some("#- "));
+    assert_eq!(resolve("a.rs", "// cfgcomment: lang=toml\n").unwrap().as_deref(), Some("#- "));
+    assert_eq!(resolve("a.rs", "fn main() {}\n").unwrap().as_deref(), Some("//# "));
+    let literal = "const S: &str = \"// cfgcomment: lang=toml\";\n";
+    assert_eq!(resolve("a.rs", literal).unwrap().as_deref(), Some("//# "));
+    assert!(matches!(
+        resolve("a.rs", "// cfgcomment: lang=cobol\n"),
+        Err(CfgCommentError::UnknownLang(name)) if name == "cobol"
+    ));
+}
+
+#[test]
+fn walker_uses_magic_comment() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("configure");
+    std::fs::write(&script, "# cfgcomment: lang=shell\n#[cfg(feature = \"a\")]\na\n#[cfg(end)]\n").unwrap();
+    let summary = walk(dir.path(), WalkOptions::default());
+    assert_eq!(summary.changed, vec![script.clone()]);
+    assert_eq!(
+        std::fs::read_to_string(&script).unwrap(),
+        "# cfgcomment: lang=shell\n#[cfg(feature = \"a\")]\n#- a\n#[cfg(end)]\n"
+    );
+}
+
+#[test]
+fn builder_defaults() {
+    let desc = LangDesc::new("--");
+    assert_eq!(
+        (desc.cfg_prefix.as_str(), desc.cfg_prefix_comment_len, desc.comment.as_str()),
+        ("--[", 2, "--# ")
+    );
+    let desc = LangDesc::block("(*", "*)").with_keyword("when");
+    assert_eq!(
+        (desc.cfg_prefix.as_str(), desc.cfg_suffix.as_str(), desc.cfg_keyword.as_str()),
+        ("(*[", "]*)", "when")
+    );
+    assert_eq!(desc.comment_end.as_deref(), Some(" #*)"));
+}
+
+#[test]
+fn register_validates() {
+    let mut registry = LangRegistry::default();
+    assert!(registry
+        .register(LangDesc::new("--").with_comment(" "), &["sql"], &[])
+        .is_err());
+    assert!(registry.get_for_path("a.sql".as_ref()).is_none());
+}
+
+#[test]
+fn extension_case_and_normalization() {
+    let mut registry = LangRegistry::with_defaults();
+    assert_eq!(registry.get_for_path("MAIN.RS".as_ref()).unwrap().comment, "//# ");
+    assert_eq!(registry.get_for_path("Cargo.Toml".as_ref()).unwrap().comment, "#- ");
+
+    // Decomposed `é` in extension matches precomposed registration and vice versa
+    registry.insert_extension("caf\u{e9}", LangDesc::new("--"));
+    assert_eq!(registry.get_for_path("a.cafe\u{301}".as_ref()).unwrap().comment, "--# ");
+    assert_eq!(registry.get_for_path("a.CAF\u{c9}".as_ref()).unwrap().comment, "--# ");
+}
+
+#[test]
+fn shebang_detection() {
+    assert_eq!(shebang_interpreter("#!/bin/bash\n"), Some("bash"));
+    assert_eq!(shebang_interpreter("#!/usr/bin/env -S FOO=1 python3.11 -u"), Some("python3.11"));
+    assert_eq!(shebang_interpreter("# not a shebang"), None);
+
+    let registry = LangRegistry::with_defaults();
+    let comment = |path: &str, line: &str| {
+        registry
+            .resolve(path.as_ref(), line)
+            .unwrap()
+            .map(|d| d.comment.clone())
+    };
+    assert_eq!(comment("bin/deploy", "#!/usr/bin/env bash\n").as_deref(), Some("#- "));
+    assert_eq!(comment("bin/run", "#!/usr/bin/python3.11\n").as_deref(), Some("#- "));
+    assert_eq!(comment("bin/tool", "#!/usr/bin/env node\n").as_deref(), Some("//# "));
+    assert_eq!(comment("bin/other", "#!/usr/bin/env ruby\n"), None);
+    // Extension takes precedence
+    assert_eq!(comment("a.rs", "#!/usr/bin/env bash\n").as_deref(), Some("//# "));
+
+    let registry = registry.with_shebang_detection(false);
+    assert!(registry
+        .resolve("bin/deploy".as_ref(), "#!/usr/bin/env bash\n")
+        .unwrap()
+        .is_none());
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use cfgcomment_core::{regions, CfgCommentError, LangDesc, Region};
+use common::apply;
+
+const INPUT: &str = "\
+fn main() {
+    //[cfg_line(feature = \"a\")]
+
+    a();
+    b();
+}
+";
+
+#[test]
+fn line_tag_applies_to_next_line() {
+    assert_eq!(apply(INPUT, "rs", &["a"]).unwrap(), INPUT);
+    assert_eq!(
+        apply(INPUT, "rs", &[]).unwrap(),
+        "fn main() {\n    //[cfg_line(feature = \"a\")]\n\n    //# a();\n    b();\n}\n"
+    );
+}
+
+#[test]
+fn line_tag_region() {
+    assert_eq!(
+        regions(INPUT, &LangDesc::default_list()["rs"], false).unwrap(),
+        vec![Region {
+            start: 2,
+            end: 4,
+            predicate_src: "feature = \"a\"".to_owned(),
+            nesting_depth: 0,
+            features: vec!["a".to_owned()],
+            required_features: vec!["a".to_owned()],
+            commented: false,
+            lines: 1,
+            commented_lines: 0,
+        }]
+    );
+}
+
+#[test]
+fn line_tag_followed_by_tag() {
+    let input = "//[cfg_line(feature = \"a\")]\n//[cfg(end)]\n";
+    assert!(matches!(
+        apply(input, "rs", &[]),
+        Err(CfgCommentError::DanglingLineTag { line: 1 })
+    ));
+}
@@ -0,0 +1,60 @@
+use cfgcomment_core::{CfgCommentError, Manifest};
+
+const MANIFEST: &str = r#"
+[package]
+name = "example"
+version = "0.1.0"
+
+[dependencies]
+serde = { version = "1", optional = true }
+tokio = { version = "1", optional = true }
+log = "0.4"
+
+[target.'cfg(unix)'.dependencies]
+nix = { version = "0.29", optional = true }
+
+[features]
+default = ["std"]
+std = ["alloc", "serde?/std"]
+alloc = []
+async = ["dep:tokio", "tokio/rt"]
+full = ["async", "serde/derive", "nix"]
+"#;
+
+fn manifest() -> (tempfile::TempDir, Manifest) {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("Cargo.toml"), MANIFEST).unwrap();
+    std::fs::create_dir(dir.path().join("src")).unwrap();
+    let manifest = Manifest::discover(&dir.path().join("src")).unwrap().unwrap();
+    (dir, manifest)
+}
+
+fn sorted(features: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut features: Vec<_> = features.into_iter().collect();
+    features.sort();
+    features
+}
+
+#[test]
+fn default_features() {
+    let (_dir, manifest) = manifest();
+    assert_eq!(sorted(manifest.resolve(&[], true).unwrap()), ["alloc", "default", "std"]);
+    assert!(manifest.resolve(&[], false).unwrap().is_empty());
+}
+
+#[test]
+fn feature_dependencies() {
+    let (_dir, manifest) = manifest();
+    let full = manifest.resolve(&["full".to_owned()], false).unwrap();
+    // Optional dependencies are features, unless referred to with `dep:`
+    assert_eq!(sorted(full), ["async", "full", "nix", "serde"]);
+}
+
+#[test]
+fn unknown_feature() {
+    let (_dir, manifest) = manifest();
+    assert!(matches!(
+        manifest.resolve(&["tokio".to_owned()], true),
+        Err(CfgCommentError::UnknownManifestFeature { feature, .. }) if feature == "tokio"
+    ));
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use cfgcomment_core::LangDesc;
+use common::apply;
+
+const INPUT: &str = "\
+<body>
+    <!--[cfg(feature = \"banner\")]-->
+    <div class=\"banner\">
+        Beta
+    </div>
+    <!--[cfg(end)]-->
+</body>
+";
+
+const DISABLED: &str = "\
+<body>
+    <!--[cfg(feature = \"banner\")]-->
+    <!--# <div class=\"banner\"> #-->
+    <!--#     Beta #-->
+    <!--# </div> #-->
+    <!--[cfg(end)]-->
+</body>
+";
+
+#[test]
+fn every_line_is_wrapped() {
+    assert_eq!(apply(INPUT, "html", &[]).unwrap(), DISABLED);
+    assert_eq!(apply(DISABLED, "html", &[]).unwrap(), DISABLED);
+    assert_eq!(apply(DISABLED, "html", &["banner"]).unwrap(), INPUT);
+}
+
+#[test]
+fn markup_extensions() {
+    let list = LangDesc::default_list();
+    for ext in ["html", "xml", "svg", "vue"] {
+        assert!(list[ext].wrap_lines);
+    }
+}
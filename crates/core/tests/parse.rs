@@ -0,0 +1,81 @@
+use cfgcomment_core::{parse_cfg, CfgCommentError, CfgExpr, Data, Predicate, TargetKey, Version};
+
+#[test]
+fn ast_is_inspectable() {
+    let expr =
+        parse_cfg("all(feature = \"a\", not(target_os = \"linux\"), version(\"1.70\"))").unwrap();
+    assert_eq!(
+        expr,
+        CfgExpr::All(vec![
+            CfgExpr::Predicate(Predicate::Feature("a".to_owned())),
+            CfgExpr::Not(Box::new(CfgExpr::Predicate(Predicate::Target {
+                key: TargetKey::Os,
+                value: "linux".to_owned(),
+            }))),
+            CfgExpr::Predicate(Predicate::Version("1.70".parse::<Version>().unwrap())),
+        ])
+    );
+}
+
+#[test]
+fn display_is_canonical() {
+    for (src, canonical) in [
+        (" feature=\"a\" ", "feature = \"a\""),
+        (
+            "any( test,env(\"CI\")=\"1\", )",
+            "any(test, env(\"CI\") = \"1\")",
+        ),
+        ("feature~\"backend-*\"", "feature ~ \"backend-*\""),
+    ] {
+        let expr: CfgExpr = src.parse().unwrap();
+        assert_eq!(expr.to_string(), canonical);
+        assert_eq!(parse_cfg(canonical).unwrap(), expr);
+    }
+}
+
+#[test]
+fn invalid_predicate() {
+    match parse_cfg("all(feature = \"a\"") {
+        Err(CfgCommentError::InvalidPredicate { column, text, .. }) => {
+            assert_eq!(column, 18);
+            assert_eq!(text, "all(feature = \"a\"");
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_cfg("feature ~ \"[\"").is_err());
+    assert!(parse_cfg("").is_err());
+}
+
+#[test]
+fn reserved_names_are_rejected() {
+    for src in ["env = \"X\"", "version = \"1\"", "any(a, env)", "not(feature)"] {
+        match parse_cfg(src) {
+            Err(CfgCommentError::InvalidPredicate { expected, .. }) => {
+                assert!(expected.contains("name other than feature"), "{}", expected);
+            }
+            other => panic!("unexpected {:?} for {}", other, src),
+        }
+    }
+    // Same names are rejected by builder
+    assert!(CfgExpr::key_value("env", "X").is_err());
+    assert!(CfgExpr::flag("version").is_err());
+    assert!(parse_cfg("environment = \"X\"").is_ok());
+}
+
+#[test]
+fn env_presence_and_value() {
+    let env = |vars: &[(&str, &str)]| Data {
+        env: vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        ..Default::default()
+    };
+    let ci = parse_cfg("env(\"CI\")").unwrap();
+    let release = parse_cfg("env(\"PROFILE\") = \"release\"").unwrap();
+    assert!(!ci.evaluate(&env(&[])));
+    assert!(!release.evaluate(&env(&[])));
+    assert!(ci.evaluate(&env(&[("CI", "")])));
+    assert!(!release.evaluate(&env(&[("PROFILE", "debug")])));
+    assert!(release.evaluate(&env(&[("PROFILE", "release")])));
+}
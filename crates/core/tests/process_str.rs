@@ -0,0 +1,87 @@
+#![cfg(feature = "walk")]
+
+mod common;
+
+use std::sync::Arc;
+
+use cfgcomment_core::{process, process_str, Data, LangDesc, WalkOptions};
+use common::walk;
+
+fn reset(input: &str) -> String {
+    let data = Data {
+        reset: true,
+        ..Default::default()
+    };
+    process_str(input, &data, &LangDesc::new("//")).unwrap()
+}
+
+#[test]
+fn empty_input() {
+    assert_eq!(reset(""), "");
+    assert_eq!(reset("\n"), "\n");
+}
+
+#[test]
+fn missing_final_newline_is_kept() {
+    let input = "//[cfg(feature = \"a\")]\n//# a\n//[cfg(end)]";
+    assert_eq!(reset(input), "//[cfg(feature = \"a\")]\na\n//[cfg(end)]");
+}
+
+#[test]
+fn crlf_is_kept() {
+    let input = "//[cfg(feature = \"a\")]\r\n//# a\r\n//[cfg(end)]\r\n";
+    assert_eq!(
+        reset(input),
+        "//[cfg(feature = \"a\")]\r\na\r\n//[cfg(end)]\r\n"
+    );
+    let disabled = process_str(&reset(input), &Data::default(), &LangDesc::new("//")).unwrap();
+    assert_eq!(disabled, input);
+}
+
+#[test]
+fn mixed_line_endings_are_kept() {
+    let input = "//[cfg(feature = \"a\")]\n//# a\r\n//# b\n//[cfg(end)]\r\nc";
+    assert_eq!(
+        reset(input),
+        "//[cfg(feature = \"a\")]\na\r\nb\n//[cfg(end)]\r\nc"
+    );
+}
+
+#[test]
+fn block_comments_keep_crlf() {
+    let desc = &LangDesc::default_list()["html"];
+    let input = "<!--[cfg(feature = \"a\")]-->\r\n<b>\r\n<!--[cfg(end)]-->\r\n";
+    let disabled = process_str(input, &Data::default(), desc).unwrap();
+    assert_eq!(
+        disabled,
+        "<!--[cfg(feature = \"a\")]-->\r\n<!--# <b> #-->\r\n<!--[cfg(end)]-->\r\n"
+    );
+}
+
+#[test]
+fn written_files_keep_line_endings() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("lib.rs");
+    std::fs::write(&file, "//[cfg(feature = \"a\")]\r\na();\r\n//[cfg(end)]").unwrap();
+    let summary = walk(dir.path(), WalkOptions::default());
+    assert_eq!(summary.changed, vec![file.clone()]);
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "//[cfg(feature = \"a\")]\r\n//# a();\r\n//[cfg(end)]"
+    );
+}
+
+#[test]
+fn pipeline_runs_on_another_thread() {
+    let lines = vec!["//[cfg(feature = \"a\")]", "a();", "//[cfg(end)]"];
+    let iter = process(
+        lines.into_iter().map(str::to_owned),
+        Arc::new(Data::default()),
+        Arc::new(LangDesc::new("//")),
+    );
+    let out = std::thread::spawn(move || iter.collect::<Result<Vec<_>, _>>())
+        .join()
+        .unwrap()
+        .unwrap();
+    assert_eq!(out, vec!["//[cfg(feature = \"a\")]", "//# a();", "//[cfg(end)]"]);
+}
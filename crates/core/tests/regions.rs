@@ -0,0 +1,143 @@
+use cfgcomment_core::{regions, CfgCommentError, LangDesc, Region};
+
+#[test]
+fn nested_regions() {
+    let input = "\
+a
+//[cfg(feature = \"a\")]
+    //[cfg(not(feature = \"b\"))]
+    b
+    //[cfg(end)]
+//[cfg(end)]
+";
+    let desc = &LangDesc::default_list()["rs"];
+    assert_eq!(
+        regions(input, desc, false).unwrap(),
+        vec![
+            Region {
+                start: 2,
+                end: 6,
+                predicate_src: "feature = \"a\"".to_owned(),
+                nesting_depth: 0,
+                features: vec!["a".to_owned()],
+                required_features: vec!["a".to_owned()],
+                commented: false,
+                lines: 1,
+                commented_lines: 0,
+            },
+            Region {
+                start: 3,
+                end: 5,
+                predicate_src: "not(feature = \"b\")".to_owned(),
+                nesting_depth: 1,
+                features: vec!["b".to_owned()],
+                required_features: vec![],
+                commented: false,
+                lines: 1,
+                commented_lines: 0,
+            },
+        ]
+    );
+}
+
+#[test]
+fn unbalanced_regions() {
+    let desc = &LangDesc::default_list()["rs"];
+    assert!(matches!(
+        regions("//[cfg(feature = \"a\")]\n", desc, false),
+        Err(CfgCommentError::Unclosed { line: 1, .. })
+    ));
+    assert!(matches!(
+        regions("a\n//[cfg(end)]\n", desc, false),
+        Err(CfgCommentError::UnexpectedEnd { line: 2 })
+    ));
+}
+
+#[test]
+fn region_features() {
+    let input = "\
+//[cfg(all(feature = \"a\", any(feature = \"b\", not(feature = \"a\")), unix))]
+//[cfg(end)]
+//[cfg(feature ~ \"backend-*\")]
+//[cfg(end)]
+//[cfg(env(\"CI\"))]
+//[cfg(end)]
+";
+    let features: Vec<Vec<String>> = regions(input, &LangDesc::default_list()["rs"], false)
+        .unwrap()
+        .into_iter()
+        .map(|r| r.features)
+        .collect();
+    assert_eq!(
+        features,
+        vec![
+            vec!["a".to_owned(), "b".to_owned()],
+            vec!["backend-*".to_owned()],
+            vec![],
+        ]
+    );
+}
+
+#[test]
+fn region_state() {
+    let input = "\
+//[cfg(feature = \"a\")]
+    //[cfg(feature = \"b\")]
+    //# b();
+    //[cfg(end)]
+    a();
+//[cfg(else)]
+//# c();
+//[cfg(end)]
+//[cfg(feature = \"d\")]
+    //[cfg(feature = \"e\")]
+    //# e();
+    //[cfg(end)]
+//[cfg(end)]
+//[cfg(feature = \"f\")]
+
+//[cfg(end)]
+";
+    let commented: Vec<(usize, bool)> = regions(input, &LangDesc::default_list()["rs"], false)
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.start, r.commented))
+        .collect();
+    assert_eq!(
+        commented,
+        vec![(1, false), (2, true), (9, true), (10, true), (14, false)]
+    );
+}
+
+#[test]
+fn region_line_counts() {
+    let rs = "\
+//[cfg(feature = \"a\")]
+a();
+
+//[cfg(feature = \"b\")]
+//# b();
+//# b();
+//[cfg(end)]
+//[cfg(end)]
+";
+    let css = "\
+/*[cfg(feature = \"a\")]*/
+/*# a {
+  color: red;
+} #*/
+/*[cfg(else)]*/
+b {}
+/*[cfg(end)]*/
+";
+    let list = LangDesc::default_list();
+    let counts = |input, ext: &str| -> Vec<(usize, usize)> {
+        regions(input, &list[ext], false)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.lines, r.commented_lines))
+            .collect()
+    };
+    assert_eq!(counts(rs, "rs"), vec![(3, 2), (2, 2)]);
+    assert_eq!(counts(css, "css"), vec![(4, 3)]);
+}
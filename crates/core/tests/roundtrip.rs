@@ -0,0 +1,134 @@
+mod common;
+
+use cfgcomment_core::{process_str, Data, LangDesc};
+use common::apply;
+use proptest::prelude::*;
+
+const FEATURES: &[&str] = &["a", "b", "c", "d"];
+
+#[derive(Debug, Clone)]
+enum Node {
+    Line(String),
+    /// Line guarded by `[cfg_line(...)]`
+    Single(String, String),
+    /// Segment of line guarded by inline tags
+    Inline(String, String),
+    Block(String, Vec<Node>, Vec<(String, Vec<Node>)>, Option<Vec<Node>>),
+}
+
+fn predicate() -> impl Strategy<Value = String> {
+    let leaf = prop::sample::select(FEATURES).prop_map(|f| format!("feature = \"{}\"", f));
+    leaf.prop_recursive(3, 8, 3, |inner| {
+        prop_oneof![
+            inner.clone().prop_map(|p| format!("not({})", p)),
+            prop::collection::vec(inner.clone(), 1..3).prop_map(|l| format!("any({})", l.join(", "))),
+            prop::collection::vec(inner, 1..3).prop_map(|l| format!("all({})", l.join(", "))),
+        ]
+    })
+}
+
+fn node() -> impl Strategy<Value = Node> {
+    let line = prop_oneof![
+        "[a-z][a-z0-9 ();]{0,12}".prop_map(Node::Line),
+        Just(Node::Line(String::new())),
+    ];
+    let line = prop_oneof![
+        line.clone(),
+        (predicate(), "[a-z][a-z0-9 ();]{0,12}").prop_map(|(pred, l)| Node::Single(pred, l)),
+        (predicate(), "[a-z][a-z0-9 ();]{0,12}").prop_map(|(pred, l)| Node::Inline(pred, l)),
+    ];
+    line.prop_recursive(4, 32, 6, |inner| {
+        (
+            predicate(),
+            prop::collection::vec(inner.clone(), 0..6),
+            prop::collection::vec((predicate(), prop::collection::vec(inner.clone(), 0..3)), 0..3),
+            prop::option::of(prop::collection::vec(inner, 0..4)),
+        )
+            .prop_map(|(pred, body, else_ifs, else_body)| {
+                Node::Block(pred, body, else_ifs, else_body)
+            })
+    })
+}
+
+fn render(nodes: &[Node], indent: usize, out: &mut String) {
+    let ws = " ".repeat(indent);
+    for node in nodes {
+        match node {
+            Node::Line(l) if l.is_empty() => out.push('\n'),
+            Node::Line(l) => out.push_str(&format!("{}{}\n", ws, l)),
+            Node::Inline(pred, l) => out.push_str(&format!(
+                "{}f(/*[cfg({})]*/ {} /*[cfg(end)]*/);\n",
+                ws, pred, l
+            )),
+            Node::Single(pred, l) => {
+                out.push_str(&format!("{}//[cfg_line({})]\n{}{}\n", ws, pred, ws, l))
+            }
+            Node::Block(pred, body, else_ifs, else_body) => {
+                out.push_str(&format!("{}//[cfg({})]\n", ws, pred));
+                render(body, indent + 4, out);
+                for (pred, body) in else_ifs {
+                    out.push_str(&format!("{}//[cfg(else if {})]\n", ws, pred));
+                    render(body, indent + 4, out);
+                }
+                if let Some(else_body) = else_body {
+                    out.push_str(&format!("{}//[cfg(else)]\n", ws));
+                    render(else_body, indent + 4, out);
+                }
+                out.push_str(&format!("{}//[cfg(end)]\n", ws));
+            }
+        }
+    }
+}
+
+fn file() -> impl Strategy<Value = String> {
+    prop::collection::vec(node(), 0..8).prop_map(|nodes| {
+        let mut out = String::new();
+        render(&nodes, 0, &mut out);
+        out
+    })
+}
+
+fn feature_set() -> impl Strategy<Value = Vec<&'static str>> {
+    prop::sample::subsequence(FEATURES, 0..=FEATURES.len())
+}
+
+fn reset(input: &str) -> String {
+    let data = Data {
+        reset: true,
+        ..Default::default()
+    };
+    process_str(input, &data, &LangDesc::default_list()["rs"]).unwrap()
+}
+
+proptest! {
+    #[test]
+    fn apply_is_idempotent(input in file(), features in feature_set()) {
+        let once = apply(&input, "rs", &features).unwrap();
+        prop_assert_eq!(apply(&once, "rs", &features).unwrap(), once);
+    }
+
+    #[test]
+    fn reset_restores_original(input in file(), features in feature_set()) {
+        prop_assert_eq!(reset(&input), input.clone());
+        prop_assert_eq!(reset(&apply(&input, "rs", &features).unwrap()), input);
+    }
+
+    #[test]
+    fn apply_after_reset_roundtrips(input in file(), features in feature_set()) {
+        let applied = apply(&input, "rs", &features).unwrap();
+        prop_assert_eq!(apply(&reset(&applied), "rs", &features).unwrap(), applied);
+    }
+
+    #[test]
+    fn apply_overrides_previous_apply(
+        input in file(),
+        first in feature_set(),
+        second in feature_set(),
+    ) {
+        let applied = apply(&input, "rs", &first).unwrap();
+        prop_assert_eq!(
+            apply(&applied, "rs", &second).unwrap(),
+            apply(&input, "rs", &second).unwrap()
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use cfgcomment_core::{Config, Data, LangDesc, Version};
+
+#[test]
+fn data_roundtrip() {
+    let mut data = Data {
+        features: ["a".to_owned(), "b".to_owned()].iter().cloned().collect(),
+        strict: true,
+        version: Some("1.70".parse().unwrap()),
+        ..Default::default()
+    };
+    data.cfg.insert(("test".to_owned(), None));
+    data.cfg
+        .insert(("panic".to_owned(), Some("abort".to_owned())));
+    data.target.os = Some("linux".to_owned());
+
+    let text = toml::to_string(&data).unwrap();
+    assert!(
+        text.contains("cfg = ['panic=\"abort\"', \"test\"]"),
+        "{}",
+        text
+    );
+    assert!(text.contains("version = \"1.70\""), "{}", text);
+
+    let parsed: Data = toml::from_str(&text).unwrap();
+    assert_eq!(parsed.features, data.features);
+    assert_eq!(parsed.cfg, data.cfg);
+    assert_eq!(parsed.version, "1.70".parse::<Version>().ok());
+    assert_eq!(parsed.target.os.as_deref(), Some("linux"));
+    assert!(parsed.strict && !parsed.reset);
+}
+
+#[test]
+fn cfg_values_are_escaped() {
+    let mut data = Data::default();
+    data.cfg.insert(("a".to_owned(), Some(r#"x="y" \z"#.to_owned())));
+    let text = toml::to_string(&data).unwrap();
+    assert!(text.contains(r#"'a="x=\"y\" \\z"'"#), "{}", text);
+    let parsed: Data = toml::from_str(&text).unwrap();
+    assert_eq!(parsed.cfg, data.cfg);
+}
+
+#[test]
+fn env_is_not_serialized() {
+    let mut data = Data::default();
+    data.env.insert("TOKEN".to_owned(), "secret".to_owned());
+    let text = toml::to_string(&data).unwrap();
+    assert!(!text.contains("secret"), "{}", text);
+    let parsed: Data = toml::from_str("env = { A = \"1\" }").unwrap();
+    assert_eq!(parsed.env["A"], "1");
+}
+
+#[test]
+fn data_fields_default() {
+    let data: Data = toml::from_str("features = [\"a\"]").unwrap();
+    assert!(data.features.contains("a"));
+    assert!(data.cfg.is_empty() && data.version.is_none());
+    assert!(toml::from_str::<Data>("feature = [\"a\"]").is_err());
+}
+
+#[test]
+fn config_roundtrip() {
+    let config: Config = toml::from_str(
+        r##"
+committed_features = ["a"]
+exclusive_features = [["gl", "vk"]]
+
+[languages.lua]
+cfg_prefix = "--["
+cfg_prefix_comment_len = 2
+cfg_suffix = "]"
+comment = "--# "
+
+[preset.embedded]
+features = ["no-std"]
+"##,
+    )
+    .unwrap();
+    let text = toml::to_string(&config).unwrap();
+    let parsed: Config = toml::from_str(&text).unwrap();
+    assert_eq!(parsed.exclusive_features, [["gl", "vk"]]);
+    assert_eq!(parsed.preset["embedded"].features, ["no-std"]);
+    assert_eq!(parsed.languages["lua"].desc.comment, "--# ");
+    assert_eq!(parsed.languages["lua"].desc.cfg_keyword, "cfg");
+}
+
+#[test]
+fn lang_desc_roundtrip() {
+    let desc = LangDesc::new("//");
+    let parsed: LangDesc = toml::from_str(&toml::to_string(&desc).unwrap()).unwrap();
+    let input = "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n";
+    let data = Data::default();
+    assert_eq!(
+        cfgcomment_core::process_str(input, &data, &parsed).unwrap(),
+        cfgcomment_core::process_str(input, &data, &desc).unwrap(),
+    );
+}
@@ -0,0 +1,76 @@
+use cfgcomment_core::{spans, BranchKind, Data, LangDesc, Span};
+
+const INPUT: &str = "\
+fn main() {}
+//[cfg(feature = \"a\")]
+a();
+  //[cfg(feature = \"c\")]
+  c();
+  //[cfg(end)]
+//[cfg(else)]
+//# b();
+//[cfg(end)]
+//[cfg_line(feature = \"a\")]
+//# d();
+";
+
+#[test]
+fn branches_tile_blocks() {
+    let data = Data {
+        features: std::iter::once("a".to_owned()).collect(),
+        ..Default::default()
+    };
+    let spans = spans(INPUT, &data, &LangDesc::new("//")).unwrap();
+    assert_eq!(
+        spans,
+        vec![
+            Span {
+                kind: BranchKind::Start,
+                nesting_depth: 0,
+                predicate: Some("feature = \"a\"".to_owned()),
+                start_line: 2,
+                end_line: 6,
+                bytes: 13..87,
+                enabled: true,
+                commented: false,
+            },
+            Span {
+                kind: BranchKind::Start,
+                nesting_depth: 1,
+                predicate: Some("feature = \"c\"".to_owned()),
+                start_line: 4,
+                end_line: 6,
+                bytes: 41..87,
+                enabled: false,
+                commented: false,
+            },
+            Span {
+                kind: BranchKind::Else,
+                nesting_depth: 0,
+                predicate: None,
+                start_line: 7,
+                end_line: 9,
+                bytes: 88..123,
+                enabled: false,
+                commented: true,
+            },
+            Span {
+                kind: BranchKind::Line,
+                nesting_depth: 0,
+                predicate: Some("feature = \"a\"".to_owned()),
+                start_line: 10,
+                end_line: 11,
+                bytes: 124..160,
+                enabled: true,
+                commented: true,
+            },
+        ]
+    );
+    assert_eq!(&INPUT[spans[2].bytes.clone()], "//[cfg(else)]\n//# b();\n//[cfg(end)]");
+}
+
+#[test]
+fn unbalanced_input_fails() {
+    let input = "//[cfg(feature = \"a\")]\na();\n";
+    assert!(spans(input, &Data::default(), &LangDesc::new("//")).is_err());
+}
@@ -0,0 +1,49 @@
+use cfgcomment_core::{process_str, regions, CfgCommentError, Data, LangDesc};
+
+const TRAILING_COMMA: &str = "\
+//[cfg(all(feature = \"a\", feature = \"b\",))]
+b
+//[cfg(end)]
+";
+
+fn apply(input: &str, strict: bool) -> Result<String, CfgCommentError> {
+    let data = Data {
+        strict,
+        ..Default::default()
+    };
+    process_str(input, &data, &LangDesc::default_list()["rs"])
+}
+
+#[test]
+fn trailing_comma_lenient() {
+    assert_eq!(
+        apply(TRAILING_COMMA, false).unwrap(),
+        "//[cfg(all(feature = \"a\", feature = \"b\",))]\n//# b\n//[cfg(end)]\n"
+    );
+    let desc = &LangDesc::default_list()["rs"];
+    assert_eq!(regions(TRAILING_COMMA, desc, false).unwrap().len(), 1);
+}
+
+#[test]
+fn trailing_comma_strict() {
+    match apply(TRAILING_COMMA, true) {
+        Err(CfgCommentError::InvalidTag {
+            line: 1,
+            column: 41,
+            expected,
+            ..
+        }) => assert!(expected.contains("no trailing comma"), "{}", expected),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    let desc = &LangDesc::default_list()["rs"];
+    assert!(matches!(
+        regions(TRAILING_COMMA, desc, true),
+        Err(CfgCommentError::InvalidTag { line: 1, .. })
+    ));
+}
+
+#[test]
+fn strict_accepts_well_formed_tags() {
+    let input = "//[cfg(any(feature = \"a\", feature = \"b\"))]\nb\n//[cfg(end)]\n";
+    assert_eq!(apply(input, true).unwrap(), apply(input, false).unwrap());
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use cfgcomment_core::{process_str, CfgCommentError, LangDesc};
+use common::data;
+
+fn pragma() -> LangDesc {
+    LangDesc::new("//")
+        .with_cfg_prefix("#pragma ", "#pragma ".len())
+        .with_tag_delimiters("", "")
+}
+
+const PRAGMA: &str = "\
+#pragma once
+#pragma cfg(feature = \"simd\")
+#include <immintrin.h>
+#pragma cfg(else)
+#include \"fallback.h\"
+#pragma cfg(end)
+";
+
+#[test]
+fn pragma_tags() {
+    let disabled = process_str(PRAGMA, &data(&[]), &pragma()).unwrap();
+    assert_eq!(
+        disabled,
+        "\
+#pragma once
+#pragma cfg(feature = \"simd\")
+//# #include <immintrin.h>
+#pragma cfg(else)
+#include \"fallback.h\"
+#pragma cfg(end)
+"
+    );
+    let enabled = process_str(&disabled, &data(&["simd"]), &pragma()).unwrap();
+    assert_eq!(enabled.lines().nth(2), Some("#include <immintrin.h>"));
+}
+
+#[test]
+fn malformed_pragma_is_reported() {
+    let err = process_str("#pragma cfg(feature = )\n", &data(&[]), &pragma()).unwrap_err();
+    assert!(matches!(err, CfgCommentError::InvalidTag { line: 1, .. }), "{:?}", err);
+}
+
+#[test]
+fn docstring_tags() {
+    let desc = LangDesc::new("#").with_tag_delimiters("@", "");
+    assert_eq!((desc.cfg_prefix.as_str(), desc.cfg_suffix.as_str()), ("#@", ""));
+    let input = "#@cfg(feature = \"a\")\nx = 1\n#@cfg(end)\n";
+    assert_eq!(
+        process_str(input, &data(&[]), &desc).unwrap(),
+        "#@cfg(feature = \"a\")\n## x = 1\n#@cfg(end)\n"
+    );
+    // Default brackets are no longer tags
+    let bracketed = "#[cfg(feature = \"a\")]\nx = 1\n";
+    assert_eq!(process_str(bracketed, &data(&[]), &desc).unwrap(), bracketed);
+}
+
+#[test]
+fn block_comment_delimiters_are_kept() {
+    let desc = LangDesc::block("/*", "*/").with_tag_delimiters("{", "}");
+    assert_eq!((desc.cfg_prefix.as_str(), desc.cfg_suffix.as_str()), ("/*{", "}*/"));
+    let input = "/*{cfg(feature = \"a\")}*/\na {}\n/*{cfg(end)}*/\n";
+    assert_eq!(
+        process_str(input, &data(&[]), &desc).unwrap(),
+        "/*{cfg(feature = \"a\")}*/\n/*# a {} #*/\n/*{cfg(end)}*/\n"
+    );
+}
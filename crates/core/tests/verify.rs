@@ -0,0 +1,29 @@
+use cfgcomment_core::{verify, CfgCommentError, LangDesc};
+
+#[test]
+fn reports_every_problem() {
+    let input = "\
+//[cfg(feature = \"a\")]
+//[cfg(feature = )]
+//[cfg(feature = \"b\")]
+  x();
+ //[cfg(end)]
+//[cfg(end)]
+//[cfg(end)]
+//[cfg(feature = \"c\")]
+  //[cfg(feature = \"d\")]
+";
+    let problems = verify(input, &LangDesc::default_list()["rs"]);
+    let lines: Vec<_> = problems.iter().map(|e| e.line().unwrap()).collect();
+    assert_eq!(lines, vec![2, 5, 7, 8, 9]);
+    assert!(matches!(problems[0], CfgCommentError::InvalidTag { .. }));
+    assert!(matches!(problems[1], CfgCommentError::MismatchedIndent { .. }));
+    assert!(matches!(problems[2], CfgCommentError::UnexpectedEnd { .. }));
+    assert!(matches!(problems[3], CfgCommentError::Unclosed { .. }));
+}
+
+#[test]
+fn clean_file() {
+    let input = "//[cfg(feature = \"a\")]\n//# a();\n//[cfg(else)]\nb();\n//[cfg(end)]\n";
+    assert!(verify(input, &LangDesc::default_list()["rs"]).is_empty());
+}
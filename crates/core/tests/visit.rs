@@ -0,0 +1,105 @@
+use cfgcomment_core::{process_str, process_str_with, Branch, Data, LangDesc, LineState, Visitor};
+
+#[derive(Default)]
+struct Recorder {
+    events: Vec<String>,
+}
+
+impl Visitor for Recorder {
+    fn enter_branch(&mut self, branch: &Branch) {
+        self.events.push(format!("enter {}", branch.line));
+    }
+    fn exit_branch(&mut self, branch: &Branch) {
+        self.events.push(format!("exit {}", branch.line));
+    }
+    fn visit_line(&mut self, line: &LineState<'_>, _output: &mut String) {
+        let state = match (line.tag, line.enabled, line.changed) {
+            (true, _, _) => "tag",
+            (false, true, false) => "enabled",
+            (false, true, true) => "uncommented",
+            (false, false, false) => "disabled",
+            (false, false, true) => "commented",
+        };
+        self.events.push(format!("{} {}", line.line, state));
+    }
+}
+
+const INPUT: &str = "\
+//[cfg(feature = \"a\")]
+a();
+  //[cfg(feature = \"b\")]
+  b();
+  //[cfg(end)]
+//[cfg(else)]
+//# c();
+//[cfg(end)]
+//[cfg_line(feature = \"a\")]
+//# d();
+e();
+";
+
+#[test]
+fn events_in_order() {
+    let data = Data {
+        features: std::iter::once("a".to_owned()).collect(),
+        ..Default::default()
+    };
+    let desc = LangDesc::new("//");
+    let mut recorder = Recorder::default();
+    let output = process_str_with(INPUT, &data, &desc, &mut recorder).unwrap();
+    assert_eq!(output, process_str(INPUT, &data, &desc).unwrap());
+    assert_eq!(
+        recorder.events,
+        [
+            "enter 1",
+            "1 tag",
+            "2 enabled",
+            "enter 3",
+            "3 tag",
+            "4 commented",
+            "5 tag",
+            "exit 3",
+            "exit 1",
+            "enter 6",
+            "6 tag",
+            "7 disabled",
+            "8 tag",
+            "exit 6",
+            "enter 9",
+            "9 tag",
+            "10 uncommented",
+            "exit 9",
+            "11 enabled",
+        ]
+    );
+}
+
+struct Marker;
+
+impl Visitor for Marker {
+    fn visit_line(&mut self, line: &LineState<'_>, output: &mut String) {
+        if line.changed {
+            output.push_str(" // changed");
+        }
+    }
+}
+
+#[test]
+fn rewrites_output() {
+    let input = "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n";
+    let output = process_str_with(input, &Data::default(), &LangDesc::new("//"), &mut Marker);
+    assert_eq!(
+        output.unwrap(),
+        "//[cfg(feature = \"a\")]\n//# a(); // changed\n//[cfg(end)]\n"
+    );
+}
+
+#[test]
+fn visited_until_error() {
+    let input = "//[cfg(feature = \"a\")]\na();\n//[cfg(feature = )]\nb();\n";
+    let mut recorder = Recorder::default();
+    assert!(
+        process_str_with(input, &Data::default(), &LangDesc::new("//"), &mut recorder).is_err()
+    );
+    assert_eq!(recorder.events, ["enter 1", "1 tag", "2 commented"]);
+}
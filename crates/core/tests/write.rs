@@ -0,0 +1,486 @@
+#![cfg(feature = "walk")]
+
+mod common;
+
+use std::{
+    fs::{File, FileTimes},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use cfgcomment_core::{
+    process_files, process_str, walkdir_parallel, Data, LangDesc, LangRegistry, PathFilter,
+    Progress, WalkOptions,
+};
+use common::{data, walk};
+
+fn set_mtime(path: &Path, time: SystemTime) {
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_times(FileTimes::new().set_modified(time))
+        .unwrap();
+}
+
+fn mtime(path: &Path) -> SystemTime {
+    std::fs::metadata(path).unwrap().modified().unwrap()
+}
+
+#[test]
+fn unchanged_files_are_not_rewritten() {
+    let dir = tempfile::tempdir().unwrap();
+    let unchanged = dir.path().join("unchanged.rs");
+    let changed = dir.path().join("changed.rs");
+    std::fs::write(
+        &unchanged,
+        "//[cfg(feature = \"a\")]\n//# a();\n//[cfg(end)]\n",
+    )
+    .unwrap();
+    std::fs::write(&changed, "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n").unwrap();
+    let past = SystemTime::now() - Duration::from_secs(3600);
+    set_mtime(&unchanged, past);
+    set_mtime(&changed, past);
+
+    let summary = walk(dir.path(), WalkOptions::default());
+    assert_eq!(summary.changed, vec![changed.clone()]);
+    assert_eq!(mtime(&unchanged), past);
+    assert_ne!(mtime(&changed), past);
+}
+
+#[cfg(unix)]
+#[test]
+fn permissions_are_kept() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("run.sh");
+    std::fs::write(&script, "#[cfg(feature = \"a\")]\necho a\n#[cfg(end)]\n").unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o750)).unwrap();
+
+    let summary = walk(dir.path(), WalkOptions::default());
+    assert_eq!(summary.changed, vec![script.clone()]);
+    let mode = std::fs::metadata(&script).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o750);
+}
+
+#[test]
+fn backups_of_changed_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let changed = dir.path().join("changed.rs");
+    let unchanged = dir.path().join("unchanged.rs");
+    let original = "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n";
+    std::fs::write(&changed, original).unwrap();
+    std::fs::write(&unchanged, "a();\n").unwrap();
+    let options = || WalkOptions {
+        backup_suffix: Some(".orig".to_owned()),
+        ..Default::default()
+    };
+
+    let summary = walk(dir.path(), options());
+    assert!(summary.errors.is_empty());
+    let backup = dir.path().join("changed.rs.orig");
+    assert_eq!(std::fs::read_to_string(&backup).unwrap(), original);
+    assert!(!dir.path().join("unchanged.rs.orig").exists());
+
+    // Existing backup is never overwritten without `force`
+    std::fs::write(&changed, original).unwrap();
+    std::fs::write(&backup, "older").unwrap();
+    let summary = walk(dir.path(), options());
+    assert_eq!(summary.errors.len(), 1);
+    assert_eq!(std::fs::read_to_string(&changed).unwrap(), original);
+    assert_eq!(std::fs::read_to_string(&backup).unwrap(), "older");
+
+    let summary = walk(
+        dir.path(),
+        WalkOptions {
+            force: true,
+            ..options()
+        },
+    );
+    assert!(summary.errors.is_empty());
+    assert_eq!(std::fs::read_to_string(&backup).unwrap(), original);
+}
+
+#[test]
+fn listed_files_bypass_walker() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n";
+    let ignored = dir.path().join("ignored.rs");
+    let unlisted = dir.path().join("unlisted.rs");
+    let missing = dir.path().join("missing.rs");
+    std::fs::write(dir.path().join(".cfgignore"), "ignored.rs\n").unwrap();
+    std::fs::write(&ignored, input).unwrap();
+    std::fs::write(&unlisted, input).unwrap();
+
+    let summary = process_files(
+        vec![ignored.clone(), missing.clone(), dir.path().to_owned()],
+        Data::default(),
+        LangRegistry::with_defaults(),
+        WalkOptions::default(),
+    );
+    assert_eq!(summary.changed, vec![ignored.clone()]);
+    assert_eq!(summary.errors.len(), 1);
+    assert_eq!(summary.errors[0].0, missing);
+    assert_eq!(std::fs::read_to_string(&unlisted).unwrap(), input);
+}
+
+#[test]
+fn excluded_paths_are_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n";
+    let kept = dir.path().join("src/lib.rs");
+    let vendored = dir.path().join("vendor/dep/lib.rs");
+    let minified = dir.path().join("src/bundle.min.rs");
+    for path in [&kept, &vendored, &minified] {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, input).unwrap();
+    }
+    let options = || WalkOptions {
+        check: true,
+        filter: PathFilter::new(&["vendor".to_owned(), "*.min.rs".to_owned()], &[], &[]).unwrap(),
+        ..Default::default()
+    };
+
+    assert_eq!(walk(dir.path(), options()).changed, vec![kept.clone()]);
+    let summary = process_files(
+        vec![kept.clone(), vendored, minified],
+        Data::default(),
+        LangRegistry::with_defaults(),
+        options(),
+    );
+    assert_eq!(summary.changed, vec![kept]);
+    assert!(PathFilter::new(&["[".to_owned()], &[], &[]).is_err());
+}
+
+#[test]
+fn only_matching_and_not_ignored_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let rust = dir.path().join("lib.rs");
+    let python = dir.path().join("script.py");
+    let generated = dir.path().join("generated/gen.py");
+    let ignore_file = dir.path().join("extra.ignore");
+    std::fs::create_dir(dir.path().join("generated")).unwrap();
+    std::fs::write(&rust, "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n").unwrap();
+    for path in [&python, &generated] {
+        std::fs::write(path, "#[cfg(feature = \"a\")]\na()\n#[cfg(end)]\n").unwrap();
+    }
+    std::fs::write(&ignore_file, "generated/\n").unwrap();
+    let filter = PathFilter::new(&[], &["*.py".to_owned()], &[ignore_file]).unwrap();
+    let options = || WalkOptions {
+        check: true,
+        filter: filter.clone(),
+        ..Default::default()
+    };
+
+    assert_eq!(walk(dir.path(), options()).changed, vec![python.clone()]);
+    let summary = process_files(
+        vec![rust, python.clone(), generated],
+        Data::default(),
+        LangRegistry::with_defaults(),
+        options(),
+    );
+    assert_eq!(summary.changed, vec![python]);
+    assert!(PathFilter::new(&[], &[], &[dir.path().join("missing.ignore")]).is_err());
+}
+
+#[test]
+fn hidden_and_ignored_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n";
+    let plain = dir.path().join("lib.rs");
+    let hidden = dir.path().join(".github/script.rs");
+    let ignored = dir.path().join("ignored.rs");
+    std::fs::create_dir(dir.path().join(".github")).unwrap();
+    std::fs::write(dir.path().join(".cfgignore"), "ignored.rs\n").unwrap();
+    for path in [&plain, &hidden, &ignored] {
+        std::fs::write(path, input).unwrap();
+    }
+    let changed = |options: WalkOptions| {
+        walk(
+            dir.path(),
+            WalkOptions {
+                check: true,
+                ..options
+            },
+        )
+        .changed
+    };
+
+    assert_eq!(changed(WalkOptions::default()), vec![plain.clone()]);
+    let options = WalkOptions {
+        hidden: true,
+        ..Default::default()
+    };
+    assert_eq!(changed(options), vec![hidden.clone(), plain.clone()]);
+    let options = WalkOptions {
+        no_ignore: true,
+        ..Default::default()
+    };
+    assert_eq!(changed(options), vec![ignored, plain]);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let dir = tempfile::tempdir().unwrap();
+    let input = "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n";
+    let real = dir.path().join("real");
+    let tree = dir.path().join("tree");
+    std::fs::create_dir(&real).unwrap();
+    std::fs::create_dir(&tree).unwrap();
+    std::fs::write(real.join("lib.rs"), input).unwrap();
+    symlink(&real, tree.join("linked")).unwrap();
+    symlink(real.join("lib.rs"), tree.join("file.rs")).unwrap();
+    symlink(&tree, real.join("loop")).unwrap();
+
+    assert!(walk(&tree, WalkOptions::default()).changed.is_empty());
+    let summary = walk(
+        &tree,
+        WalkOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        },
+    );
+    assert!(summary.errors.is_empty());
+    assert_eq!(summary.changed.len(), 1);
+    assert!(std::fs::symlink_metadata(tree.join("file.rs"))
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_ne!(std::fs::read_to_string(real.join("lib.rs")).unwrap(), input);
+}
+
+#[test]
+fn large_files_are_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n";
+    let small = dir.path().join("small.rs");
+    let large = dir.path().join("large.rs");
+    std::fs::write(&small, input).unwrap();
+    std::fs::write(&large, input.repeat(10)).unwrap();
+    let options = || WalkOptions {
+        check: true,
+        max_filesize: Some(input.len() as u64),
+        ..Default::default()
+    };
+
+    let summary = walk(dir.path(), options());
+    assert_eq!(summary.changed, vec![small.clone()]);
+    assert_eq!(summary.scanned, 1);
+    assert_eq!(summary.files, vec![small.clone()]);
+    let summary = process_files(
+        vec![small.clone(), large],
+        Data::default(),
+        LangRegistry::with_defaults(),
+        options(),
+    );
+    assert_eq!(summary.changed, vec![small]);
+}
+
+#[test]
+fn progress_is_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join("a.rs"),
+        "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n",
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("b.rs"), "b();\n").unwrap();
+    std::fs::write(dir.path().join("c.txt"), "c\n").unwrap();
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let sink = reports.clone();
+    let options = WalkOptions {
+        check: true,
+        progress: Some(Arc::new(move |p| sink.lock().unwrap().push(p))),
+        ..Default::default()
+    };
+
+    walk(dir.path(), options);
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 3);
+    assert_eq!(
+        reports.iter().max_by_key(|p| p.processed),
+        Some(&Progress {
+            discovered: 3,
+            processed: 3,
+            changed: 1,
+        })
+    );
+}
+
+#[test]
+fn single_thread_matches_parallel() {
+    let input = "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n";
+    let run = |threads| {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..16 {
+            std::fs::write(dir.path().join(format!("{}.rs", i)), input).unwrap();
+        }
+        let summary = walk(
+            dir.path(),
+            WalkOptions {
+                threads,
+                ..Default::default()
+            },
+        );
+        let names: Vec<_> = summary
+            .changed
+            .iter()
+            .map(|p| p.strip_prefix(dir.path()).unwrap().to_owned())
+            .collect();
+        (names, summary.scanned)
+    };
+    let single = run(1);
+    assert_eq!(single.0.len(), 16);
+    assert_eq!(single, run(0));
+}
+
+#[test]
+fn nothing_to_walk() {
+    let summary = walkdir_parallel(
+        Vec::new(),
+        Data::default(),
+        LangRegistry::with_defaults(),
+        WalkOptions::default(),
+    );
+    assert_eq!(summary.scanned, 0);
+    assert!(summary.errors.is_empty());
+}
+
+#[test]
+fn cached_files_are_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.rs");
+    std::fs::write(&file, "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n").unwrap();
+    let run = |features: &[&str]| {
+        walkdir_parallel(
+            vec![dir.path().to_owned()],
+            data(features),
+            LangRegistry::with_defaults(),
+            WalkOptions {
+                cache: Some(cache_dir.path().join("cache")),
+                ..Default::default()
+            },
+        )
+    };
+    assert_eq!(run(&[]).changed, vec![file.clone()]);
+
+    // Same size and mtime, so only content check could notice the change
+    let tampered = "//[cfg(feature = \"a\")]\nbb();;;;\n//[cfg(end)]\n";
+    let time = mtime(&file);
+    std::fs::write(&file, tampered).unwrap();
+    set_mtime(&file, time);
+    let summary = run(&[]);
+    assert!(summary.changed.is_empty());
+    assert_eq!(summary.scanned, 1);
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), tampered);
+
+    // Different features invalidate the whole cache
+    assert_eq!(run(&["b"]).changed, vec![file.clone()]);
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "//[cfg(feature = \"a\")]\n//# bb();;;;\n//[cfg(end)]\n"
+    );
+}
+
+#[test]
+fn unknown_files_are_not_cached() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.rs");
+    std::fs::write(&file, "a();\n").unwrap();
+    std::fs::write(dir.path().join("notes.unknown"), "text\n").unwrap();
+    let run = || {
+        walkdir_parallel(
+            vec![dir.path().to_owned()],
+            data(&[]),
+            LangRegistry::with_defaults(),
+            WalkOptions {
+                cache: Some(cache_dir.path().join("cache")),
+                ..Default::default()
+            },
+        )
+    };
+    for _ in 0..2 {
+        let summary = run();
+        assert_eq!(summary.scanned, 1);
+        assert_eq!(summary.files, vec![file.clone()]);
+    }
+}
+
+#[test]
+fn cache_depends_only_on_referenced_env() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.rs");
+    std::fs::write(&file, "//[cfg(env(\"A\"))]\na();\n//[cfg(end)]\n").unwrap();
+    let run = |env: &[(&str, &str)]| {
+        let data = Data {
+            env: env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..Data::default()
+        };
+        walkdir_parallel(
+            vec![dir.path().to_owned()],
+            data,
+            LangRegistry::with_defaults(),
+            WalkOptions {
+                cache: Some(cache_dir.path().join("cache")),
+                ..Default::default()
+            },
+        )
+    };
+    assert_eq!(run(&[("B", "1")]).changed, vec![file.clone()]);
+
+    // Tamper without changing size and mtime, so only rescanning notices it
+    let tampered = "//[cfg(env(\"A\"))]\n//# b();\n//[cfg(end)]\n";
+    let time = mtime(&file);
+    std::fs::write(&file, tampered).unwrap();
+    set_mtime(&file, time);
+    assert!(run(&[("B", "2")]).changed.is_empty());
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), tampered);
+
+    assert_eq!(run(&[("A", "")]).changed, vec![file.clone()]);
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "//[cfg(env(\"A\"))]\nb();\n//[cfg(end)]\n"
+    );
+}
+
+#[test]
+fn large_files_match_in_memory_processing() {
+    let dir = tempfile::tempdir().unwrap();
+    let rs = "//[cfg(feature = \"a\")]\r\na();\n//# b();\n//[cfg(end)]\nc(/*[cfg(feature = \"a\")]*/ d /*[cfg(end)]*/);\n";
+    let css = "/*[cfg(feature = \"a\")]*/\na {}\n/*[cfg(end)]*/\n/*[cfg(feature = \"b\")]*/\n/*# b {}\n #*/\n/*[cfg(end)]*/\n";
+    let list = LangDesc::default_list();
+    let mut expected = Vec::new();
+    for (ext, block) in [("rs", rs), ("css", css)].iter() {
+        // Past the size processed with memory map
+        let input = block.repeat((2 << 20) / block.len());
+        let path = dir.path().join(format!("large.{}", ext));
+        std::fs::write(&path, &input).unwrap();
+        expected.push((path, process_str(&input, &data(&["b"]), &list[*ext]).unwrap()));
+    }
+    let run = |check| {
+        walkdir_parallel(
+            vec![dir.path().to_owned()],
+            data(&["b"]),
+            LangRegistry::with_defaults(),
+            WalkOptions {
+                check,
+                ..Default::default()
+            },
+        )
+    };
+    let checked = run(true);
+    assert_eq!(checked.changed.len(), 2);
+    assert_eq!(checked.changed_lines[&expected[0].0][..2], [2, 5]);
+    assert_eq!(run(false).changed, checked.changed);
+    for (path, expected) in expected {
+        assert!(std::fs::read_to_string(&path).unwrap() == expected);
+    }
+}
@@ -0,0 +1,78 @@
+#ifndef CFGCOMMENT_H
+#define CFGCOMMENT_H
+
+/* Generated by cbindgen from crates/ffi, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Outcome of call, message of any failure is available from [`cfgcomment_last_error`]
+ */
+typedef enum CfgcommentStatus {
+  CFGCOMMENT_STATUS_OK = 0,
+  /**
+   * Null pointer, or string which isn't UTF-8
+   */
+  CFGCOMMENT_STATUS_INVALID_ARGUMENT,
+  CFGCOMMENT_STATUS_UNKNOWN_LANG,
+  /**
+   * Input isn't UTF-8
+   */
+  CFGCOMMENT_STATUS_INVALID_INPUT,
+  /**
+   * Input has malformed or unbalanced tags
+   */
+  CFGCOMMENT_STATUS_INVALID_TAGS,
+  /**
+   * Bug in cfgcomment, please report it
+   */
+  CFGCOMMENT_STATUS_PANIC,
+} CfgcommentStatus;
+
+/**
+ * Output owned by the library, released with [`cfgcomment_buffer_free`]
+ */
+typedef struct CfgcommentBuffer {
+  uint8_t *data;
+  size_t len;
+} CfgcommentBuffer;
+
+/**
+ * Processes `len` bytes of UTF-8 text at `buf` in language `lang`, given by name or
+ * extension (i.e `"rs"`), with `features` enabled. Features are separated by commas or
+ * spaces, NULL enables none.
+ *
+ * On success, processed text is stored to `out`, which must be released with
+ * [`cfgcomment_buffer_free`]. `out` is left untouched on failure.
+ *
+ * # Safety
+ *
+ * `buf` must point to `len` readable bytes, `lang` and `features` to NUL-terminated
+ * strings, `out` to writable [`CfgcommentBuffer`].
+ */
+enum CfgcommentStatus cfgcomment_process(const uint8_t *buf,
+                                         size_t len,
+                                         const char *lang,
+                                         const char *features,
+                                         struct CfgcommentBuffer *out);
+
+/**
+ * Releases buffer returned by [`cfgcomment_process`], buffer with NULL data is ignored
+ *
+ * # Safety
+ *
+ * `buffer` must be returned by [`cfgcomment_process`] and not released yet
+ */
+void cfgcomment_buffer_free(struct CfgcommentBuffer buffer);
+
+/**
+ * Message of last failure on calling thread, NULL if nothing failed yet. Pointer stays
+ * valid until next failing call on the same thread
+ */
+const char *cfgcomment_last_error(void);
+
+#endif  /* CFGCOMMENT_H */
@@ -0,0 +1,289 @@
+//! C API, see `include/cfgcomment.h`. Every function is safe to call from multiple threads
+
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use cfgcomment_core::{Data, LangRegistry};
+
+/// Outcome of call, message of any failure is available from [`cfgcomment_last_error`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CfgcommentStatus {
+    Ok = 0,
+    /// Null pointer, or string which isn't UTF-8
+    InvalidArgument,
+    UnknownLang,
+    /// Input isn't UTF-8
+    InvalidInput,
+    /// Input has malformed or unbalanced tags
+    InvalidTags,
+    /// Bug in cfgcomment, please report it
+    Panic,
+}
+
+/// Output owned by the library, released with [`cfgcomment_buffer_free`]
+#[repr(C)]
+pub struct CfgcommentBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn fail(status: CfgcommentStatus, message: impl ToString) -> CfgcommentStatus {
+    // Messages never contain NUL, but it isn't worth a panic
+    let message = CString::new(message.to_string().replace('\0', " ")).ok();
+    LAST_ERROR.with(|e| *e.borrow_mut() = message);
+    status
+}
+
+unsafe fn str_arg<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, CfgcommentStatus> {
+    if ptr.is_null() {
+        return Err(fail(
+            CfgcommentStatus::InvalidArgument,
+            format!("{} is NULL", name),
+        ));
+    }
+    CStr::from_ptr(ptr).to_str().map_err(|_| {
+        fail(
+            CfgcommentStatus::InvalidArgument,
+            format!("{} isn't UTF-8", name),
+        )
+    })
+}
+
+unsafe fn process(
+    buf: *const u8,
+    len: usize,
+    lang: *const c_char,
+    features: *const c_char,
+    out: *mut CfgcommentBuffer,
+) -> Result<(), CfgcommentStatus> {
+    if out.is_null() || (buf.is_null() && len != 0) {
+        return Err(fail(
+            CfgcommentStatus::InvalidArgument,
+            "out or buf is NULL",
+        ));
+    }
+    let input = match len {
+        0 => &[][..],
+        _ => slice::from_raw_parts(buf, len),
+    };
+    let lang = str_arg(lang, "lang")?;
+    let features = match features.is_null() {
+        true => "",
+        false => str_arg(features, "features")?,
+    };
+
+    let registry = LangRegistry::with_defaults();
+    let desc = registry.get_by_name(lang).ok_or_else(|| {
+        fail(
+            CfgcommentStatus::UnknownLang,
+            format!("unknown language {:?}", lang),
+        )
+    })?;
+    let input = std::str::from_utf8(input).map_err(|e| fail(CfgcommentStatus::InvalidInput, e))?;
+    let data = Data {
+        features: features
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|f| !f.is_empty())
+            .map(|f| f.to_owned())
+            .collect(),
+        ..Default::default()
+    };
+    let output = cfgcomment_core::process_str(input, &data, desc)
+        .map_err(|e| fail(CfgcommentStatus::InvalidTags, e))?;
+
+    let output = Box::<[u8]>::from(output.into_bytes());
+    let len = output.len();
+    *out = CfgcommentBuffer {
+        data: Box::into_raw(output) as *mut u8,
+        len,
+    };
+    Ok(())
+}
+
+/// Processes `len` bytes of UTF-8 text at `buf` in language `lang`, given by name or
+/// extension (i.e `"rs"`), with `features` enabled. Features are separated by commas or
+/// spaces, NULL enables none.
+///
+/// On success, processed text is stored to `out`, which must be released with
+/// [`cfgcomment_buffer_free`]. `out` is left untouched on failure.
+///
+/// # Safety
+///
+/// `buf` must point to `len` readable bytes, `lang` and `features` to NUL-terminated
+/// strings, `out` to writable [`CfgcommentBuffer`].
+#[no_mangle]
+pub unsafe extern "C" fn cfgcomment_process(
+    buf: *const u8,
+    len: usize,
+    lang: *const c_char,
+    features: *const c_char,
+    out: *mut CfgcommentBuffer,
+) -> CfgcommentStatus {
+    let result = catch_unwind(AssertUnwindSafe(|| process(buf, len, lang, features, out)));
+    match result {
+        Ok(Ok(())) => CfgcommentStatus::Ok,
+        Ok(Err(status)) => status,
+        Err(_) => fail(CfgcommentStatus::Panic, "cfgcomment panicked"),
+    }
+}
+
+/// Releases buffer returned by [`cfgcomment_process`], buffer with NULL data is ignored
+///
+/// # Safety
+///
+/// `buffer` must be returned by [`cfgcomment_process`] and not released yet
+#[no_mangle]
+pub unsafe extern "C" fn cfgcomment_buffer_free(buffer: CfgcommentBuffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        )));
+    }
+}
+
+/// Message of last failure on calling thread, NULL if nothing failed yet. Pointer stays
+/// valid until next failing call on the same thread
+#[no_mangle]
+pub extern "C" fn cfgcomment_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |m| m.as_ptr()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &[u8] = b"//[cfg(feature = \"a\")]\n//# a();\n//[cfg(end)]\nb();\n";
+
+    fn last_error() -> String {
+        let message = cfgcomment_last_error();
+        assert!(!message.is_null());
+        unsafe { CStr::from_ptr(message) }
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    fn empty_buffer() -> CfgcommentBuffer {
+        CfgcommentBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    fn run(input: &[u8], lang: &[u8], features: Option<&[u8]>) -> Result<String, CfgcommentStatus> {
+        let lang = CString::new(lang).unwrap();
+        let features = features.map(|f| CString::new(f).unwrap());
+        let mut out = empty_buffer();
+        let status = unsafe {
+            cfgcomment_process(
+                input.as_ptr(),
+                input.len(),
+                lang.as_ptr(),
+                features.as_ref().map_or(ptr::null(), |f| f.as_ptr()),
+                &mut out,
+            )
+        };
+        if status != CfgcommentStatus::Ok {
+            assert!(out.data.is_null());
+            return Err(status);
+        }
+        let output = unsafe { slice::from_raw_parts(out.data, out.len) }.to_vec();
+        unsafe { cfgcomment_buffer_free(out) };
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn processes_input() {
+        assert_eq!(
+            run(INPUT, b"rs", Some(b"b, a")).unwrap(),
+            "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\nb();\n"
+        );
+        assert_eq!(
+            run(INPUT, b"rs", None).unwrap(),
+            std::str::from_utf8(INPUT).unwrap()
+        );
+        assert_eq!(run(b"", b"rs", None).unwrap(), "");
+    }
+
+    #[test]
+    fn null_arguments_are_rejected() {
+        let lang = CString::new("rs").unwrap();
+        let mut out = empty_buffer();
+        let status = unsafe {
+            cfgcomment_process(
+                INPUT.as_ptr(),
+                INPUT.len(),
+                ptr::null(),
+                ptr::null(),
+                &mut out,
+            )
+        };
+        assert_eq!(status, CfgcommentStatus::InvalidArgument);
+        assert_eq!(last_error(), "lang is NULL");
+        let status =
+            unsafe { cfgcomment_process(ptr::null(), 1, lang.as_ptr(), ptr::null(), &mut out) };
+        assert_eq!(status, CfgcommentStatus::InvalidArgument);
+        let status = unsafe {
+            cfgcomment_process(
+                INPUT.as_ptr(),
+                INPUT.len(),
+                lang.as_ptr(),
+                ptr::null(),
+                ptr::null_mut(),
+            )
+        };
+        assert_eq!(status, CfgcommentStatus::InvalidArgument);
+        assert!(out.data.is_null());
+    }
+
+    #[test]
+    fn invalid_utf8_is_rejected() {
+        assert_eq!(
+            run(INPUT, b"r\xffs", None),
+            Err(CfgcommentStatus::InvalidArgument)
+        );
+        assert_eq!(last_error(), "lang isn't UTF-8");
+        assert_eq!(
+            run(INPUT, b"rs", Some(b"\xff")),
+            Err(CfgcommentStatus::InvalidArgument)
+        );
+        assert_eq!(last_error(), "features isn't UTF-8");
+        assert_eq!(
+            run(b"a\xff\n", b"rs", None),
+            Err(CfgcommentStatus::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn unknown_lang_is_rejected() {
+        assert_eq!(
+            run(INPUT, b"nope", None),
+            Err(CfgcommentStatus::UnknownLang)
+        );
+        assert_eq!(last_error(), "unknown language \"nope\"");
+    }
+
+    #[test]
+    fn malformed_tags_are_rejected() {
+        assert_eq!(
+            run(b"//[cfg(feature = \"a\")]\n", b"rs", None),
+            Err(CfgcommentStatus::InvalidTags)
+        );
+    }
+
+    #[test]
+    fn null_buffer_is_ignored() {
+        unsafe { cfgcomment_buffer_free(empty_buffer()) };
+    }
+}
@@ -0,0 +1,180 @@
+//! `cfgcomment` Python module, built with `maturin build -m crates/python/Cargo.toml`
+
+use std::path::PathBuf;
+
+use cfgcomment_core::{BranchKind, Config, Data, LangDesc, LangRegistry};
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+fn value_error(e: impl std::fmt::Display) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+/// Language by name or extension, i.e `rs`
+fn lang(lang: &str) -> PyResult<LangDesc> {
+    LangRegistry::with_defaults()
+        .get_by_name(lang)
+        .cloned()
+        .ok_or_else(|| value_error(format!("unknown language {:?}", lang)))
+}
+
+fn data(features: Option<Vec<String>>, reset: bool) -> Data {
+    Data {
+        features: features.unwrap_or_default().into_iter().collect(),
+        reset,
+        ..Default::default()
+    }
+}
+
+/// Processes `input` in language `lang`, commenting out blocks which are disabled with
+/// `features`, or uncommenting every block if `reset` is set
+#[pyfunction]
+#[pyo3(signature = (input, lang, features = None, reset = false))]
+fn process_str(
+    input: &str,
+    lang: &str,
+    features: Option<Vec<String>>,
+    reset: bool,
+) -> PyResult<String> {
+    let desc = self::lang(lang)?;
+    cfgcomment_core::process_str(input, &data(features, reset), &desc).map_err(value_error)
+}
+
+/// Branch of cfg block, with whether it is enabled by given features
+#[pyclass(frozen, get_all, module = "cfgcomment")]
+struct Branch {
+    /// Line of tag starting the branch, 1-based
+    line: usize,
+    /// `start`, `else_if`, `else`, `line` or `file`
+    kind: &'static str,
+    nesting_depth: usize,
+    /// Predicate in canonical form, `None` for `[cfg(else)]`
+    predicate: Option<String>,
+    /// Value of predicate itself, regardless of enclosing blocks
+    matched: Option<bool>,
+    enabled: bool,
+}
+
+/// Every branch of cfg blocks of `input`, evaluated against `features`
+#[pyfunction]
+#[pyo3(signature = (input, lang, features = None))]
+fn evaluate(input: &str, lang: &str, features: Option<Vec<String>>) -> PyResult<Vec<Branch>> {
+    let desc = self::lang(lang)?;
+    let branches = cfgcomment_core::explain(input, &data(features, false), &desc);
+    Ok(branches
+        .map_err(value_error)?
+        .into_iter()
+        .map(|b| Branch {
+            line: b.line,
+            kind: match b.kind {
+                BranchKind::Start => "start",
+                BranchKind::ElseIf => "else_if",
+                BranchKind::Else => "else",
+                BranchKind::Line => "line",
+                BranchKind::File => "file",
+            },
+            nesting_depth: b.nesting_depth,
+            predicate: b.evaluation.as_ref().map(|e| e.predicate.clone()),
+            matched: b.evaluation.map(|e| e.value),
+            enabled: b.enabled,
+        })
+        .collect())
+}
+
+/// Lines of cfg block
+#[pyclass(frozen, get_all, module = "cfgcomment")]
+struct Region {
+    /// Line of start tag, 1-based
+    start: usize,
+    /// Line of end tag
+    end: usize,
+    predicate: String,
+    nesting_depth: usize,
+    /// Features named by predicate
+    features: Vec<String>,
+    /// Features without which block can't be enabled
+    required_features: Vec<String>,
+    /// Whether lines of block are commented out now
+    commented: bool,
+}
+
+/// Every cfg block of `input`, ordered by start line, without processing it
+#[pyfunction]
+fn blocks(input: &str, lang: &str) -> PyResult<Vec<Region>> {
+    let desc = self::lang(lang)?;
+    let regions = cfgcomment_core::regions(input, &desc, false).map_err(value_error)?;
+    Ok(regions
+        .into_iter()
+        .map(|r| Region {
+            start: r.start,
+            end: r.end,
+            predicate: r.predicate_src,
+            nesting_depth: r.nesting_depth,
+            features: r.features,
+            required_features: r.required_features,
+            commented: r.commented,
+        })
+        .collect())
+}
+
+/// Processes files and directories in `paths` in place, skipping ones ignored by
+/// `.gitignore` and `.cfgignore`. Returns changed files, raises on first error
+#[pyfunction]
+#[pyo3(signature = (paths, features = None, reset = false))]
+fn process_tree(
+    py: Python<'_>,
+    paths: Vec<PathBuf>,
+    features: Option<Vec<String>>,
+    reset: bool,
+) -> PyResult<Vec<PathBuf>> {
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    let lang_config = Config::discover(&paths[0])
+        .map_err(value_error)?
+        .unwrap_or_default()
+        .lang_config();
+    let data = data(features, reset);
+    let summary = py
+        .detach(|| cfgcomment_core::walkdir_parallel(paths, data, lang_config, Default::default()));
+    if let Some((path, e)) = summary.errors.first() {
+        return Err(value_error(format!("{}: {}", path.display(), e)));
+    }
+    Ok(summary.changed)
+}
+
+#[pymodule]
+fn cfgcomment(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(process_str, m)?)?;
+    m.add_function(wrap_pyfunction!(evaluate, m)?)?;
+    m.add_function(wrap_pyfunction!(blocks, m)?)?;
+    m.add_function(wrap_pyfunction!(process_tree, m)?)?;
+    m.add_class::<Branch>()?;
+    m.add_class::<Region>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "//[cfg(feature = \"a\")]\n//# a();\n//[cfg(end)]\n";
+
+    #[test]
+    fn processes_input() {
+        let output = process_str(INPUT, "rs", Some(vec!["a".to_owned()]), false).unwrap();
+        assert_eq!(output, "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n");
+        assert_eq!(process_str(INPUT, "rs", None, false).unwrap(), INPUT);
+    }
+
+    #[test]
+    fn errors_are_value_errors() {
+        Python::initialize();
+        Python::attach(|py| {
+            let e = process_str(INPUT, "nope", None, false).unwrap_err();
+            assert!(e.is_instance_of::<PyValueError>(py));
+            assert_eq!(e.value(py).to_string(), "unknown language \"nope\"");
+            let e = process_str("//[cfg(end)]\n", "rs", None, false).unwrap_err();
+            assert!(e.is_instance_of::<PyValueError>(py));
+        });
+    }
+}
@@ -0,0 +1,53 @@
+//! `processStr` for JavaScript, built with `wasm-pack build crates/wasm`
+
+use cfgcomment_core::{Data, LangRegistry};
+use wasm_bindgen::prelude::*;
+
+/// Processes `input` in language `lang`, given by name or extension (i.e `rs`), with
+/// `features` enabled. Throws on unknown language and malformed tags
+#[wasm_bindgen(js_name = processStr)]
+pub fn process_str(input: &str, lang: &str, features: Vec<String>) -> Result<String, JsError> {
+    process(input, lang, features).map_err(|e| JsError::new(&e))
+}
+
+/// [`process_str`] without JavaScript error, which exists only on wasm32
+fn process(input: &str, lang: &str, features: Vec<String>) -> Result<String, String> {
+    let registry = LangRegistry::with_defaults();
+    let desc = registry
+        .get_by_name(lang)
+        .ok_or_else(|| format!("unknown language {:?}", lang))?;
+    let data = Data {
+        features: features.into_iter().collect(),
+        ..Default::default()
+    };
+    cfgcomment_core::process_str(input, &data, desc).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "//[cfg(feature = \"a\")]\n//# a();\n//[cfg(end)]\n";
+
+    #[test]
+    fn processes_input() {
+        assert_eq!(
+            process(INPUT, "rs", vec!["a".to_owned()]).unwrap(),
+            "//[cfg(feature = \"a\")]\na();\n//[cfg(end)]\n"
+        );
+        assert_eq!(process(INPUT, "rs", Vec::new()).unwrap(), INPUT);
+    }
+
+    #[test]
+    fn unknown_lang_is_rejected() {
+        assert_eq!(
+            process(INPUT, "nope", Vec::new()).unwrap_err(),
+            "unknown language \"nope\""
+        );
+    }
+
+    #[test]
+    fn malformed_tags_are_rejected() {
+        assert!(process("//[cfg(end)]\n", "rs", Vec::new()).is_err());
+    }
+}